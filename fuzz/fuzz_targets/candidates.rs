@@ -0,0 +1,22 @@
+//! Feeds arbitrary JSON through the reading of plan files, and checks
+//! that whatever it takes survives being written out and read again.
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+#[allow(dead_code)]
+#[path = "../../src/offset.rs"]
+mod offset;
+
+#[allow(dead_code)]
+#[path = "../../src/candidates.rs"]
+mod candidates;
+
+fuzz_target!(|data: &[u8]| {
+    let entries: Vec<candidates::Entry> = match serde_json::from_slice(data) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    let written = serde_json::to_string(&entries).unwrap();
+    let read: Vec<candidates::Entry> = serde_json::from_str(&written).unwrap();
+    assert_eq!(read.len(), entries.len());
+});
@@ -0,0 +1,23 @@
+//! Feeds arbitrary start/end values, as silencedetect and blackdetect
+//! would attach them to frames, through the metadata parser.
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use std::time::Duration;
+
+#[allow(dead_code)]
+#[path = "../../src/metadata.rs"]
+mod metadata;
+
+fuzz_target!(|data: &[u8]| {
+    let text = String::from_utf8_lossy(data);
+    let mut inside_pause = false;
+    // each line is a frame's start and end values, separated by a tab:
+    for (i, line) in text.lines().enumerate() {
+        let mut values = line.splitn(2, '\t');
+        let start = values.next().filter(|v| !v.is_empty());
+        let end = values.next().filter(|v| !v.is_empty());
+        let at = Duration::from_millis(i as u64 * 40);
+        let pause = metadata::pause_from(&mut inside_pause, start, end, Some(at));
+        assert!(pause.at().map_or(true, |pause_at| pause_at == at));
+    }
+});
@@ -0,0 +1,27 @@
+//! Feeds arbitrary CSV through the reading of add-chapter-markers' rows
+//! and the checks on their offsets.
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use std::time::Duration;
+
+#[allow(dead_code)]
+#[path = "../../src/offset.rs"]
+mod offset;
+
+#[path = "../../src/title_info.rs"]
+mod title_info;
+
+fuzz_target!(|data: &[u8]| {
+    let chapter_starts = [Duration::from_secs(0), Duration::from_secs(300)];
+    let duration = Duration::from_secs(1800);
+    let mut rows = csv::Reader::from_reader(data);
+    for row in rows.deserialize() {
+        let row: title_info::TitleInfo = match row {
+            Ok(row) => row,
+            Err(_) => continue,
+        };
+        if let Ok((start, end)) = row.theme_range(&chapter_starts, Some(duration)) {
+            assert!(start < end && end <= duration);
+        }
+    }
+});
@@ -0,0 +1,103 @@
+//! Time detection with different decoder settings on one file, and
+//! check whether the faster ones still find the same breaks.
+use crate::detect::{Candidate, VideoSettings};
+use anyhow::{Context, Result};
+use indicatif::HumanDuration;
+use std::cmp::{max, min};
+use std::time::{Duration, Instant};
+
+/// How far apart two candidates from different configurations may be
+/// and still count as the same break.
+const AGREEMENT_TOLERANCE: Duration = Duration::from_secs(1);
+
+/// Height that the "downscaled" configurations scale video to.
+const DOWNSCALE_HEIGHT: u32 = 360;
+
+struct Outcome {
+    name: String,
+    elapsed: Duration,
+    scanned: Duration,
+    candidates: Vec<Candidate>,
+}
+
+fn configurations(threads: usize) -> Vec<(String, VideoSettings)> {
+    let full = VideoSettings::with_threads(threads);
+    let mut configs = vec![
+        (format!("{} threads, full-res", threads), full),
+        (
+            format!("{} threads, {}p", threads, DOWNSCALE_HEIGHT),
+            VideoSettings {
+                downscale_to: Some(DOWNSCALE_HEIGHT),
+                ..full
+            },
+        ),
+        (
+            format!("{} threads, keyframes only", threads),
+            VideoSettings {
+                keyframes_only: true,
+                ..full
+            },
+        ),
+    ];
+    if threads > 1 {
+        configs.push((
+            "1 thread, full-res".to_string(),
+            VideoSettings::with_threads(1),
+        ));
+    }
+    configs
+}
+
+/// Fraction of `baseline`'s candidates that `other` found too.
+fn agreement(baseline: &[Candidate], other: &[Candidate]) -> f64 {
+    if baseline.is_empty() {
+        return if other.is_empty() { 1.0 } else { 0.0 };
+    }
+    let found = baseline
+        .iter()
+        .filter(|b| {
+            other
+                .iter()
+                .any(|o| max(o.offset, b.offset) - min(o.offset, b.offset) <= AGREEMENT_TOLERANCE)
+        })
+        .count();
+    found as f64 / baseline.len() as f64
+}
+
+/// Scans with each detector configuration by calling `scan`, which
+/// returns how much of the file it covered and the breaks detection
+/// would consider, and prints how fast each was and how well its
+/// breaks match those of the full-resolution scan.
+pub(crate) fn bench(
+    threads: usize,
+    mut scan: impl FnMut(&VideoSettings) -> Result<(Duration, Vec<Candidate>)>,
+) -> Result<()> {
+    let mut outcomes = vec![];
+    for (name, settings) in configurations(threads) {
+        let started = Instant::now();
+        let (scanned, candidates) = scan(&settings).context(name.clone())?;
+        outcomes.push(Outcome {
+            name,
+            elapsed: started.elapsed(),
+            scanned,
+            candidates,
+        });
+    }
+    let baseline = outcomes[0].candidates.clone();
+    println!(
+        "{:<30} {:>10} {:>8} {:>10} {:>9}",
+        "configuration", "time", "speed", "candidates", "agreement"
+    );
+    for outcome in outcomes {
+        println!(
+            "{:<30} {:>10} {:>7.1}x {:>10} {:>8.0}%",
+            outcome.name,
+            format!("{}", HumanDuration(outcome.elapsed)),
+            outcome.scanned.as_secs_f64() / outcome.elapsed.as_secs_f64().max(0.001),
+            outcome.candidates.len(),
+            agreement(&baseline, &outcome.candidates) * 100.0
+        );
+    }
+    println!("(no hardware decoding configurations: hwaccel isn't supported yet)");
+    Ok(())
+}
@@ -0,0 +1,192 @@
+//! Find detection settings from an example: given where the intro of
+//! one episode really is, try silence and blackness levels and pause
+//! lengths until detection finds that intro too, and turn the settings
+//! that do into a profile for its show.
+use crate::config::Config;
+use crate::detect::{Candidate, Thresholds};
+use crate::intro::{self, ForcedIntro, ScanLength};
+use crate::sidecar::Overrides;
+use crate::{episode, planfile};
+use anyhow::{bail, Context, Result};
+use indicatif::ProgressBar;
+use std::fmt::Write as _;
+use std::fs::OpenOptions;
+use std::io::Write as _;
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::time::Duration;
+
+/// The settings tried, each including the default.
+const SILENCE_LEVELS: [f64; 9] = [
+    -70.0, -65.0, -60.0, -55.0, -50.0, -45.0, -40.0, -35.0, -30.0,
+];
+const BLACK_LEVELS: [f64; 5] = [0.04, 0.07, 0.1, 0.13, 0.16];
+const PAUSES_MS: [u64; 6] = [100, 200, 300, 500, 750, 1000];
+
+/// Where the defaults are among the settings tried.
+const DEFAULT_SILENCE: usize = 4;
+const DEFAULT_BLACK: usize = 2;
+const DEFAULT_PAUSE: usize = 1;
+
+/// How far off the labeled start and end a found intro may be.
+const TOLERANCE: Duration = Duration::from_secs(2);
+
+/// How far past the labeled intro to scan, so a break right after it
+/// can still be found.
+const MARGIN: Duration = Duration::from_secs(30);
+
+/// Settings that recovered the labeled intro.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Calibration {
+    pub(crate) thresholds: Thresholds,
+    pub(crate) threshold: Duration,
+    pub(crate) found: (Duration, Duration),
+
+    /// How many of the settings tried found the intro.
+    pub(crate) hits: usize,
+    pub(crate) tried: usize,
+
+    /// How many steps away from the defaults the settings are.
+    steps: usize,
+}
+
+impl Calibration {
+    /// The profile for the show `path` is an episode of, as it would be
+    /// written into a --config file, or the settings alone, as for a
+    /// sidecar, if its show isn't known.
+    pub(crate) fn profile(&self, path: &Path, intro: &ForcedIntro) -> String {
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "# calibrated on {:?}, with its intro at {:.1}s..{:.1}s",
+            path.file_name().unwrap_or_default(),
+            intro.start.as_secs_f64(),
+            intro.end.as_secs_f64()
+        );
+        if let Some((series, _)) = episode::series_and_season(path) {
+            let _ = writeln!(out, "[profiles.{}]", planfile::quote(&series));
+        }
+        let _ = writeln!(out, "silence_noise = {:?}", self.thresholds.silence_db);
+        let _ = writeln!(out, "black_pixel = {:?}", self.thresholds.black_pixel);
+        let _ = writeln!(
+            out,
+            "threshold = \"{}\"",
+            humantime::format_duration(self.threshold)
+        );
+        out
+    }
+}
+
+fn off_by(found: (Duration, Duration), intro: &ForcedIntro) -> Duration {
+    found.0.abs_diff(intro.start) + found.1.abs_diff(intro.end)
+}
+
+/// Whether `found` starts and ends close enough to the labeled intro.
+fn recovers(found: (Duration, Duration), intro: &ForcedIntro) -> bool {
+    found.0.abs_diff(intro.start) <= TOLERANCE && found.1.abs_diff(intro.end) <= TOLERANCE
+}
+
+/// Scans the beginning of a file with each combination of silence and
+/// blackness levels by calling `scan` (which returns the breaks
+/// detection would consider with those `Overrides`), and looks for
+/// `intro` among the breaks found with each pause length, as an intro
+/// of `intro_length`. Of the settings that find it, returns the ones
+/// that stray the least from the defaults (and of those, the ones that
+/// find it most exactly).
+pub(crate) fn calibrate(
+    intro: &ForcedIntro,
+    intro_length: &RangeInclusive<Duration>,
+    mut scan: impl FnMut(&Overrides) -> Result<Vec<Candidate>>,
+) -> Result<Option<Calibration>> {
+    if !intro_length.contains(&(intro.end - intro.start)) {
+        bail!(
+            "the intro lasts {:.1}s, but detection only looks for intros of {}..{} (see --intro-min and --intro-max)",
+            (intro.end - intro.start).as_secs_f64(),
+            humantime::format_duration(*intro_length.start()),
+            humantime::format_duration(*intro_length.end())
+        );
+    }
+    let window = intro.end + MARGIN;
+    let shortest_pause = Duration::from_millis(PAUSES_MS[0]);
+    let tried = SILENCE_LEVELS.len() * BLACK_LEVELS.len() * PAUSES_MS.len();
+    let bar = ProgressBar::new((SILENCE_LEVELS.len() * BLACK_LEVELS.len()) as u64);
+    let mut best: Option<Calibration> = None;
+    let mut hits = 0;
+    for (s, silence_db) in SILENCE_LEVELS.iter().enumerate() {
+        for (b, black_pixel) in BLACK_LEVELS.iter().enumerate() {
+            let thresholds = Thresholds {
+                silence_db: *silence_db,
+                black_pixel: *black_pixel,
+            };
+            let overrides = Overrides {
+                until: Some(ScanLength::Fixed(window)),
+                // the longer pauses are picked out of these below:
+                threshold: Some(shortest_pause),
+                silence_noise: Some(*silence_db),
+                black_pixel: Some(*black_pixel),
+                ..Default::default()
+            };
+            let candidates =
+                scan(&overrides).with_context(|| format!("{}dB, {}", silence_db, black_pixel))?;
+            for (p, pause) in PAUSES_MS.iter().enumerate() {
+                let threshold = Duration::from_millis(*pause);
+                let starts: Vec<Duration> = candidates
+                    .iter()
+                    .filter(|cand| cand.length > threshold)
+                    .map(|cand| cand.offset)
+                    .collect();
+                let found = match intro::find_intro(&starts, intro_length) {
+                    Some(found) if recovers(found, intro) => found,
+                    _ => continue,
+                };
+                hits += 1;
+                let steps = s.abs_diff(DEFAULT_SILENCE)
+                    + b.abs_diff(DEFAULT_BLACK)
+                    + p.abs_diff(DEFAULT_PAUSE);
+                let better = match &best {
+                    None => true,
+                    Some(best) => {
+                        (steps, off_by(found, intro)) < (best.steps, off_by(best.found, intro))
+                    }
+                };
+                if better {
+                    best = Some(Calibration {
+                        thresholds,
+                        threshold,
+                        found,
+                        hits: 0,
+                        tried,
+                        steps,
+                    });
+                }
+            }
+            bar.inc(1);
+        }
+    }
+    bar.finish_and_clear();
+    Ok(best.map(|best| Calibration { hits, ..best }))
+}
+
+/// Appends `profile` to the --config file at `config`, unless that
+/// already has a profile for the show of `path`.
+pub(crate) fn append_profile(config: &Path, path: &Path, profile: &str) -> Result<()> {
+    if episode::series_and_season(path).is_none() {
+        bail!(
+            "can't tell which show {:?} is an episode of; save the settings as its sidecar instead",
+            path
+        );
+    }
+    if config.exists() && Config::load(config)?.profile_for(path).is_some() {
+        bail!(
+            "{:?} already has a profile for the show of {:?}; merge the settings into it by hand",
+            config,
+            path
+        );
+    }
+    let mut f = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(config)
+        .context(format!("opening {:?}", config))?;
+    write!(f, "\n{}", profile).context(format!("writing {:?}", config))
+}
@@ -0,0 +1,88 @@
+//! Save the chapters detection came up with to a file, so they can be
+//! reviewed (and edited) before being written by a separate command,
+//! possibly on another machine.
+use crate::offset;
+use anyhow::{Context, Result};
+use serde_derive::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+/// Offsets in seconds, so they're easy to edit by hand (which may
+/// also give them as `HH:MM:SS.mmm` or `1m32s`).
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Span {
+    #[serde(deserialize_with = "offset::offset_secs")]
+    pub(crate) start: f64,
+    #[serde(deserialize_with = "offset::offset_secs")]
+    pub(crate) end: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Marker {
+    #[serde(deserialize_with = "offset::offset_secs")]
+    pub(crate) start: f64,
+    pub(crate) name: String,
+
+    /// How long the pause the marker was detected at lasted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) pause: Option<f64>,
+}
+
+/// The chapters planned for one file.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Entry {
+    pub(crate) path: PathBuf,
+
+    /// Season and episode, as `S01E02`, if the file name says.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) episode: Option<String>,
+
+    /// How the intro came about; for people reading the file only.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) status: Option<String>,
+
+    /// The Matroska file to remux into, instead of writing to `path`.
+    #[serde(default)]
+    pub(crate) remux_to: Option<PathBuf>,
+
+    #[serde(default)]
+    pub(crate) intro: Option<Span>,
+
+    /// Whether the intro was detected (rather than given, or not
+    /// looked for at all).
+    #[serde(default)]
+    pub(crate) detected: bool,
+
+    /// Whether the intro differs a lot from the rest of its season.
+    #[serde(default)]
+    pub(crate) outlier: bool,
+
+    pub(crate) chapters: Vec<Marker>,
+
+    /// The file's fingerprint when it was scanned, so a file replaced
+    /// since then isn't given chapters meant for its old version.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) checksum: Option<String>,
+}
+
+/// Prints the entries to stdout.
+pub(crate) fn print(entries: &[Entry]) -> Result<()> {
+    let stdout = std::io::stdout();
+    serde_json::to_writer_pretty(stdout.lock(), entries)?;
+    println!();
+    Ok(())
+}
+
+pub(crate) fn write(path: &Path, entries: &[Entry]) -> Result<()> {
+    let f = File::create(path).context(format!("creating {:?}", path))?;
+    serde_json::to_writer_pretty(BufWriter::new(f), entries)
+        .context(format!("writing {:?}", path))?;
+    Ok(())
+}
+
+pub(crate) fn read(path: &Path) -> Result<Vec<Entry>> {
+    let f = File::open(path).context(format!("opening {:?}", path))?;
+    serde_json::from_reader(BufReader::new(f)).context(format!("parsing {:?}", path))
+}
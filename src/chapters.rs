@@ -0,0 +1,1089 @@
+//! Chapter lists: reading them from files and writing them back
+use crate::intro::ForcedIntro;
+use crate::{ebml, offset, provenance, remux, service, util, xml};
+use anyhow::{self, bail, Context};
+use mktemp::Temp;
+use serde_derive::Serialize;
+use std::cmp::{max, min};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Chapters starting closer together than this are considered duplicates.
+pub(crate) const DUPLICATE_WINDOW: Duration = Duration::from_millis(100);
+
+/// Chapters starting this close to the existing ones are considered
+/// unchanged.
+const UNCHANGED_TOLERANCE: Duration = Duration::from_millis(10);
+
+/// Extensions of Matroska-family containers, all of which mkvpropedit
+/// can edit in place.
+pub(crate) const MATROSKA_EXTENSIONS: &[&str] = &["mkv", "mka", "mk3d", "webm"];
+
+/// Extensions of MP4-family containers, whose chapters we rewrite by
+/// remuxing the file.
+pub(crate) const MP4_EXTENSIONS: &[&str] = &["mp4", "m4v", "mov"];
+
+/// Every file extension we can both detect on and write chapters to.
+pub(crate) const SUPPORTED_EXTENSIONS: &[&str] =
+    &["mkv", "mka", "mk3d", "webm", "mp4", "m4v", "mov"];
+
+/// The ways we know of getting chapters into a file.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(crate) enum Writer {
+    /// Edit the chapters of a Matroska-family file in place.
+    Mkvpropedit,
+
+    /// Copy all streams into a new file of the same container with the
+    /// new chapters, then replace the original.
+    Remux,
+}
+
+impl Writer {
+    /// Picks the writer that can handle the container of `path`, based
+    /// on its extension.
+    pub(crate) fn for_path(path: &Path) -> anyhow::Result<Writer> {
+        let ext = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        match ext.as_deref() {
+            Some(ext) if MATROSKA_EXTENSIONS.contains(&ext) => Ok(Writer::Mkvpropedit),
+            Some(ext) if MP4_EXTENSIONS.contains(&ext) => Ok(Writer::Remux),
+            _ => bail!("don't know how to write chapters into {:?}", path),
+        }
+    }
+}
+
+/// Returns whether the Matroska file at `path` plays back through
+/// ordered chapters or linked segments, so that its presentation
+/// timeline differs from its decode timeline. Other containers don't
+/// have these.
+pub(crate) fn uses_ordered_chapters(path: &Path) -> anyhow::Result<bool> {
+    if Writer::for_path(path).ok() != Some(Writer::Mkvpropedit) {
+        return Ok(false);
+    }
+    let file = File::open(path).with_context(|| format!("opening {:?}", path))?;
+    ebml::uses_ordered_chapters(&mut BufReader::new(file))
+        .with_context(|| format!("reading the chapters of {:?}", path))
+}
+
+/// How to run mkvpropedit.
+#[derive(Debug, Clone)]
+pub(crate) struct Mkvpropedit {
+    pub(crate) program: PathBuf,
+
+    /// Options passed before the file name, e.g. `--parse-mode full`.
+    pub(crate) args: Vec<String>,
+
+    /// Fail on warnings, too. mkvpropedit warns after writing, so the
+    /// file is changed either way.
+    pub(crate) strict: bool,
+
+    /// Write the chapters' tags along with them.
+    pub(crate) tags: bool,
+
+    pub(crate) numbering: Numbering,
+}
+
+impl Default for Mkvpropedit {
+    fn default() -> Self {
+        Mkvpropedit {
+            program: PathBuf::from("mkvpropedit"),
+            args: vec![],
+            strict: false,
+            tags: false,
+            numbering: Numbering::default(),
+        }
+    }
+}
+
+impl Mkvpropedit {
+    /// mkvextract, from the same MKVToolNix installation.
+    fn mkvextract(&self) -> PathBuf {
+        self.program.with_file_name("mkvextract")
+    }
+}
+
+#[derive(PartialEq, Debug)]
+pub(crate) struct Chapter {
+    pub(crate) id: usize,
+    pub(crate) start: Duration,
+    pub(crate) name: String,
+
+    /// How long the pause the chapter was detected at lasted.
+    pub(crate) pause: Option<Duration>,
+
+    /// Name/value pairs describing how the chapter came about, written
+    /// as tags on the chapter where the container allows.
+    pub(crate) tags: Vec<(String, String)>,
+}
+
+impl Chapter {
+    fn from_ffmpeg(id: usize, chapter: ffmpeg::format::chapter::Chapter) -> Self {
+        Chapter {
+            id,
+            start: util::to_duration(chapter.start(), chapter.time_base()),
+            name: chapter
+                .metadata()
+                .get("title")
+                .unwrap_or("untitled")
+                .to_string(),
+            pause: None,
+            tags: vec![],
+        }
+    }
+
+    pub(crate) fn new(id: usize, start: Duration, name: String) -> Self {
+        Chapter {
+            id,
+            start,
+            name,
+            pause: None,
+            tags: vec![],
+        }
+    }
+
+    pub(crate) fn with_tag(mut self, name: &str, value: String) -> Self {
+        self.tags.push((name.to_string(), value));
+        self
+    }
+}
+
+/// How chapters are numbered: in the names we give them, and in the
+/// IDs of simple chapter files.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Numbering {
+    /// The number of the first chapter; at least 1.
+    pub(crate) start: usize,
+
+    /// Pad numbers with zeros to this many digits.
+    pub(crate) width: Option<usize>,
+}
+
+impl Default for Numbering {
+    fn default() -> Self {
+        Numbering {
+            start: 1,
+            width: None,
+        }
+    }
+}
+
+impl Numbering {
+    /// The number to name the `n`th (counting from 0) chapter with.
+    pub(crate) fn label(&self, n: usize) -> String {
+        format!("{:0>w$}", n + self.start, w = self.width.unwrap_or(0))
+    }
+
+    /// The ID of the chapter numbered `id` (counting from 0): the first
+    /// chapter is CHAPTER00 for the default start of 1, and each start
+    /// past that shifts the IDs by one.
+    fn id(&self, id: usize) -> String {
+        format!("{:0>w$}", id + self.start - 1, w = self.width.unwrap_or(2))
+    }
+}
+
+impl Chapter {
+    /// Renders the chapter's lines of a simple (OGM-style) chapter file.
+    fn to_ogm(&self, numbering: Numbering) -> String {
+        let secs = self.start.as_secs();
+        let id = numbering.id(self.id);
+        format!(
+            "CHAPTER{}={:0>2}:{:0>2}:{:0>2}.{:0>3}\nCHAPTER{}NAME={}",
+            id,
+            secs / 60 / 60,
+            secs / 60 % 60,
+            secs % 60,
+            self.start.subsec_millis(),
+            id,
+            self.name
+        )
+    }
+}
+
+impl fmt::Display for Chapter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{}", self.to_ogm(Numbering::default()))
+    }
+}
+
+pub(crate) fn existing_chapters(ictx: &ffmpeg::format::context::Input) -> Vec<Chapter> {
+    ictx.chapters()
+        .enumerate()
+        .map(|(i, chapter)| Chapter::from_ffmpeg(i, chapter))
+        .collect()
+}
+
+/// Splits the part off names of chapters in files that hold several
+/// episodes, like "Intro (Part 2)".
+fn split_part(name: &str) -> (&str, Option<usize>) {
+    let name = name.trim();
+    name.strip_suffix(')')
+        .and_then(|rest| rest.rsplit_once(" (Part "))
+        .and_then(|(base, part)| Some((base, Some(part.parse().ok()?))))
+        .unwrap_or((name, None))
+}
+
+/// Whether `chapter` is one of the intro chapters we write: "Start of
+/// intro" and "End of intro", or "Intro (Part N)" and "End of intro
+/// (Part N)" in files holding several episodes.
+pub(crate) fn is_intro_chapter(chapter: &Chapter) -> bool {
+    let (name, part) = split_part(&chapter.name);
+    name.eq_ignore_ascii_case("Start of intro")
+        || name.eq_ignore_ascii_case("End of intro")
+        || (part.is_some() && name.eq_ignore_ascii_case("Intro"))
+}
+
+/// Finds the intro among chapters a file already has: a "Start of
+/// intro" and "End of intro" pair (as written by us), the pair for the
+/// first episode in a file holding several, or a chapter named "Intro"
+/// along with the one after it.
+pub(crate) fn existing_intro(chapters: &[Chapter]) -> Option<ForcedIntro> {
+    let mut chapters: Vec<&Chapter> = chapters.iter().collect();
+    chapters.sort_by_key(|c| c.start);
+    let named = |name: &str, part: Option<usize>| {
+        chapters.iter().position(|c| {
+            let (base, p) = split_part(&c.name);
+            p == part && base.eq_ignore_ascii_case(name)
+        })
+    };
+    let pairs = [
+        (named("Start of intro", None), named("End of intro", None)),
+        (named("Intro", Some(1)), named("End of intro", Some(1))),
+    ];
+    for pair in &pairs {
+        if let (Some(start), Some(end)) = *pair {
+            if chapters[end].start > chapters[start].start {
+                return Some(ForcedIntro {
+                    start: chapters[start].start,
+                    end: chapters[end].start,
+                });
+            }
+        }
+    }
+    let intro = named("Intro", None)?;
+    let next = chapters.get(intro + 1)?;
+    Some(ForcedIntro {
+        start: chapters[intro].start,
+        end: next.start,
+    })
+}
+
+/// Finds an intro recorded in `SKIP_START` and `SKIP_END` tags on the
+/// container, as other intro-skipping tools write them.
+pub(crate) fn tagged_intro(ictx: &ffmpeg::format::context::Input) -> Option<ForcedIntro> {
+    let metadata = ictx.metadata();
+    let start = offset::parse_offset(metadata.get("SKIP_START")?)?;
+    let end = offset::parse_offset(metadata.get("SKIP_END")?)?;
+    if end <= start {
+        return None;
+    }
+    Some(ForcedIntro { start, end })
+}
+
+/// Puts a chapter list into a shape every player can cope with: sorted
+/// by start time, without chapters past the end of the file or within
+/// 100ms of the previous one, and numbered consecutively.
+pub(crate) fn normalize(
+    chapters: impl IntoIterator<Item = Chapter>,
+    file_duration: Option<Duration>,
+) -> Vec<Chapter> {
+    let mut chapters: Vec<Chapter> = chapters
+        .into_iter()
+        .filter(|ch| file_duration.map(|d| ch.start < d).unwrap_or(true))
+        .collect();
+    chapters.sort_by_key(|ch| ch.start);
+
+    let mut normalized: Vec<Chapter> = Vec::with_capacity(chapters.len());
+    for ch in chapters {
+        if let Some(prev) = normalized.last() {
+            if ch.start < prev.start + DUPLICATE_WINDOW {
+                continue;
+            }
+        }
+        normalized.push(ch);
+    }
+    for (id, ch) in normalized.iter_mut().enumerate() {
+        ch.id = id;
+    }
+    normalized
+}
+
+/// Moves every chapter but one at the very start by `shift`. Chapters
+/// that end up past the end of a file of `file_duration` are left out,
+/// and of those that end up at its start, only the last one is kept:
+/// the others would have no time left to play.
+pub(crate) fn shifted(
+    chapters: Vec<Chapter>,
+    shift: util::Shift,
+    file_duration: Option<Duration>,
+) -> Vec<Chapter> {
+    let zero = Duration::from_secs(0);
+    let mut shifted: Vec<Chapter> = chapters
+        .into_iter()
+        .filter_map(|mut ch| {
+            if ch.start > zero {
+                ch.start = shift.apply(ch.start, file_duration)?;
+            }
+            Some(ch)
+        })
+        .collect();
+    shifted.sort_by_key(|ch| ch.start);
+    let at_start = shifted.iter().filter(|ch| ch.start == zero).count();
+    shifted.drain(..at_start.saturating_sub(1));
+    for (id, ch) in shifted.iter_mut().enumerate() {
+        ch.id = id;
+    }
+    shifted
+}
+
+/// Adds the `proposed` chapters to the `existing` ones, leaving out
+/// those within `window` of an existing chapter. Returns the merged
+/// chapters and how many were left out.
+pub(crate) fn merge(
+    existing: Vec<Chapter>,
+    proposed: Vec<Chapter>,
+    window: Duration,
+) -> (Vec<Chapter>, usize) {
+    let near_existing = |ch: &Chapter| {
+        existing
+            .iter()
+            .any(|old| max(old.start, ch.start) - min(old.start, ch.start) <= window)
+    };
+    let (suppressed, kept): (Vec<Chapter>, Vec<Chapter>) =
+        proposed.into_iter().partition(near_existing);
+    let mut merged = existing;
+    merged.extend(kept);
+    (merged, suppressed.len())
+}
+
+/// Whether `proposed` are the same chapters as `existing`, give or take
+/// the rounding of chapter times in the container.
+fn same_chapters(existing: &[Chapter], proposed: &[Chapter]) -> bool {
+    existing.len() == proposed.len()
+        && existing.iter().zip(proposed).all(|(old, new)| {
+            let distance = max(old.start, new.start) - min(old.start, new.start);
+            distance <= UNCHANGED_TOLERANCE && old.name == new.name
+        })
+}
+
+/// What `set_chapters` did to a file.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Written {
+    /// The file already had these chapters.
+    Unchanged,
+
+    /// The chapters were written, with these warnings from the writer.
+    Changed(Vec<String>),
+}
+
+/// Writes `chapters` into the file, unless it already has exactly
+/// these (and the intro status in `file_tags`), and tags the file with
+/// `file_tags`. Temporary files go into
+/// `temp_dir`, if given.
+pub(crate) fn set_chapters(
+    mkv_file: &Path,
+    chapters: impl IntoIterator<Item = Chapter>,
+    file_tags: &[(String, String)],
+    mkvpropedit: &Mkvpropedit,
+    temp_dir: Option<&Path>,
+) -> anyhow::Result<Written> {
+    let (file_duration, existing, same_status) = {
+        let ictx = ffmpeg::format::input(&mkv_file)
+            .with_context(|| format!("opening {:?} to check its duration", mkv_file))?;
+        (
+            util::container_duration(&ictx),
+            normalize(existing_chapters(&ictx), None),
+            provenance::same_intro_status(&ictx, file_tags),
+        )
+    };
+    let chapters = normalize(chapters, file_duration);
+    if same_chapters(&existing, &chapters) && same_status {
+        return Ok(Written::Unchanged);
+    }
+    let warnings = match Writer::for_path(mkv_file)? {
+        Writer::Mkvpropedit => {
+            write_with_mkvpropedit(mkv_file, chapters, file_tags, mkvpropedit, temp_dir)?
+        }
+        Writer::Remux => {
+            remux::rewrite_in_place(mkv_file, &chapters, file_tags, temp_dir)?;
+            vec![]
+        }
+    };
+    Ok(Written::Changed(warnings))
+}
+
+/// Whether `name` can go into a simple (OGM-style) chapter file, which
+/// has one `KEY=VALUE` line per entry.
+fn fits_simple_format(name: &str) -> bool {
+    !name.chars().any(|c| c == '=' || c.is_control())
+}
+
+/// Escapes `text` for XML. Control characters other than tabs and line
+/// breaks can't appear in XML 1.0 at all, not even escaped, so they
+/// become spaces.
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if c < ' ' || c == '\u{fffe}' || c == '\u{ffff}' => escaped.push(' '),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Renders the chapters as a Matroska XML chapter file, which can hold
+/// any name. Chapters get the UIDs in `uids`, if given, for tags to
+/// refer to.
+fn xml_chapters(chapters: &[Chapter], uids: Option<&[u64]>) -> String {
+    let mut lines = vec![
+        r#"<?xml version="1.0" encoding="UTF-8"?>"#.to_string(),
+        r#"<!DOCTYPE Chapters SYSTEM "matroskachapters.dtd">"#.to_string(),
+        "<Chapters>".to_string(),
+        "  <EditionEntry>".to_string(),
+    ];
+    for (n, ch) in chapters.iter().enumerate() {
+        let secs = ch.start.as_secs();
+        lines.push("    <ChapterAtom>".to_string());
+        if let Some(uids) = uids {
+            lines.push(format!("      <ChapterUID>{}</ChapterUID>", uids[n]));
+        }
+        lines.push(format!(
+            "      <ChapterTimeStart>{:0>2}:{:0>2}:{:0>2}.{:0>9}</ChapterTimeStart>",
+            secs / 60 / 60,
+            secs / 60 % 60,
+            secs % 60,
+            ch.start.subsec_nanos()
+        ));
+        lines.push("      <ChapterDisplay>".to_string());
+        lines.push(format!(
+            "        <ChapterString>{}</ChapterString>",
+            escape_xml(&ch.name)
+        ));
+        lines.push("      </ChapterDisplay>".to_string());
+        lines.push("    </ChapterAtom>".to_string());
+    }
+    lines.push("  </EditionEntry>".to_string());
+    lines.push("</Chapters>".to_string());
+    lines.join("\n") + "\n"
+}
+
+/// Renders the chapter file for mkvpropedit: a simple one, if it can
+/// hold all the names and no tags need to refer to the chapters by
+/// `uids`, XML otherwise. Returns whether it's a simple one, too.
+fn chapter_file(
+    chapters: &[Chapter],
+    uids: Option<&[u64]>,
+    numbering: Numbering,
+) -> (bool, String) {
+    let simple = uids.is_none() && chapters.iter().all(|ch| fits_simple_format(&ch.name));
+    if !simple {
+        return (false, xml_chapters(chapters, uids));
+    }
+    let mut contents = String::new();
+    for ch in chapters {
+        contents.push_str(&ch.to_ogm(numbering));
+        contents.push('\n');
+    }
+    (true, contents)
+}
+
+/// Picks a UID for each chapter. Matroska only asks that they be
+/// non-zero and unlikely to collide.
+fn chapter_uids(mkv_file: &Path, chapters: &[Chapter]) -> Vec<u64> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    chapters
+        .iter()
+        .map(|ch| {
+            let seed = format!("{:?} {} {} {}", mkv_file, now, ch.id, ch.start.as_nanos());
+            util::fnv1a64(seed.bytes()).max(1)
+        })
+        .collect()
+}
+
+/// Renders a `<Tag>` element holding `tags`, on the chapter with
+/// `chapter_uid` or, without one, on the whole file.
+fn xml_tag(tags: &[(String, String)], chapter_uid: Option<u64>) -> Vec<String> {
+    let mut lines = vec!["  <Tag>".to_string(), "    <Targets>".to_string()];
+    match chapter_uid {
+        Some(uid) => {
+            lines.push("      <TargetTypeValue>30</TargetTypeValue>".to_string());
+            lines.push(format!("      <ChapterUID>{}</ChapterUID>", uid));
+        }
+        None => lines.push("      <TargetTypeValue>50</TargetTypeValue>".to_string()),
+    }
+    lines.push("    </Targets>".to_string());
+    for (name, value) in tags {
+        lines.push("    <Simple>".to_string());
+        lines.push(format!("      <Name>{}</Name>", escape_xml(name)));
+        lines.push(format!("      <String>{}</String>", escape_xml(value)));
+        lines.push("    </Simple>".to_string());
+    }
+    lines.push("  </Tag>".to_string());
+    lines
+}
+
+/// Merges our tags into the file's `existing` tags (as mkvextract
+/// exports them), since mkvpropedit can only replace all of a file's
+/// tags at once. Tags on the old chapters go away with them, and our
+/// file tags replace those of earlier runs.
+fn merged_tags(existing: &str, ours: Vec<String>) -> anyhow::Result<String> {
+    let mut lines = vec![
+        r#"<?xml version="1.0" encoding="UTF-8"?>"#.to_string(),
+        r#"<!DOCTYPE Tags SYSTEM "matroskatags.dtd">"#.to_string(),
+        "<Tags>".to_string(),
+    ];
+    // files without tags export nothing at all:
+    if !existing.trim().is_empty() {
+        let root = xml::parse(existing).context("reading the file's tags")?;
+        if root.name != "Tags" {
+            bail!("the file's tags are in a <{}> element", root.name);
+        }
+        for tag in root.children_named("Tag") {
+            let on_chapter = tag
+                .children_named("Targets")
+                .any(|targets| targets.children_named("ChapterUID").next().is_some());
+            let previous_run = tag.children_named("Simple").any(|simple| {
+                simple
+                    .children_named("Name")
+                    .any(|name| name.text.trim() == provenance::VERSION_TAG)
+            });
+            if !on_chapter && !previous_run {
+                lines.push(format!("  {}", &existing[tag.span.clone()]));
+            }
+        }
+    }
+    lines.extend(ours);
+    lines.push("</Tags>".to_string());
+    Ok(lines.join("\n") + "\n")
+}
+
+/// Exports the tags `mkv_file` has now, if any. Without mkvextract,
+/// there's no telling, and the file is taken to have none.
+fn existing_tags(
+    mkv_file: &Path,
+    mkvpropedit: &Mkvpropedit,
+    temp_dir: Option<&Path>,
+) -> anyhow::Result<String> {
+    let tmpfile = match temp_dir {
+        Some(dir) => Temp::new_file_in(dir)?,
+        None => Temp::new_file()?,
+    };
+    let program = mkvpropedit.mkvextract();
+    let output = match Command::new(&program)
+        .arg(mkv_file)
+        .arg("tags")
+        .arg(tmpfile.as_path())
+        .output()
+    {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            service::warn(format!(
+                "{:?}: no {:?} to export its tags with, replacing them all",
+                mkv_file, &program
+            ));
+            return Ok(String::new());
+        }
+        output => output.with_context(|| format!("running {:?}", &program))?,
+    };
+    if !output.status.success() {
+        bail!(
+            "couldn't export the existing tags of {:?}: {}",
+            mkv_file,
+            String::from_utf8_lossy(&output.stdout).trim()
+        );
+    }
+    // files without tags leave it empty:
+    Ok(fs::read_to_string(tmpfile.as_path()).unwrap_or_default())
+}
+
+/// How to show chapters that would be written.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum PreviewFormat {
+    /// One line for each chapter, with when it starts and how long it
+    /// runs.
+    Table,
+
+    /// A simple (OGM-style) chapter file.
+    Ogm,
+    Json,
+
+    /// The Matroska XML chapter file.
+    Xml,
+}
+
+impl FromStr for PreviewFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "table" => Ok(PreviewFormat::Table),
+            "ogm" => Ok(PreviewFormat::Ogm),
+            "json" => Ok(PreviewFormat::Json),
+            "xml" => Ok(PreviewFormat::Xml),
+            _ => bail!(
+                "unknown preview format {:?} (known: table, ogm, json, xml)",
+                s
+            ),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct PreviewEntry<'a> {
+    start: f64,
+    length: Option<f64>,
+    pause: Option<f64>,
+    name: &'a str,
+}
+
+impl PreviewFormat {
+    /// Renders `chapters` (sorted by start) for showing; the last one
+    /// runs until `file_duration`, if known.
+    pub(crate) fn render(
+        self,
+        chapters: &[Chapter],
+        file_duration: Option<Duration>,
+        numbering: Numbering,
+    ) -> String {
+        let length = |n: usize| {
+            chapters
+                .get(n + 1)
+                .map(|next| next.start)
+                .or(file_duration)
+                .map(|end| end.saturating_sub(chapters[n].start))
+        };
+        match self {
+            PreviewFormat::Table => {
+                let rounded = |d: Duration| {
+                    humantime::format_duration(Duration::from_millis(d.as_millis() as u64))
+                        .to_string()
+                };
+                let rows: Vec<(String, String, String, &str)> = (0..chapters.len())
+                    .map(|n| {
+                        (
+                            rounded(chapters[n].start),
+                            length(n).map(rounded).unwrap_or_else(|| "-".to_string()),
+                            chapters[n]
+                                .pause
+                                .map(|pause| format!("{:.1}s", pause.as_secs_f64()))
+                                .unwrap_or_else(|| "-".to_string()),
+                            chapters[n].name.as_str(),
+                        )
+                    })
+                    .collect();
+                let start_width = rows.iter().map(|r| r.0.len()).max().unwrap_or(0).max(5);
+                let length_width = rows.iter().map(|r| r.1.len()).max().unwrap_or(0).max(6);
+                let pause_width = rows.iter().map(|r| r.2.len()).max().unwrap_or(0).max(5);
+                let mut lines = vec![format!(
+                    "{:>3}  {:<sw$}  {:<lw$}  {:<pw$}  name",
+                    "#",
+                    "start",
+                    "length",
+                    "pause",
+                    sw = start_width,
+                    lw = length_width,
+                    pw = pause_width
+                )];
+                for (n, (start, length, pause, name)) in rows.iter().enumerate() {
+                    lines.push(format!(
+                        "{:>3}  {:<sw$}  {:<lw$}  {:<pw$}  {}",
+                        n + 1,
+                        start,
+                        length,
+                        pause,
+                        name,
+                        sw = start_width,
+                        lw = length_width,
+                        pw = pause_width
+                    ));
+                }
+                lines.join("\n")
+            }
+            PreviewFormat::Ogm => chapters
+                .iter()
+                .map(|ch| ch.to_ogm(numbering))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            PreviewFormat::Json => {
+                let entries: Vec<PreviewEntry> = (0..chapters.len())
+                    .map(|n| PreviewEntry {
+                        start: chapters[n].start.as_secs_f64(),
+                        length: length(n).map(|l| l.as_secs_f64()),
+                        pause: chapters[n].pause.map(|p| p.as_secs_f64()),
+                        name: &chapters[n].name,
+                    })
+                    .collect();
+                serde_json::to_string_pretty(&entries).unwrap_or_default()
+            }
+            PreviewFormat::Xml => xml_chapters(chapters, None).trim_end().to_string(),
+        }
+    }
+}
+
+/// mkvpropedit's exit code for "done, but with warnings".
+const MKVPROPEDIT_WARNINGS: i32 = 1;
+
+fn write_with_mkvpropedit(
+    mkv_file: &Path,
+    chapters: Vec<Chapter>,
+    file_tags: &[(String, String)],
+    mkvpropedit: &Mkvpropedit,
+    temp_dir: Option<&Path>,
+) -> anyhow::Result<Vec<String>> {
+    let tmpfile = match temp_dir {
+        Some(dir) => Temp::new_file_in(dir)?,
+        None => Temp::new_file()?,
+    };
+    let f = File::create(tmpfile.as_path())?;
+    let mut w = BufWriter::new(f);
+    let tagged = mkvpropedit.tags && chapters.iter().any(|ch| !ch.tags.is_empty());
+    let uids = if tagged {
+        Some(chapter_uids(mkv_file, &chapters))
+    } else {
+        None
+    };
+    let (simple, contents) = chapter_file(&chapters, uids.as_deref(), mkvpropedit.numbering);
+    write!(&mut w, "{}", contents)?;
+    w.into_inner()?.sync_all()?;
+    let mut tags = vec![];
+    if !file_tags.is_empty() {
+        tags.extend(xml_tag(file_tags, None));
+    }
+    if let Some(uids) = &uids {
+        for (ch, uid) in chapters.iter().zip(uids) {
+            if !ch.tags.is_empty() {
+                tags.extend(xml_tag(&ch.tags, Some(*uid)));
+            }
+        }
+    }
+    let tags_file = match tags.is_empty() {
+        true => None,
+        false => {
+            let tags = merged_tags(&existing_tags(mkv_file, mkvpropedit, temp_dir)?, tags)?;
+            let tags_file = match temp_dir {
+                Some(dir) => Temp::new_file_in(dir)?,
+                None => Temp::new_file()?,
+            };
+            fs::write(tags_file.as_path(), tags)?;
+            Some(tags_file)
+        }
+    };
+
+    let mut command = Command::new(&mkvpropedit.program);
+    command.args(&mkvpropedit.args).arg(mkv_file);
+    // in its own process group, so Ctrl-C doesn't stop it halfway
+    // through writing:
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut command, 0);
+    if simple {
+        // XML declares its own encoding; simple chapter files would
+        // be read in the system's:
+        command.arg("--chapter-charset").arg("UTF-8");
+    }
+    command.arg("--chapters").arg(tmpfile.as_path());
+    if let Some(tags_file) = &tags_file {
+        let mut selector = std::ffi::OsString::from("all:");
+        selector.push(tags_file.as_path());
+        command.arg("--tags").arg(selector);
+    }
+    let output = command
+        .output()
+        .with_context(|| format!("running {:?}", &mkvpropedit.program))?;
+    let mut warnings: Vec<String> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|line| line.starts_with("Warning:"))
+        .map(|line| line.to_string())
+        .collect();
+    let warned = output.status.code() == Some(MKVPROPEDIT_WARNINGS);
+    if warned && warnings.is_empty() {
+        warnings.push("Warning: (no details given)".to_string());
+    }
+    // there's no asking mkvpropedit beforehand, so by now the file has
+    // the new chapters nonetheless:
+    if warned && mkvpropedit.strict {
+        bail!(
+            "mkvpropedit warned about {:?}, which it wrote to nonetheless: {}",
+            mkv_file,
+            warnings.join("; ")
+        );
+    }
+    if !output.status.success() && !warned {
+        bail!(
+            "unsuccessful for {:?} - mkv chapter contents:\n{:?}\n\nmkvpropedit stdout:\n{:?}\nstderr:\n{:?}",
+            mkv_file,
+            fs::read_to_string(tmpfile.as_path()).unwrap_or("unreadable".to_string()),
+            output.stdout,
+            output.stderr
+        );
+    }
+
+    Ok(warnings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chapter(id: usize, secs: u64, name: &str) -> Chapter {
+        Chapter::new(id, Duration::from_secs(secs), name.to_string())
+    }
+
+    /// The chapters each output format is checked against, with the
+    /// duration of the file they're in.
+    fn scenarios() -> Vec<(&'static str, Vec<Chapter>, Option<Duration>)> {
+        let long = vec![
+            chapter(0, 0, "Part 1"),
+            Chapter {
+                pause: Some(Duration::from_millis(1250)),
+                ..Chapter::new(1, Duration::from_millis(3_599_999), "Part 2".to_string())
+            },
+            chapter(2, 3600, "Part 3"),
+            Chapter::new(3, Duration::from_millis(36_061_500), "Part 4".to_string()),
+        ];
+        let unicode = vec![
+            chapter(0, 0, "オープニング 🎵"),
+            chapter(1, 90, "Tom & Jerry <live>"),
+            chapter(2, 600, "Épilogue"),
+        ];
+        let ends = || vec![chapter(0, 0, "Part 1"), chapter(1, 1300, "Part 2")];
+        vec![
+            ("empty", vec![], Some(Duration::from_secs(1320))),
+            ("long", long, Some(Duration::from_secs(36_100))),
+            ("unicode", unicode, Some(Duration::from_secs(1320))),
+            ("known_end", ends(), Some(Duration::from_millis(1_320_500))),
+            ("unknown_end", ends(), None),
+        ]
+    }
+
+    /// Compares each format's rendering of each scenario with the file
+    /// in tests/golden; run with ICA_BLESS=1 to write those instead.
+    #[test]
+    fn renders_like_the_golden_files() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+        let bless = std::env::var_os("ICA_BLESS").is_some();
+        for (scenario, chapters, duration) in scenarios() {
+            for format in &["table", "ogm", "json", "xml"] {
+                let rendered = format.parse::<PreviewFormat>().unwrap().render(
+                    &chapters,
+                    duration,
+                    Numbering::default(),
+                ) + "\n";
+                let path = dir.join(format!("{}.{}", scenario, format));
+                if bless {
+                    fs::create_dir_all(&dir).unwrap();
+                    fs::write(&path, &rendered).unwrap();
+                    continue;
+                }
+                let golden = fs::read_to_string(&path)
+                    .unwrap_or_else(|e| panic!("reading {:?}: {}", path, e));
+                assert_eq!(rendered, golden, "{} as {}", scenario, format);
+            }
+        }
+    }
+
+    #[test]
+    fn shifts_chapters_within_the_file() {
+        let chapters = vec![
+            chapter(0, 0, "Prologue"),
+            chapter(1, 1, "Intro"),
+            chapter(2, 2, "Part 1"),
+            chapter(3, 600, "Part 2"),
+            chapter(4, 1299, "Credits"),
+        ];
+        let earlier: util::Shift = "-1.5s".parse().unwrap();
+        let earlier = shifted(chapters, earlier, Some(Duration::from_secs(1300)));
+        let starts: Vec<_> = earlier
+            .iter()
+            .map(|ch| (ch.id, ch.start.as_millis(), ch.name.as_str()))
+            .collect();
+        assert_eq!(
+            starts,
+            vec![
+                (0, 0, "Intro"),
+                (1, 500, "Part 1"),
+                (2, 598_500, "Part 2"),
+                (3, 1_297_500, "Credits")
+            ]
+        );
+        let later: util::Shift = "+3s".parse().unwrap();
+        let later = shifted(earlier, later, Some(Duration::from_secs(1300)));
+        assert_eq!(later.last().unwrap().name, "Part 2");
+    }
+
+    #[test]
+    fn numbers_chapters() {
+        let default = Numbering::default();
+        assert_eq!((default.label(0), default.id(0)), ("1".into(), "00".into()));
+        let padded = Numbering {
+            start: 1,
+            width: Some(3),
+        };
+        assert_eq!(
+            (padded.label(0), padded.id(1)),
+            ("001".into(), "001".into())
+        );
+        let later = Numbering {
+            start: 5,
+            width: None,
+        };
+        assert_eq!(
+            (later.label(0), later.id(0), later.id(1)),
+            ("5".into(), "04".into(), "05".into())
+        );
+    }
+
+    #[test]
+    fn recognizes_our_intro_chapters() {
+        for name in &[
+            "Start of intro",
+            " end of intro",
+            "Intro (Part 2)",
+            "End of intro (Part 1)",
+        ] {
+            assert!(is_intro_chapter(&chapter(0, 0, name)), "{:?}", name);
+        }
+        for name in &["Intro", "Part 1", "Intro (Part two)", "Pause 1"] {
+            assert!(!is_intro_chapter(&chapter(0, 0, name)), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn finds_existing_intros() {
+        let intro = |chapters: &[Chapter]| {
+            existing_intro(chapters).map(|i| (i.start.as_secs(), i.end.as_secs()))
+        };
+        let ours = [
+            chapter(0, 0, "Cold open"),
+            chapter(1, 60, "Start of intro"),
+            chapter(2, 90, "End of intro"),
+        ];
+        assert_eq!(intro(&ours), Some((60, 90)));
+        let parts = [
+            chapter(0, 30, "Intro (Part 1)"),
+            chapter(1, 60, "End of intro (Part 1)"),
+            chapter(2, 1300, "Intro (Part 2)"),
+            chapter(3, 1330, "End of intro (Part 2)"),
+        ];
+        assert_eq!(intro(&parts), Some((30, 60)));
+        let dvd = [
+            chapter(0, 0, "Prologue"),
+            chapter(1, 45, "Intro"),
+            chapter(2, 100, "Act 1"),
+        ];
+        assert_eq!(intro(&dvd), Some((45, 100)));
+        assert_eq!(
+            intro(&[chapter(0, 0, "Act 1"), chapter(1, 600, "Act 2")]),
+            None
+        );
+    }
+
+    #[test]
+    fn escapes_markup() {
+        assert_eq!(
+            escape_xml(r#"Tom & Jerry <"live"> 'n' more"#),
+            "Tom &amp; Jerry &lt;&quot;live&quot;&gt; &apos;n&apos; more"
+        );
+    }
+
+    #[test]
+    fn replaces_characters_xml_cant_hold() {
+        assert_eq!(escape_xml("a\u{1}b\u{1b}c\u{ffff}"), "a b c ");
+        assert_eq!(escape_xml("tab\tnew\nline\r"), "tab\tnew\nline\r");
+    }
+
+    #[test]
+    fn keeps_emoji_and_cjk_names_in_xml() {
+        let xml = xml_chapters(
+            &[chapter(0, 0, "オープニング 🎵"), chapter(1, 90, "第1幕")],
+            None,
+        );
+        assert!(xml.contains("<ChapterString>オープニング 🎵</ChapterString>"));
+        assert!(xml.contains("<ChapterString>第1幕</ChapterString>"));
+        assert!(xml.contains("<ChapterTimeStart>00:01:30.000000000</ChapterTimeStart>"));
+        assert!(!xml.contains("<ChapterUID>"));
+    }
+
+    #[test]
+    fn writes_plain_names_as_simple_chapters() {
+        let chapters = [chapter(0, 0, "Start of intro"), chapter(1, 75, "第1幕 🎬")];
+        let (simple, contents) = chapter_file(&chapters, None, Numbering::default());
+        assert!(simple);
+        assert_eq!(
+            contents,
+            "CHAPTER00=00:00:00.000\nCHAPTER00NAME=Start of intro\n\
+             CHAPTER01=00:01:15.000\nCHAPTER01NAME=第1幕 🎬\n"
+        );
+    }
+
+    #[test]
+    fn writes_names_simple_files_cant_hold_as_xml() {
+        for name in &["a = b", "two\nlines", "bell\u{7}"] {
+            let (simple, contents) =
+                chapter_file(&[chapter(0, 0, name)], None, Numbering::default());
+            assert!(!simple, "{:?}", name);
+            assert!(contents.starts_with("<?xml"), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn writes_tagged_chapters_as_xml() {
+        let (simple, contents) =
+            chapter_file(&[chapter(0, 0, "Intro")], Some(&[42]), Numbering::default());
+        assert!(!simple);
+        assert!(contents.contains("<ChapterUID>42</ChapterUID>"));
+    }
+
+    #[test]
+    fn merges_with_the_files_own_tags() {
+        let existing = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE Tags SYSTEM "matroskatags.dtd">
+<Tags>
+  <Tag >
+    <Targets><TargetTypeValue>50</TargetTypeValue></Targets>
+    <Simple><Name>TITLE</Name><String>Keep &amp; me</String></Simple>
+  </Tag>
+  <Tag>
+    <Targets>
+      <ChapterUID>123</ChapterUID>
+    </Targets>
+    <Simple><Name>ICA_SIGNALS</Name><String>black</String></Simple>
+  </Tag>
+  <Tag>
+    <Simple>
+      <Name> {} </Name>
+      <String>0.1</String>
+    </Simple>
+  </Tag>
+</Tags>
+"#,
+            provenance::VERSION_TAG
+        );
+        let ours = xml_tag(&[("ICA_RUN".to_string(), "1".to_string())], None);
+        let merged = merged_tags(&existing, ours).unwrap();
+        let root = xml::parse(&merged).unwrap();
+        assert_eq!(root.children.len(), 2);
+        assert!(merged.contains("Keep &amp; me"));
+        assert!(merged.contains("ICA_RUN"));
+        assert!(!merged.contains("ICA_SIGNALS"));
+        assert!(!merged.contains(provenance::VERSION_TAG));
+
+        assert_eq!(merged_tags("", vec![]).unwrap().lines().count(), 4);
+        assert!(merged_tags("<Tags><Tag></Tags>", vec![]).is_err());
+    }
+}
@@ -0,0 +1,222 @@
+//! Persist how far a scan got, so an interrupted scan of a long file
+//! can pick up from there.
+use crate::detect::Candidate;
+use crate::util;
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// How far before the checkpointed position to resume scanning, so
+/// detectors have settled by the time they reach it.
+pub(crate) const RESUME_MARGIN: Duration = Duration::from_secs(5);
+
+/// A position in a file up to which all candidates have been found.
+#[derive(Debug)]
+pub(crate) struct Checkpoint {
+    fingerprint: String,
+    until: Duration,
+    pub(crate) position: Duration,
+    pub(crate) candidates: Vec<Candidate>,
+}
+
+/// Returns the path of the checkpoint for `path`, a hidden file next
+/// to it.
+fn checkpoint_path(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{}.ica-checkpoint", name))
+}
+
+fn parse_secs(value: &str) -> Result<Duration> {
+    let secs: f64 = value.parse().context(format!("invalid time {:?}", value))?;
+    Ok(Duration::try_from_secs_f64(secs)?)
+}
+
+impl Checkpoint {
+    fn parse(text: &str) -> Result<Self> {
+        let mut fingerprint = None;
+        let mut until = None;
+        let mut position = None;
+        let mut candidates = vec![];
+        for line in text.lines() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields.as_slice() {
+                ["fingerprint", value] => fingerprint = Some(value.to_string()),
+                // from before fingerprints; never matches a file:
+                ["size", _] => fingerprint = Some(String::new()),
+                ["until", value] => until = Some(parse_secs(value)?),
+                ["position", value] => position = Some(parse_secs(value)?),
+                ["candidate", offset, length] => {
+                    candidates.push(Candidate::new(parse_secs(offset)?, parse_secs(length)?))
+                }
+                ["candidate", offset, length, end] => {
+                    let mut cand = Candidate::new(parse_secs(offset)?, parse_secs(length)?);
+                    cand.end = parse_secs(end)?;
+                    candidates.push(cand)
+                }
+                [] => {}
+                _ => bail!("unexpected line {:?}", line),
+            }
+        }
+        Ok(Checkpoint {
+            fingerprint: fingerprint.context("no fingerprint")?,
+            until: until.context("no scan length")?,
+            position: position.context("no position")?,
+            candidates,
+        })
+    }
+
+    fn render(&self) -> String {
+        let mut text = format!(
+            "fingerprint {}\nuntil {:.3}\nposition {:.3}\n",
+            self.fingerprint,
+            self.until.as_secs_f64(),
+            self.position.as_secs_f64()
+        );
+        for cand in &self.candidates {
+            text.push_str(&format!(
+                "candidate {:.3} {:.3} {:.3}\n",
+                cand.offset.as_secs_f64(),
+                cand.length.as_secs_f64(),
+                cand.end.as_secs_f64()
+            ));
+        }
+        text
+    }
+
+    /// Whether this is the checkpoint of a scan up to `until`. Scan
+    /// lengths are saved to the millisecond, so they're compared that
+    /// way: --until auto and --compilation come up with finer ones.
+    fn scans_until(&self, until: Duration) -> bool {
+        let millis = |d: Duration| (d.as_secs_f64() * 1000.0).round() as u64;
+        millis(self.until) == millis(until)
+    }
+
+    /// Loads the checkpoint of an earlier scan of `path` up to `until`,
+    /// if there is one and the file hasn't been replaced since.
+    pub(crate) fn load(path: &Path, until: Duration) -> Result<Option<Self>> {
+        let cp_path = checkpoint_path(path);
+        let text = match fs::read_to_string(&cp_path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).context(format!("reading {:?}", &cp_path)),
+        };
+        let checkpoint = Self::parse(&text).context(format!("parsing {:?}", &cp_path))?;
+        if checkpoint.fingerprint != util::fingerprint(path)? || !checkpoint.scans_until(until) {
+            return Ok(None);
+        }
+        Ok(Some(checkpoint))
+    }
+
+    /// Removes the checkpoint for `path`, once it's no longer needed.
+    pub(crate) fn remove(path: &Path) -> Result<()> {
+        match fs::remove_file(checkpoint_path(path)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Saves checkpoints of a scan at regular (wall-clock) intervals.
+pub(crate) struct Checkpointer {
+    path: PathBuf,
+    fingerprint: String,
+    until: Duration,
+    every: Duration,
+    last_saved: Instant,
+}
+
+impl Checkpointer {
+    pub(crate) fn new(path: &Path, until: Duration, every: Duration) -> Result<Self> {
+        Ok(Checkpointer {
+            path: path.to_owned(),
+            fingerprint: util::fingerprint(path)?,
+            until,
+            every,
+            last_saved: Instant::now(),
+        })
+    }
+
+    /// Saves a checkpoint at `position`, if it's been long enough since
+    /// the last one.
+    pub(crate) fn maybe_save(
+        &mut self,
+        position: Duration,
+        candidates: &[Candidate],
+    ) -> Result<()> {
+        if self.last_saved.elapsed() < self.every {
+            return Ok(());
+        }
+        self.last_saved = Instant::now();
+        let checkpoint = Checkpoint {
+            fingerprint: self.fingerprint.clone(),
+            until: self.until,
+            position,
+            candidates: candidates.to_vec(),
+        };
+        let cp_path = checkpoint_path(&self.path);
+        let tmp_path = cp_path.with_extension("ica-checkpoint-tmp");
+        fs::write(&tmp_path, checkpoint.render())
+            .context(format!("writing checkpoint {:?}", &tmp_path))?;
+        fs::rename(&tmp_path, &cp_path).context(format!("renaming to {:?}", &cp_path))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_what_it_writes() {
+        let ms = Duration::from_millis;
+        let mut cand = Candidate::new(ms(92_500), ms(1_250));
+        cand.end = ms(94_000);
+        let checkpoint = Checkpoint {
+            fingerprint: "1234-5678".to_string(),
+            until: ms(600_000),
+            position: ms(321_125),
+            candidates: vec![Candidate::new(ms(10_000), ms(500)), cand],
+        };
+        let text = checkpoint.render();
+        assert_eq!(
+            text,
+            "fingerprint 1234-5678\nuntil 600.000\nposition 321.125\n\
+             candidate 10.000 0.500 10.500\ncandidate 92.500 1.250 94.000\n"
+        );
+        let read = Checkpoint::parse(&text).unwrap();
+        assert_eq!(read.fingerprint, checkpoint.fingerprint);
+        assert_eq!(
+            (read.until, read.position),
+            (checkpoint.until, checkpoint.position)
+        );
+        assert_eq!(read.candidates, checkpoint.candidates);
+    }
+
+    #[test]
+    fn matches_scan_lengths_finer_than_it_saves() {
+        let until = Duration::from_secs_f64(1_234.567_890_1);
+        let checkpoint = Checkpoint {
+            fingerprint: "1234-5678".to_string(),
+            until,
+            position: Duration::from_secs(30),
+            candidates: vec![],
+        };
+        let read = Checkpoint::parse(&checkpoint.render()).unwrap();
+        assert!(read.scans_until(until));
+        assert!(!read.scans_until(until + Duration::from_millis(1)));
+    }
+
+    #[test]
+    fn reads_older_checkpoints() {
+        let read =
+            Checkpoint::parse("size 123\nuntil 600\nposition 30\ncandidate 10 0.5\n").unwrap();
+        assert_eq!(read.fingerprint, "");
+        assert_eq!(read.candidates[0].end, Duration::from_millis(10_500));
+        assert!(Checkpoint::parse("until 600\nposition 30\n").is_err());
+        assert!(Checkpoint::parse("fingerprint x\nuntil soon\nposition 30\n").is_err());
+    }
+}
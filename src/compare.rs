@@ -0,0 +1,137 @@
+//! Compare the results of two detection runs saved with `--emit`.
+use crate::candidates::{self, Entry, Marker};
+use anyhow::{bail, Result};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Offsets at most this far apart (in seconds) count as the same.
+const TOLERANCE: f64 = 0.1;
+
+fn describe_intro(entry: &Entry) -> String {
+    match &entry.intro {
+        Some(intro) => format!("{:.3}s..{:.3}s", intro.start, intro.end),
+        None => "none".to_string(),
+    }
+}
+
+/// Returns the chapters only in `a`, as "NAME at OFFSET".
+fn missing_from(a: &[Marker], b: &[Marker]) -> Vec<String> {
+    a.iter()
+        .filter(|m| {
+            !b.iter()
+                .any(|other| other.name == m.name && (other.start - m.start).abs() <= TOLERANCE)
+        })
+        .map(|m| format!("{:?} at {:.3}s", m.name, m.start))
+        .collect()
+}
+
+/// Whether the intro of one file differs between runs.
+fn intro_moved(before: &Entry, after: &Entry) -> bool {
+    match (&before.intro, &after.intro) {
+        (Some(a), Some(b)) => {
+            (a.start - b.start).abs() > TOLERANCE || (a.end - b.end).abs() > TOLERANCE
+        }
+        (None, None) => false,
+        _ => true,
+    }
+}
+
+/// Lists how the intro and chapters of one file differ between runs.
+fn differences(before: &Entry, after: &Entry) -> Vec<String> {
+    let mut differences = vec![];
+    if intro_moved(before, after) {
+        differences.push(format!(
+            "intro {} -> {}",
+            describe_intro(before),
+            describe_intro(after)
+        ));
+    }
+    for removed in missing_from(&before.chapters, &after.chapters) {
+        differences.push(format!("- {}", removed));
+    }
+    for added in missing_from(&after.chapters, &before.chapters) {
+        differences.push(format!("+ {}", added));
+    }
+    differences
+}
+
+/// Prints the files whose results differ between the runs saved in
+/// `before` and `after`, and a summary. Fails if any differ.
+pub(crate) fn compare(before: &Path, after: &Path) -> Result<()> {
+    let by_path = |entries: Vec<Entry>| -> BTreeMap<PathBuf, Entry> {
+        entries.into_iter().map(|e| (e.path.clone(), e)).collect()
+    };
+    let before = by_path(candidates::read(before)?);
+    let mut after = by_path(candidates::read(after)?);
+
+    let (mut same, mut changed, mut intros_changed, mut only_one) = (0, 0, 0, 0);
+    for (path, old) in &before {
+        let new = match after.remove(path) {
+            Some(new) => new,
+            None => {
+                println!("{:?}: only in the first run", path);
+                only_one += 1;
+                continue;
+            }
+        };
+        let differences = differences(old, &new);
+        if differences.is_empty() {
+            same += 1;
+            continue;
+        }
+        changed += 1;
+        if intro_moved(old, &new) {
+            intros_changed += 1;
+        }
+        println!("{:?}:", path);
+        for difference in differences {
+            println!("  {}", difference);
+        }
+    }
+    for path in after.keys() {
+        println!("{:?}: only in the second run", path);
+        only_one += 1;
+    }
+    println!(
+        "{} files the same, {} changed ({} with a different intro), {} in only one run",
+        same, changed, intros_changed, only_one
+    );
+    if changed + only_one > 0 {
+        bail!("the runs differ");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(json: &str) -> Entry {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn lists_what_changed() {
+        let before = entry(
+            r#"{"path": "/tv/a.mkv", "intro": {"start": 60, "end": 90},
+                "chapters": [{"start": 0, "name": "Prologue"}, {"start": 60, "name": "Intro"}]}"#,
+        );
+        let same = entry(
+            r#"{"path": "/tv/a.mkv", "intro": {"start": 60.05, "end": 90},
+                "chapters": [{"start": 0, "name": "Prologue"}, {"start": 60.05, "name": "Intro"}]}"#,
+        );
+        assert!(differences(&before, &same).is_empty());
+        let after = entry(
+            r#"{"path": "/tv/a.mkv",
+                "chapters": [{"start": 0, "name": "Prologue"}, {"start": 300, "name": "Part 2"}]}"#,
+        );
+        assert_eq!(
+            differences(&before, &after),
+            vec![
+                "intro 60.000s..90.000s -> none",
+                "- \"Intro\" at 60.000s",
+                "+ \"Part 2\" at 300.000s",
+            ]
+        );
+    }
+}
@@ -0,0 +1,91 @@
+//! Read the commercial breaks comskip found in a recording, from the
+//! `.txt` or `.edl` file it writes next to it.
+use crate::chapters::Chapter;
+use crate::edl;
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+use std::time::Duration;
+
+/// Parses comskip's frame-based output: a header line ending in the
+/// frame rate (in hundredths of frames per second, on older versions),
+/// a dashed line, then one `START_FRAME END_FRAME` line per break.
+fn parse_txt(text: &str) -> Result<Vec<Range<Duration>>> {
+    let mut lines = text.lines();
+    let header = lines.next().context("empty file")?;
+    let rate: f64 = header
+        .rsplit("FRAMES AT")
+        .next()
+        .filter(|_| header.contains("FRAMES AT"))
+        .context("no frame rate in header")?
+        .trim()
+        .parse()
+        .context("invalid frame rate")?;
+    let fps = if rate > 500.0 { rate / 100.0 } else { rate };
+    if fps <= 0.0 {
+        bail!("invalid frame rate {}", rate);
+    }
+    let at = |frame: &str| -> Result<Duration> {
+        let frame: f64 = frame
+            .parse()
+            .context(format!("invalid frame {:?}", frame))?;
+        Ok(Duration::try_from_secs_f64(frame / fps)?)
+    };
+    let mut breaks = vec![];
+    for line in lines {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.as_slice() {
+            [start, end] => breaks.push(at(start)?..at(end)?),
+            _ => continue,
+        }
+    }
+    breaks.sort_by_key(|b| b.start);
+    Ok(breaks)
+}
+
+/// Reads the commercial breaks of the recording at `path`, preferring
+/// comskip's `.edl` over its `.txt` output.
+pub(crate) fn breaks_for(path: &Path) -> Result<Vec<Range<Duration>>> {
+    if let Some(segments) = edl::load(&edl::sidecar_path(path))? {
+        return Ok(segments.into_iter().map(|s| s.span).collect());
+    }
+    let txt = path.with_extension("txt");
+    let text = match fs::read_to_string(&txt) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            bail!("no comskip output (.edl or .txt) next to {:?}", path)
+        }
+        Err(e) => return Err(e).context(format!("reading {:?}", &txt)),
+    };
+    parse_txt(&text).context(format!("parsing {:?}", &txt))
+}
+
+/// Turns commercial breaks into chapters: one for each break, and one
+/// for each part of the show between them.
+pub(crate) fn chapters(breaks: &[Range<Duration>]) -> Vec<Chapter> {
+    let mut chapters = vec![];
+    let mut part = 1;
+    let starts_with_show = breaks
+        .first()
+        .map(|b| b.start > Duration::from_secs(0))
+        .unwrap_or(true);
+    if starts_with_show {
+        chapters.push(Chapter::new(
+            0,
+            Duration::from_secs(0),
+            "Part 1".to_string(),
+        ));
+        part += 1;
+    }
+    for (i, commercial) in breaks.iter().enumerate() {
+        chapters.push(Chapter::new(
+            0,
+            commercial.start,
+            format!("Commercial {}", i + 1),
+        ));
+        chapters.push(Chapter::new(0, commercial.end, format!("Part {}", part)));
+        part += 1;
+    }
+    chapters
+}
@@ -0,0 +1,63 @@
+//! A settings file for server deployments, so that what would be a pile
+//! of flags can live in one place:
+//!
+//! ```toml
+//! [paths]
+//! "/media" = "/Volumes/Media"
+//!
+//! [profiles."Some Show"]
+//! threshold = "500ms"
+//! exclude = "0s..20s"
+//! ```
+//!
+//! Profiles also hold the intros learned by "apply --learn-into" (see
+//! `learned`).
+use crate::episode;
+use crate::sidecar::Overrides;
+use anyhow::{Context, Result};
+use serde_derive::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Config {
+    /// Server path prefixes and where they're mounted locally.
+    #[serde(default)]
+    pub(crate) paths: BTreeMap<String, String>,
+
+    /// Detection settings for the episodes of a series, by the series'
+    /// name (as far as file names tell; compared case-insensitively).
+    /// A file's sidecar takes precedence.
+    #[serde(default)]
+    profiles: BTreeMap<String, Overrides>,
+}
+
+impl Config {
+    pub(crate) fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path).context(format!("reading {:?}", path))?;
+        let config: Self = toml::from_str(&text).context(format!("parsing {:?}", path))?;
+        for (series, profile) in &config.profiles {
+            profile
+                .validate()
+                .with_context(|| format!("{:?}: in profiles.{:?}", path, series))?;
+        }
+        Ok(config)
+    }
+
+    /// The settings for the series `path` is an episode of, if any.
+    pub(crate) fn profile_for(&self, path: &Path) -> Option<&Overrides> {
+        self.profile_named_for(path).map(|(_, profile)| profile)
+    }
+
+    /// The name and settings of the profile for the series `path` is an
+    /// episode of, if any.
+    pub(crate) fn profile_named_for(&self, path: &Path) -> Option<(&str, &Overrides)> {
+        let (series, _) = episode::series_and_season(path)?;
+        self.profiles
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(&series))
+            .map(|(name, profile)| (name.as_str(), profile))
+    }
+}
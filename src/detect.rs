@@ -1,17 +1,35 @@
 //! Detect silence / blackness on an input file
-use crate::util::to_duration;
-use anyhow::{Context, Result};
-use ffmpeg::{codec, filter, format, frame, media, Packet, Rational, Stream};
+use crate::checkpoint::{Checkpoint, Checkpointer};
+use crate::frames::{self, Filtered};
+use crate::metadata::{self, PauseMatch};
+use crate::service;
+use crate::trace::{self, Trace};
+use crate::util::{self, to_duration};
+use anyhow::{bail, Context, Result};
+use ffmpeg::{codec, filter, format, frame, media, threading, Packet, Rational, Stream};
 use format::context::input::PacketIter;
 use indicatif::{HumanDuration, ProgressBar};
-use std::{cmp::max, collections::VecDeque, fmt::Debug, time::Duration};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, SyncSender, TryRecvError};
+use std::time::{Duration, Instant};
+use std::{
+    cmp::{max, min},
+    collections::VecDeque,
+    fmt::Debug,
+    ops::Range,
+    str::FromStr,
+    thread,
+};
 
 /// A spot in the video where there's both a blank (black) screen and
 /// a silence.
-#[derive(PartialEq)]
+#[derive(PartialEq, Clone)]
 pub struct Candidate {
     pub offset: Duration,
     pub length: Duration,
+
+    /// Where the pause stops being both quiet and black.
+    pub end: Duration,
 }
 
 impl Debug for Candidate {
@@ -27,7 +45,76 @@ impl Debug for Candidate {
 
 impl Candidate {
     pub(crate) fn new(offset: Duration, length: Duration) -> Self {
-        Self { offset, length }
+        Self {
+            offset,
+            length,
+            end: offset + length,
+        }
+    }
+
+    /// A candidate where audio and video were quiet and black together
+    /// from `offset` to `end`, keyed off one's pause of `length`.
+    fn fused(offset: Duration, length: Duration, end: Duration) -> Self {
+        Self {
+            offset,
+            length,
+            end: max(end, offset),
+        }
+    }
+
+    /// Returns where in the pause a chapter should go.
+    pub(crate) fn at(&self, anchor: Anchor) -> Duration {
+        match anchor {
+            Anchor::Start => self.offset,
+            Anchor::Middle => self.offset + (self.end - self.offset) / 2,
+            Anchor::End => self.end,
+        }
+    }
+}
+
+/// Where in a pause its chapter goes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Anchor {
+    Start,
+
+    /// Halfway through the black, which looks nicer to players that
+    /// jump straight to a chapter.
+    Middle,
+    End,
+}
+
+impl FromStr for Anchor {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "start" => Ok(Anchor::Start),
+            "middle" => Ok(Anchor::Middle),
+            "end" => Ok(Anchor::End),
+            _ => bail!("unknown anchor {:?} (known: start, middle, end)", s),
+        }
+    }
+}
+
+/// How files get scanned.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Backend {
+    /// Decoding and filtering with the linked ffmpeg libraries.
+    Libav,
+
+    /// Running the ffmpeg command, and reading what its filters log.
+    Cli,
+}
+
+impl FromStr for Backend {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "libav" => Ok(Backend::Libav),
+            "cli" => Ok(Backend::Cli),
+            _ => bail!("unknown backend {:?} (known: libav, cli)", s),
+        }
     }
 }
 
@@ -44,13 +131,448 @@ pub(crate) struct Detector {
     video: BlankDetector,
 }
 
+/// Which detector (if any) packets of a given stream go to.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(crate) enum StreamRoute {
+    Audio,
+    Video,
+    Skip,
+}
+
 pub(crate) struct BlankIterator<'a> {
-    detector: Detector,
+    engine: Engine,
     packets: PacketIter<'a>,
+    routes: Vec<StreamRoute>,
     blank_state: DetectState,
     candidates: VecDeque<Candidate>,
+    keyframes: Vec<Duration>,
+
+    /// Start times of every video frame seen, if asked to keep them.
+    frames: Option<Vec<Duration>>,
+    checkpointer: Option<Checkpointer>,
+    emitted: Vec<Candidate>,
+    resumed_after: Option<Duration>,
+    windows: Option<Vec<Range<Duration>>>,
     until: Duration,
     bar: &'a ProgressBar,
+
+    /// Whether the audio detector found any silence at all.
+    heard_silence: bool,
+    blank_since: Option<Duration>,
+
+    /// Black stretches seen before any silence, to fall back on.
+    blanks: Vec<Candidate>,
+    video_only: bool,
+
+    trace: Option<(&'a Trace, PathBuf)>,
+
+    /// What stopped the scan before the end, see `check`.
+    error: Option<anyhow::Error>,
+}
+
+impl<'a> BlankIterator<'a> {
+    /// Returns the error that ended the scan early, if decoding or
+    /// filtering failed; the candidates found until then are still
+    /// good.
+    pub fn check(&mut self) -> Result<()> {
+        self.error.take().map_or(Ok(()), Err)
+    }
+
+    /// Returns the latest video keyframe seen so far at or before
+    /// `offset`.
+    pub fn preceding_keyframe(&self, offset: Duration) -> Option<Duration> {
+        self.keyframes.iter().rev().find(|k| **k <= offset).copied()
+    }
+
+    /// Remembers when each video frame starts, for `nearest_frame`.
+    pub fn keep_frame_times(&mut self) {
+        self.frames.get_or_insert_with(Vec::new);
+    }
+
+    /// Returns the start of the video frame seen so far that starts
+    /// closest to `offset`, if frame times are kept.
+    pub fn nearest_frame(&self, offset: Duration) -> Option<Duration> {
+        self.frames
+            .as_ref()?
+            .iter()
+            .min_by_key(|start| max(**start, offset) - min(**start, offset))
+            .copied()
+    }
+
+    /// Picks up from an earlier scan's checkpoint: its candidates are
+    /// returned first, and candidates found again after resuming at
+    /// the (earlier) seek position are skipped.
+    pub fn resume_from(&mut self, checkpoint: Checkpoint) {
+        self.resumed_after = checkpoint.candidates.last().map(|c| c.offset);
+        self.candidates.extend(checkpoint.candidates);
+    }
+
+    /// Saves checkpoints while scanning.
+    pub fn save_checkpoints(&mut self, checkpointer: Checkpointer) {
+        self.checkpointer = Some(checkpointer);
+    }
+
+    /// Only decode packets inside these time windows.
+    pub fn only_within(&mut self, windows: Vec<Range<Duration>>) {
+        self.windows = Some(windows);
+    }
+
+    /// Records every pause in `trace`, as found in the file at `path`.
+    pub fn trace_to(&mut self, trace: &'a Trace, path: &Path) {
+        self.trace = Some((trace, path.to_owned()));
+    }
+
+    fn record(
+        &self,
+        from: StreamRoute,
+        pause: PauseMatch,
+        before: DetectState,
+        after: DetectState,
+        cand: Option<&Candidate>,
+    ) {
+        let (trace, path) = match &self.trace {
+            Some(trace) => trace,
+            None => return,
+        };
+        let (kind, at) = match pause {
+            PauseMatch::Start(at) => ("start", at),
+            PauseMatch::End(at) => ("end", at),
+            PauseMatch::None => return,
+        };
+        trace.record(&trace::Event {
+            file: path,
+            stream: match from {
+                StreamRoute::Audio => "audio",
+                _ => "video",
+            },
+            pause: kind,
+            at: at.as_secs_f64(),
+            state_before: format!("{:?}", before),
+            state_after: format!("{:?}", after),
+            candidate: cand.map(|c| (c.offset.as_secs_f64(), c.length.as_secs_f64())),
+        });
+    }
+
+    /// Whether the audio never went quiet, so the candidates are black
+    /// stretches alone.
+    pub fn used_video_only(&self) -> bool {
+        self.video_only
+    }
+
+    /// Keeps track of black stretches by themselves, in case the audio
+    /// never goes quiet (e.g. tracks with a constant hum).
+    fn track_blanks(&mut self, from: StreamRoute, pause: PauseMatch) {
+        match (from, pause) {
+            (StreamRoute::Audio, PauseMatch::Start(_)) => {
+                self.heard_silence = true;
+                self.blanks.clear();
+            }
+            (StreamRoute::Video, PauseMatch::Start(at)) => self.blank_since = Some(at),
+            (StreamRoute::Video, PauseMatch::End(end)) => {
+                if let Some(start) = self.blank_since.take() {
+                    if !self.heard_silence {
+                        self.blanks.push(Candidate::new(start, end - start));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn emit(&mut self, cand: Candidate) -> Option<Candidate> {
+        if self.checkpointer.is_some() {
+            self.emitted.push(cand.clone());
+        }
+        Some(cand)
+    }
+}
+
+/// Advances the combined detection state by a pause that the detector
+/// for `from` found, returning the candidate this completes, if any.
+fn fuse(
+    state: DetectState,
+    from: StreamRoute,
+    pause: PauseMatch,
+    bar: &ProgressBar,
+) -> (DetectState, Option<Candidate>) {
+    let found = |offset: Duration, length: Duration, end: Duration| {
+        bar.set_message(&format!("quiet blackness at {}", HumanDuration(offset)));
+        Some(Candidate::fused(offset, length, end))
+    };
+    match (from, pause, state) {
+        (_, PauseMatch::None, s) => (s, None),
+        (StreamRoute::Audio, PauseMatch::Start(d), DetectState::None) => {
+            (DetectState::Audio(d), None)
+        }
+        (StreamRoute::Audio, PauseMatch::Start(audio), DetectState::Video(video)) => {
+            (DetectState::VideoAndAudio { video, audio }, None)
+        }
+        (StreamRoute::Audio, PauseMatch::End(end), DetectState::VideoAndAudio { video, audio }) => {
+            (
+                DetectState::Video(video),
+                found(max(video, audio), end - audio, end),
+            )
+        }
+        (StreamRoute::Audio, PauseMatch::End(_), DetectState::Audio(_)) => {
+            (DetectState::None, None)
+        }
+        (StreamRoute::Video, PauseMatch::Start(d), DetectState::None) => {
+            (DetectState::Video(d), None)
+        }
+        (StreamRoute::Video, PauseMatch::Start(video), DetectState::Audio(audio)) => {
+            (DetectState::VideoAndAudio { audio, video }, None)
+        }
+        (StreamRoute::Video, PauseMatch::End(end), DetectState::VideoAndAudio { audio, video }) => {
+            (
+                DetectState::Audio(audio),
+                found(max(audio, video), end - video, end),
+            )
+        }
+        (StreamRoute::Video, PauseMatch::End(_), DetectState::Video(_)) => {
+            (DetectState::None, None)
+        }
+        // pauses that don't start and end in turn (e.g. an end whose
+        // start was dropped) can't be fused with anything, so they're
+        // ignored:
+        (_, _, s) => (s, None),
+    }
+}
+
+/// Fuses the pauses that the audio and video detectors found, in the
+/// order they happened, into candidates. Like scanning, this falls
+/// back to black stretches alone if the audio never went quiet, and
+/// says whether it did.
+pub(crate) fn fuse_pauses(
+    pauses: Vec<(StreamRoute, PauseMatch)>,
+    bar: &ProgressBar,
+) -> (Vec<Candidate>, bool) {
+    let mut state = DetectState::None;
+    let mut candidates = vec![];
+    let mut heard_silence = false;
+    let mut blank_since = None;
+    let mut blanks = vec![];
+    for (from, pause) in pauses {
+        match (from, pause) {
+            (StreamRoute::Audio, PauseMatch::Start(_)) => heard_silence = true,
+            (StreamRoute::Video, PauseMatch::Start(at)) => blank_since = Some(at),
+            (StreamRoute::Video, PauseMatch::End(end)) => {
+                if let Some(start) = blank_since.take() {
+                    blanks.push(Candidate::new(start, end - start));
+                }
+            }
+            _ => {}
+        }
+        let (next, cand) = fuse(state, from, pause, bar);
+        state = next;
+        candidates.extend(cand);
+    }
+    if !heard_silence && !blanks.is_empty() {
+        return (blanks, true);
+    }
+    (candidates, false)
+}
+
+/// How many packets may be queued up for each detector thread.
+const PIPELINE_DEPTH: usize = 64;
+
+/// A detector running on its own thread, fed packets through a bounded
+/// channel and sending back the pauses it finds.
+struct Worker {
+    packets: Option<SyncSender<(Rational, Packet)>>,
+    pauses: Receiver<PauseMatch>,
+    pending: VecDeque<PauseMatch>,
+    closed: bool,
+    handle: Option<thread::JoinHandle<Result<()>>>,
+}
+
+impl Worker {
+    fn spawn<D>(mut detector: D, until: Duration, bar: &ProgressBar) -> Self
+    where
+        D: PauseDetector + Send + 'static,
+    {
+        let (packets, packets_rx) = mpsc::sync_channel::<(Rational, Packet)>(PIPELINE_DEPTH);
+        let (pauses_tx, pauses) = mpsc::channel();
+        let bar = bar.clone();
+        let handle = thread::spawn(move || {
+            for (time_base, mut packet) in packets_rx {
+                detector.detected_pauses_from_packet(
+                    time_base,
+                    &mut packet,
+                    until,
+                    &bar,
+                    |pause| {
+                        if pause != PauseMatch::None {
+                            let _ = pauses_tx.send(pause);
+                        }
+                    },
+                )?;
+                if detector.is_at_end() {
+                    break;
+                }
+            }
+            Ok(())
+        });
+        Worker {
+            packets: Some(packets),
+            pauses,
+            pending: VecDeque::new(),
+            closed: false,
+            handle: Some(handle),
+        }
+    }
+
+    fn send(&mut self, time_base: Rational, packet: Packet) {
+        if let Some(packets) = &self.packets {
+            if packets.send((time_base, packet)).is_err() {
+                // the detector is done:
+                self.packets = None;
+            }
+        }
+    }
+
+    /// Picks up the pauses found so far, without waiting. Returns the
+    /// error the detector stopped with, if it did.
+    fn poll(&mut self) -> Result<()> {
+        loop {
+            match self.pauses.try_recv() {
+                Ok(pause) => self.pending.push_back(pause),
+                Err(TryRecvError::Empty) => return Ok(()),
+                Err(TryRecvError::Disconnected) => {
+                    self.closed = true;
+                    return self.join();
+                }
+            }
+        }
+    }
+
+    /// Tells the detector there are no more packets and waits for the
+    /// remaining pauses.
+    fn finish(&mut self) -> Result<()> {
+        self.packets = None;
+        self.pending.extend(self.pauses.iter());
+        self.closed = true;
+        self.join()
+    }
+
+    /// Waits for the detector's thread to end, returning how it did.
+    fn join(&mut self) -> Result<()> {
+        match self.handle.take().map(|handle| handle.join()) {
+            Some(Ok(result)) => result,
+            Some(Err(panic)) => std::panic::resume_unwind(panic),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Both detectors on their own threads, with their pauses merged back
+/// into time order.
+struct Pipeline {
+    audio: Worker,
+    video: Worker,
+}
+
+impl Pipeline {
+    /// Moves the pauses that can safely be ordered into `out`: a pause
+    /// is only taken once the other detector has reported a later one
+    /// (or won't report any more).
+    fn ready(&mut self, out: &mut Vec<(StreamRoute, PauseMatch)>) -> Result<()> {
+        self.audio.poll()?;
+        self.video.poll()?;
+        loop {
+            let from = match (self.audio.pending.front(), self.video.pending.front()) {
+                (Some(audio), Some(video)) if audio.at() <= video.at() => StreamRoute::Audio,
+                (Some(_), Some(_)) => StreamRoute::Video,
+                (Some(_), None) if self.video.closed => StreamRoute::Audio,
+                (None, Some(_)) if self.audio.closed => StreamRoute::Video,
+                _ => return Ok(()),
+            };
+            let worker = match from {
+                StreamRoute::Audio => &mut self.audio,
+                _ => &mut self.video,
+            };
+            out.push((from, worker.pending.pop_front().unwrap()));
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.audio.closed && self.video.closed
+    }
+
+    fn finish(&mut self, out: &mut Vec<(StreamRoute, PauseMatch)>) -> Result<()> {
+        self.audio.finish()?;
+        self.video.finish()?;
+        self.ready(out)
+    }
+}
+
+/// How the detectors get run on the packets.
+enum Engine {
+    /// Both on the thread iterating.
+    Serial(Detector),
+
+    /// Each on its own thread.
+    Pipelined(Pipeline),
+}
+
+impl Engine {
+    /// Hands the packet to the right detector, adding the pauses that
+    /// are ready to `out`.
+    fn feed(
+        &mut self,
+        route: StreamRoute,
+        stream: &Stream,
+        mut packet: Packet,
+        until: Duration,
+        bar: &ProgressBar,
+        out: &mut Vec<(StreamRoute, PauseMatch)>,
+    ) -> Result<()> {
+        match self {
+            Engine::Serial(detector) => {
+                let mut found = |pause| out.push((route, pause));
+                if route == StreamRoute::Audio {
+                    detector.audio.detected_pauses_from_packet(
+                        stream.time_base(),
+                        &mut packet,
+                        until,
+                        bar,
+                        &mut found,
+                    )?;
+                } else {
+                    detector.video.detected_pauses_from_packet(
+                        stream.time_base(),
+                        &mut packet,
+                        until,
+                        bar,
+                        &mut found,
+                    )?;
+                }
+                Ok(())
+            }
+            Engine::Pipelined(pipeline) => {
+                match route {
+                    StreamRoute::Audio => pipeline.audio.send(stream.time_base(), packet),
+                    _ => pipeline.video.send(stream.time_base(), packet),
+                }
+                pipeline.ready(out)
+            }
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        match self {
+            Engine::Serial(detector) => detector.video.at_end && detector.audio.at_end,
+            Engine::Pipelined(pipeline) => pipeline.is_done(),
+        }
+    }
+
+    /// Collects the pauses still outstanding once there are no more
+    /// packets.
+    fn finish(&mut self, out: &mut Vec<(StreamRoute, PauseMatch)>) -> Result<()> {
+        match self {
+            Engine::Serial(_) => Ok(()),
+            Engine::Pipelined(pipeline) => pipeline.finish(out),
+        }
+    }
 }
 
 impl<'a> Iterator for BlankIterator<'a> {
@@ -58,77 +580,163 @@ impl<'a> Iterator for BlankIterator<'a> {
 
     fn next(&mut self) -> Option<Self::Item> {
         if let Some(cand) = self.candidates.pop_front() {
-            return Some(cand);
+            return self.emit(cand);
+        }
+        if self.error.is_some() {
+            return None;
         }
         // nothing queued up, let's get the next packet & iterate:
-        let mut blank_state = self.blank_state;
-        let mut candidates = VecDeque::new();
         let bar = self.bar;
+        let mut pauses = vec![];
 
-        while let Some((stream, mut packet)) = self.packets.next() {
-            self.detector
-                .audio
-                .detected_pauses_from_packet(&stream, &mut packet, self.until, bar, |pause| {
-                    blank_state = match (pause, blank_state) {
-                        (PauseMatch::None, s) => s,
-                        (PauseMatch::Start(d), DetectState::None) => DetectState::Audio(d),
-                        (PauseMatch::Start(audio), DetectState::Video(video)) => {
-                            DetectState::VideoAndAudio { video, audio }
-                        }
-                        (PauseMatch::End(end), DetectState::VideoAndAudio { video, audio }) => {
-                            let offset = max(video, audio);
-                            let length = end - audio;
-                            bar.set_message(&format!(
-                                "quiet blackness at {}",
-                                HumanDuration(offset)
-                            ));
-                            candidates.push_back(Candidate::new(offset, length));
-                            DetectState::Video(video)
-                        }
-                        (PauseMatch::End(_), DetectState::Audio(_)) => DetectState::None,
-                        combo => {
-                            unreachable!("Unclear combination of audio circumstances: {:?}", combo);
-                        }
-                    };
-                })
-                .unwrap();
-            self.detector
-                .video
-                .detected_pauses_from_packet(&stream, &mut packet, self.until, bar, |pause| {
-                    blank_state = match (pause, blank_state) {
-                        (PauseMatch::None, s) => s,
-                        (PauseMatch::Start(d), DetectState::None) => DetectState::Video(d),
-                        (PauseMatch::Start(video), DetectState::Audio(audio)) => {
-                            DetectState::VideoAndAudio { audio, video }
-                        }
-                        (PauseMatch::End(end), DetectState::VideoAndAudio { audio, video }) => {
-                            let offset = max(audio, video);
-                            let length = end - video;
-                            bar.set_message(&format!(
-                                "quiet blackness at {}",
-                                HumanDuration(offset)
-                            ));
-                            candidates.push_back(Candidate::new(offset, length));
-                            DetectState::Audio(audio)
+        loop {
+            let mut packet_ts = None;
+            let mut exhausted = true;
+            if let Some((stream, packet)) = self.packets.next() {
+                let route = self
+                    .routes
+                    .get(stream.index())
+                    .copied()
+                    .unwrap_or(StreamRoute::Skip);
+                if route == StreamRoute::Skip {
+                    continue;
+                }
+                packet_ts = packet.pts().map(|pts| to_duration(pts, stream.time_base()));
+                let past_windows = match (&self.windows, packet_ts) {
+                    (Some(windows), Some(ts)) => {
+                        if ts < self.until && !windows.iter().any(|window| window.contains(&ts)) {
+                            continue;
                         }
-                        (PauseMatch::End(_), DetectState::Video(_)) => DetectState::None,
-                        combo => {
-                            unreachable!("Unclear combination of video circumstances: {:?}", combo);
+                        ts >= self.until
+                    }
+                    _ => false,
+                };
+                if !past_windows {
+                    exhausted = false;
+                    if route == StreamRoute::Video {
+                        if let Some(ts) = packet_ts {
+                            if packet.is_key() {
+                                self.keyframes.push(ts);
+                            }
+                            if let Some(frames) = &mut self.frames {
+                                frames.push(ts);
+                            }
                         }
                     }
-                })
-                .unwrap();
+                    let fed =
+                        self.engine
+                            .feed(route, &stream, packet, self.until, bar, &mut pauses);
+                    if let Err(e) = fed {
+                        self.error = Some(e);
+                        return None;
+                    }
+                }
+            }
+            if exhausted || self.engine.is_done() {
+                if let Err(e) = self.engine.finish(&mut pauses) {
+                    self.error = Some(e);
+                    return None;
+                }
+                exhausted = true;
+            }
 
-            self.blank_state = blank_state;
-            self.candidates.append(&mut candidates);
+            let mut candidates = vec![];
+            for (from, pause) in pauses.drain(..) {
+                self.track_blanks(from, pause);
+                let (state, cand) = fuse(self.blank_state, from, pause, bar);
+                self.record(from, pause, self.blank_state, state, cand.as_ref());
+                self.blank_state = state;
+                candidates.extend(cand);
+            }
+            if exhausted && !self.heard_silence && !self.blanks.is_empty() {
+                self.video_only = true;
+                candidates.append(&mut self.blanks);
+            }
+            if let Some(after) = self.resumed_after {
+                candidates.retain(|cand| cand.offset > after + crate::chapters::DUPLICATE_WINDOW);
+            }
+            self.candidates.extend(candidates);
             if let Some(cand) = self.candidates.pop_front() {
-                return Some(cand);
+                return self.emit(cand);
             }
-            if self.detector.video.at_end && self.detector.audio.at_end {
+            if let (Some(checkpointer), Some(ts), DetectState::None) =
+                (&mut self.checkpointer, packet_ts, self.blank_state)
+            {
+                // nothing is in progress, so it's safe to resume here:
+                if let Err(e) = checkpointer.maybe_save(ts, &self.emitted) {
+                    let message = format!("couldn't save a checkpoint: {:#}", e);
+                    if bar.is_hidden() {
+                        service::warn(message);
+                    } else {
+                        bar.println(message);
+                    }
+                }
+            }
+            if exhausted {
                 return None;
             }
         }
-        None
+    }
+}
+
+/// The candidates one of the backends finds.
+// one per scan, so the size of a variant doesn't matter:
+#[allow(clippy::large_enum_variant)]
+pub(crate) enum Markers<'a> {
+    Libav(BlankIterator<'a>),
+
+    /// Found by the ffmpeg command, all at once.
+    Cli {
+        candidates: std::vec::IntoIter<Candidate>,
+        video_only: bool,
+    },
+}
+
+impl Iterator for Markers<'_> {
+    type Item = Candidate;
+
+    fn next(&mut self) -> Option<Candidate> {
+        match self {
+            Markers::Libav(markers) => markers.next(),
+            Markers::Cli { candidates, .. } => candidates.next(),
+        }
+    }
+}
+
+impl Markers<'_> {
+    /// See `BlankIterator::check`; the ffmpeg command's errors come
+    /// up before any candidates do.
+    pub fn check(&mut self) -> Result<()> {
+        match self {
+            Markers::Libav(markers) => markers.check(),
+            Markers::Cli { .. } => Ok(()),
+        }
+    }
+
+    /// See `BlankIterator::used_video_only`.
+    pub fn used_video_only(&self) -> bool {
+        match self {
+            Markers::Libav(markers) => markers.used_video_only(),
+            Markers::Cli { video_only, .. } => *video_only,
+        }
+    }
+
+    /// See `BlankIterator::preceding_keyframe`; the ffmpeg command
+    /// doesn't say where keyframes are.
+    pub fn preceding_keyframe(&self, offset: Duration) -> Option<Duration> {
+        match self {
+            Markers::Libav(markers) => markers.preceding_keyframe(offset),
+            Markers::Cli { .. } => None,
+        }
+    }
+
+    /// See `BlankIterator::nearest_frame`; the ffmpeg command doesn't
+    /// say where frames start.
+    pub fn nearest_frame(&self, offset: Duration) -> Option<Duration> {
+        match self {
+            Markers::Libav(markers) => markers.nearest_frame(offset),
+            Markers::Cli { .. } => None,
+        }
     }
 }
 
@@ -139,142 +747,518 @@ impl Detector {
         until: Duration,
         bar: &'a ProgressBar,
     ) -> Result<BlankIterator<'a>> {
+        self.iterate(ictx, until, bar, false)
+    }
+
+    /// Like `markers`, but runs the audio and video detectors on
+    /// threads of their own.
+    pub fn pipelined_markers<'a>(
+        self,
+        ictx: &'a mut format::context::Input,
+        until: Duration,
+        bar: &'a ProgressBar,
+    ) -> Result<BlankIterator<'a>> {
+        self.iterate(ictx, until, bar, true)
+    }
+
+    fn iterate<'a>(
+        self,
+        ictx: &'a mut format::context::Input,
+        until: Duration,
+        bar: &'a ProgressBar,
+        pipelined: bool,
+    ) -> Result<BlankIterator<'a>> {
+        let routes = ictx
+            .streams()
+            .map(|stream| {
+                if self.audio.is_applicable_stream(&stream) {
+                    StreamRoute::Audio
+                } else if self.video.is_applicable_stream(&stream) {
+                    StreamRoute::Video
+                } else {
+                    StreamRoute::Skip
+                }
+            })
+            .collect();
+        let engine = if pipelined {
+            Engine::Pipelined(Pipeline {
+                audio: Worker::spawn(self.audio, until, bar),
+                video: Worker::spawn(self.video, until, bar),
+            })
+        } else {
+            Engine::Serial(self)
+        };
         Ok(BlankIterator {
-            detector: self,
+            engine,
             packets: ictx.packets(),
+            routes,
             blank_state: DetectState::None,
             candidates: VecDeque::new(),
+            keyframes: vec![],
+            frames: None,
+            checkpointer: None,
+            emitted: vec![],
+            resumed_after: None,
+            windows: None,
             until,
             bar,
+            heard_silence: false,
+            blank_since: None,
+            blanks: vec![],
+            video_only: false,
+            trace: None,
+            error: None,
         })
     }
 }
 
+/// Decoded frames each video decoder thread and the filter graph may
+/// hold on to at once, on top of the one being looked at.
+const FRAMES_IN_FLIGHT_PER_THREAD: u64 = 4;
+
+/// Rough allowance for the audio side, demuxer buffers and filter graph
+/// state of one file.
+const BASE_MEMORY_PER_FILE: u64 = 32 * 1024 * 1024;
+
+/// Estimates how much memory scanning `ictx` takes, based on the frame
+/// size of the video stream detection uses and the number of decoder
+/// threads. This only looks at the stream's parameters, so it can be
+/// reserved before any decoder is opened.
+pub(crate) fn estimated_memory(ictx: &format::context::Input, decoder_threads: usize) -> u64 {
+    let (width, height) = match ictx.streams().best(media::Type::Video) {
+        Some(video) => unsafe {
+            let parameters = &*video.parameters().as_ptr();
+            (
+                parameters.width.max(0) as u64,
+                parameters.height.max(0) as u64,
+            )
+        },
+        None => (0, 0),
+    };
+    // 12 bits per pixel for the usual 4:2:0 formats:
+    let frame_size = width * height * 3 / 2;
+    let frames = 1 + FRAMES_IN_FLIGHT_PER_THREAD * decoder_threads.max(1) as u64;
+    BASE_MEMORY_PER_FILE + frame_size * frames
+}
+
 impl Debug for Detector {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
             "Detector {{ audio_stream: {:?}, video_stream: {:?} }}",
-            self.audio.audio_stream, self.video.video_stream
+            self.audio.frames.stream(),
+            self.video.frames.stream()
         )
     }
 }
 
-pub(crate) fn detector(ictx: &mut format::context::Input) -> Result<Detector> {
-    let audio = ictx
-        .streams()
-        .best(media::Type::Audio)
-        .context("finding 'best' audio stream")?;
+/// Which of several audio streams to look for silence in. Streams
+/// matching neither preference (or without any given) leave the choice
+/// to ffmpeg's idea of the "best" one.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct AudioPreference {
+    /// Prefer the stream marked as the default track.
+    pub(crate) default_track: bool,
 
-    // AV decoding:
+    /// Prefer a stream in the original language: one marked as the
+    /// original, or else one in the same language as the video.
+    pub(crate) original_language: bool,
+}
+
+/// Returns the language tag of `stream`, unless it's unknown.
+fn language(stream: &Stream) -> Option<String> {
+    stream
+        .metadata()
+        .get("language")
+        .filter(|language| *language != "und")
+        .map(str::to_string)
+}
+
+fn preferred_audio<'a>(
+    ictx: &'a format::context::Input,
+    preference: &AudioPreference,
+) -> Option<Stream<'a>> {
+    let audio = || {
+        ictx.streams()
+            .filter(|s| s.parameters().medium() == media::Type::Audio)
+    };
+    if preference.original_language {
+        let marked = audio().find(|s| {
+            s.disposition()
+                .contains(ffmpeg::format::stream::Disposition::ORIGINAL)
+        });
+        if marked.is_some() {
+            return marked;
+        }
+        let video_language = ictx
+            .streams()
+            .best(media::Type::Video)
+            .and_then(|video| language(&video));
+        if let Some(video_language) = video_language {
+            let same = audio().find(|s| language(s).as_ref() == Some(&video_language));
+            if same.is_some() {
+                return same;
+            }
+        }
+    }
+    if preference.default_track {
+        let default = audio().find(|s| {
+            s.disposition()
+                .contains(ffmpeg::format::stream::Disposition::DEFAULT)
+        });
+        if default.is_some() {
+            return default;
+        }
+    }
+    ictx.streams().best(media::Type::Audio)
+}
+
+/// Finds the preferred (or else the "best") audio stream in the input
+/// and opens a decoder for it, returning the stream index along with
+/// the decoder.
+pub(crate) fn audio_decoder(
+    ictx: &format::context::Input,
+    preference: &AudioPreference,
+) -> Result<(usize, codec::decoder::Audio)> {
+    let audio = preferred_audio(ictx, preference).context("finding an audio stream")?;
     let mut audio_decoder = audio
         .codec()
         .decoder()
         .audio()
         .context("getting an audio decoder")?;
     audio_decoder.set_parameters(audio.parameters())?;
+    Ok((audio.index(), audio_decoder))
+}
 
+/// The indices of the audio and video stream that detection scans: the
+/// preferred audio stream, as for `audio_decoder`, and the "best" video
+/// stream.
+pub(crate) fn scanned_streams(
+    ictx: &format::context::Input,
+    preference: &AudioPreference,
+) -> Result<(usize, usize)> {
+    let audio = preferred_audio(ictx, preference).context("finding an audio stream")?;
     let video = ictx
         .streams()
         .best(media::Type::Video)
-        .context("finding 'video' audio stream")?;
-    let mut video_decoder = video
-        .codec()
-        .decoder()
-        .video()
-        .context("getting a video decoder")?;
-    video_decoder.set_parameters(video.parameters())?;
+        .context("finding a video stream")?;
+    Ok((audio.index(), video.index()))
+}
+
+/// Sample formats that the audio filters get converted to, whatever
+/// the decoder produces.
+const FILTER_SAMPLE_FORMATS: &str = "flt|fltp";
+
+/// Returns the decoder's channel layout, and whether it's the one the
+/// decoder reported. If that's missing or doesn't match the channel
+/// count, this guesses the usual layout for the channel count (or
+/// stereo, if even that's unknown).
+fn channel_layout(decoder: &codec::decoder::Audio) -> (ffmpeg::ChannelLayout, bool) {
+    let layout = decoder.channel_layout();
+    let channels = i32::from(decoder.channels());
+    if !layout.is_empty() && layout.channels() == channels {
+        return (layout, true);
+    }
+    let guess = ffmpeg::ChannelLayout::default(channels);
+    if channels > 0 && !guess.is_empty() {
+        (guess, false)
+    } else {
+        (ffmpeg::ChannelLayout::STEREO, false)
+    }
+}
+
+/// Filters that no scan can do without.
+const REQUIRED_FILTERS: &[&str] = &[
+    "abuffer",
+    "abuffersink",
+    "buffer",
+    "buffersink",
+    "aformat",
+    "blackdetect",
+];
+
+/// Filters that only some signals need, and what's not possible (or
+/// done differently) without them.
+const OPTIONAL_FILTERS: &[(&str, &str)] = &[
+    (
+        "silencedetect",
+        "no silence detection; breaks are found by black frames alone",
+    ),
+    (
+        "astats",
+        "no loudness measurements, for --auto-tune, --dump-features, --scene-cuts and --snap-end-to-sound",
+    ),
+    (
+        "signalstats",
+        "no brightness measurements, for --auto-tune, --dump-features and --scene-cuts",
+    ),
+    (
+        "select",
+        "no scene change scores, for --auto-tune, --dump-features and --scene-cuts",
+    ),
+    (
+        "scale",
+        "no downscaling, for --auto-tune, --dump-features and --scene-cuts",
+    ),
+];
+
+/// Looks up a filter in the linked ffmpeg, which may have been built
+/// without it.
+fn find_filter(name: &str) -> Result<filter::Filter> {
+    filter::find(name).with_context(|| format!("the linked ffmpeg lacks the {:?} filter", name))
+}
+
+/// Checks that the linked ffmpeg has the filters detection uses: fails
+/// naming the first missing one that every scan needs, and otherwise
+/// describes what can't be done for lack of the others.
+pub(crate) fn missing_filters() -> Result<Vec<String>> {
+    for name in REQUIRED_FILTERS {
+        find_filter(name)?;
+    }
+    Ok(OPTIONAL_FILTERS
+        .iter()
+        .filter(|(name, _)| filter::find(name).is_none())
+        .map(|(name, consequence)| {
+            format!(
+                "the linked ffmpeg lacks the {:?} filter: {}",
+                name, consequence
+            )
+        })
+        .collect())
+}
 
-    // audio filter chain:
+/// Fails naming the first filter in the chain `spec` that the linked
+/// ffmpeg lacks, as parsing the chain would only say that one is.
+fn check_filters(spec: &str) -> Result<()> {
+    // commas escaped with a backslash are part of a filter's options:
+    let mut escaped = false;
+    let filters = spec.split(|c| {
+        let split = c == ',' && !escaped;
+        escaped = c == '\\' && !escaped;
+        split
+    });
+    for part in filters {
+        let name = part.split('=').next().unwrap_or_default().trim();
+        find_filter(name)?;
+    }
+    Ok(())
+}
+
+/// Builds a filter graph that feeds frames from `decoder` through the
+/// filter chain described by `spec`.
+pub(crate) fn audio_filter(decoder: &codec::decoder::Audio, spec: &str) -> Result<filter::Graph> {
+    if decoder.format() == ffmpeg::format::Sample::None || decoder.rate() == 0 {
+        bail!("audio decoder doesn't report its sample format/rate");
+    }
     let mut audio_filter = filter::Graph::new();
+    let (layout, known) = channel_layout(decoder);
     let audio_args = format!(
         "time_base={}:sample_rate={}:sample_fmt={}:channel_layout=0x{:x}",
-        audio_decoder.time_base(),
-        audio_decoder.rate(),
-        audio_decoder.format().name(),
-        audio_decoder.channel_layout().bits(),
+        decoder.time_base(),
+        decoder.rate(),
+        decoder.format().name(),
+        layout.bits(),
     );
-    audio_filter.add(&filter::find("abuffer").unwrap(), "in", &audio_args)?;
-    audio_filter.add(&filter::find("abuffersink").unwrap(), "out", "")?;
+    audio_filter.add(&find_filter("abuffer")?, "in", &audio_args)?;
+    audio_filter.add(&find_filter("abuffersink")?, "out", "")?;
+    // Convert to a sample format that every filter handles; and since
+    // detectors behave oddly on many-channel (e.g. Atmos) audio, or on
+    // layouts we had to guess, mix those down to stereo:
+    let mut conversion = format!("aformat=sample_fmts={}", FILTER_SAMPLE_FORMATS);
+    if !known || layout.channels() > 2 {
+        conversion.push_str(":channel_layouts=stereo");
+    }
+    let spec = format!("{},{}", conversion, spec);
+    check_filters(&spec)?;
     audio_filter
         .output("in", 0)?
         .input("out", 0)?
-        .parse("silencedetect=n=-50dB:d=0.3")?;
+        .parse(&spec)?;
     audio_filter.validate().context("validating audio filter")?;
+    Ok(audio_filter)
+}
+
+/// How video gets decoded and prepared for black frame detection.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct VideoSettings {
+    /// Number of frame-threaded decoder threads.
+    pub(crate) threads: usize,
 
-    // video filter chain:
+    /// Scale frames down to this height before looking for blackness.
+    pub(crate) downscale_to: Option<u32>,
+
+    /// Only decode keyframes.
+    pub(crate) keyframes_only: bool,
+}
+
+impl VideoSettings {
+    pub(crate) fn with_threads(threads: usize) -> Self {
+        VideoSettings {
+            threads,
+            downscale_to: None,
+            keyframes_only: false,
+        }
+    }
+}
+
+/// Levels below which audio counts as silent and video as black.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Thresholds {
+    /// Audio quieter than this many dBFS is silent.
+    pub(crate) silence_db: f64,
+
+    /// Pixels darker than this fraction of the luma range are black
+    /// (blackdetect's `pix_th`).
+    pub(crate) black_pixel: f64,
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        Thresholds {
+            silence_db: -50.0,
+            black_pixel: 0.1,
+        }
+    }
+}
+
+/// Finds the "best" video stream in the input and opens a decoder for
+/// it, returning the stream index along with the decoder.
+impl Thresholds {
+    /// The filter chain that finds silences.
+    pub(crate) fn silence_filter(&self) -> String {
+        format!("silencedetect=n={}dB:d=0.3", self.silence_db)
+    }
+
+    /// The filter chain that finds black frames.
+    pub(crate) fn black_filter(&self) -> String {
+        format!("blackdetect=d=0.5:pix_th={}", self.black_pixel)
+    }
+}
+
+pub(crate) fn video_decoder(
+    ictx: &format::context::Input,
+    settings: &VideoSettings,
+) -> Result<(usize, codec::decoder::Video)> {
+    let video = ictx
+        .streams()
+        .best(media::Type::Video)
+        .context("finding 'video' audio stream")?;
+    let mut video_codec = video.codec();
+    video_codec.set_threading(threading::Config {
+        kind: threading::Type::Frame,
+        count: settings.threads,
+        safe: false,
+    });
+    let mut video_decoder = video_codec.decoder();
+    if settings.keyframes_only {
+        video_decoder.skip_frame(ffmpeg::Discard::NonKey);
+    }
+    let mut video_decoder = video_decoder.video().context("getting a video decoder")?;
+    video_decoder.set_parameters(video.parameters())?;
+    Ok((video.index(), video_decoder))
+}
+
+/// Sets up a filter graph feeding frames from `decoder` through the
+/// filter chain described by `spec`, scaled down to `downscale_to`
+/// lines first, if given.
+pub(crate) fn video_filter(
+    decoder: &codec::decoder::Video,
+    downscale_to: Option<u32>,
+    spec: &str,
+) -> Result<filter::Graph> {
     let mut video_filter = filter::Graph::new();
     let video_args = format!(
         "time_base={}:frame_rate={}:width={}:height={}:pix_fmt={}",
-        video_decoder.time_base(),
-        video_decoder.frame_rate().expect("Frame rate not known"),
-        video_decoder.width(),
-        video_decoder.height(),
-        video_decoder
+        decoder.time_base(),
+        decoder.frame_rate().context("frame rate not known")?,
+        decoder.width(),
+        decoder.height(),
+        decoder
             .format()
             .descriptor()
-            .expect("Pixel format descriptor not known")
+            .context("pixel format descriptor not known")?
             .name(),
     );
-    video_filter.add(&filter::find("buffer").unwrap(), "in", &video_args)?;
-    video_filter.add(&filter::find("buffersink").unwrap(), "out", "")?;
+    video_filter.add(&find_filter("buffer")?, "in", &video_args)?;
+    video_filter.add(&find_filter("buffersink")?, "out", "")?;
+    let spec = match downscale_to {
+        Some(height) => format!("scale=-2:{},{}", height, spec),
+        None => spec.to_string(),
+    };
+    check_filters(&spec)?;
     video_filter
         .output("in", 0)?
         .input("out", 0)?
-        .parse("blackdetect=d=0.5:pix_th=0.1")?;
+        .parse(&spec)?;
     video_filter.validate().context("validating video filter")?;
+    Ok(video_filter)
+}
+
+pub(crate) fn detector(
+    ictx: &mut format::context::Input,
+    settings: &VideoSettings,
+    thresholds: &Thresholds,
+    audio: &AudioPreference,
+) -> Result<Detector> {
+    // AV decoding:
+    let (audio_index, audio_decoder) = audio_decoder(ictx, audio)?;
+    let (video_index, video_decoder) = video_decoder(ictx, settings)?;
+
+    // filter chains; without silencedetect, the audio never goes quiet,
+    // so breaks are found by black frames alone:
+    let silence = match filter::find("silencedetect") {
+        Some(_) => thresholds.silence_filter(),
+        None => "anull".to_string(),
+    };
+    let audio_filter = audio_filter(&audio_decoder, &silence)?;
+    let video_filter = video_filter(
+        &video_decoder,
+        settings.downscale_to,
+        &thresholds.black_filter(),
+    )?;
 
     Ok(Detector {
-        audio: SilenceDetector::new(audio.index(), audio_filter, audio_decoder),
-        video: BlankDetector::new(video.index(), video_filter, video_decoder),
+        audio: SilenceDetector::new(audio_index, audio_filter, audio_decoder),
+        video: BlankDetector::new(video_index, video_filter, video_decoder),
     })
 }
 
-#[derive(PartialEq, Debug, Clone, Copy)]
-enum PauseMatch {
-    None,
-    Start(Duration),
-    End(Duration),
-}
-
 trait PauseDetector {
+    type Decoder: frames::Decoder;
+
+    /// Feeds a packet through the decoder and filter chain. Callers
+    /// only hand in packets from the stream `is_applicable_stream`
+    /// accepted.
     fn detected_pauses_from_packet(
         &mut self,
-        stream: &Stream,
+        stream_time_base: Rational,
         packet: &mut Packet,
         until: Duration,
         bar: &ProgressBar,
         mut callback: impl FnMut(PauseMatch),
     ) -> Result<Option<bool>> {
-        if !self.is_applicable_stream(&stream) {
-            return Ok(Some(false));
-        }
         if self.is_at_end() {
             return Ok(None);
         }
-        let in_time_base = self.time_base();
-        packet.rescale_ts(stream.time_base(), in_time_base);
-
-        let mut frame = Self::empty_frame();
-        if let (Ok(true), timestamp) = self.decode(&packet, &mut frame) {
-            if let Some(timestamp) = timestamp {
-                let at_ts = to_duration(timestamp, in_time_base);
-                if at_ts >= until {
-                    self.set_at_end();
-                }
-                Self::update_progress(bar, at_ts.as_millis() as u64);
-            }
-            self.filter_frame_in(&frame).unwrap();
-            while let Ok(..) = self.filter_frame_output(&mut frame) {
-                callback(self.frame_matches(&frame));
+        let (frames, inside_pause) = self.frames();
+        let time_base = frames.time_base();
+        let decoded = frames.feed(stream_time_base, packet, |frame| {
+            let at = frame
+                .timestamp()
+                .and_then(|ts| util::checked_duration(ts, time_base));
+            callback(Self::frame_matches(inside_pause, frame, at));
+        })?;
+        if let Some(timestamp) = decoded {
+            let at_ts = to_duration(timestamp, time_base);
+            if at_ts >= until {
+                self.set_at_end();
             }
+            self.update_progress(bar, at_ts.as_millis() as u64);
         }
         Ok(Some(true))
     }
 
-    fn update_progress(_bar: &ProgressBar, _position: u64) {}
-
-    type FrameType;
+    fn update_progress(&mut self, _bar: &ProgressBar, _position: u64) {}
 
     fn is_applicable_stream(&self, stream: &Stream) -> bool;
 
@@ -282,28 +1266,20 @@ trait PauseDetector {
 
     fn is_at_end(&self) -> bool;
 
-    fn time_base(&self) -> Rational;
-
-    fn empty_frame() -> Self::FrameType;
+    /// The decoded and filtered frames, and whether the last of them
+    /// was inside a pause.
+    fn frames(&mut self) -> (&mut Filtered<Self::Decoder>, &mut bool);
 
-    fn decode(
-        &mut self,
-        packet: &Packet,
-        frame: &mut Self::FrameType,
-    ) -> (Result<bool, ffmpeg::Error>, Option<i64>);
-
-    fn filter_frame_in(&mut self, frame: &Self::FrameType) -> Result<(), ffmpeg::Error>;
-
-    fn filter_frame_output(&mut self, frame: &mut Self::FrameType) -> Result<(), ffmpeg::Error>;
-
-    fn frame_matches(&mut self, frame: &Self::FrameType) -> PauseMatch;
+    /// What a filtered `frame`, shown `at`, says about pauses.
+    fn frame_matches(
+        inside_pause: &mut bool,
+        frame: &<Self::Decoder as frames::Decoder>::Frame,
+        at: Option<Duration>,
+    ) -> PauseMatch;
 }
 
 struct SilenceDetector {
-    audio_stream: usize,
-    time_base: Rational,
-    audio_filter: filter::Graph,
-    audio_decoder: codec::decoder::Audio,
+    frames: Filtered<codec::decoder::Audio>,
     at_end: bool,
     inside_pause: bool,
 }
@@ -311,15 +1287,11 @@ struct SilenceDetector {
 impl SilenceDetector {
     fn new(
         audio_stream: usize,
-        mut audio_filter: filter::Graph,
+        audio_filter: filter::Graph,
         audio_decoder: codec::decoder::Audio,
     ) -> Self {
-        audio_filter.validate().expect("audio filter can't work!");
         Self {
-            audio_stream,
-            time_base: audio_decoder.time_base(),
-            audio_filter,
-            audio_decoder,
+            frames: Filtered::new(audio_stream, audio_decoder, audio_filter),
             at_end: false,
             inside_pause: false,
         }
@@ -327,8 +1299,10 @@ impl SilenceDetector {
 }
 
 impl PauseDetector for SilenceDetector {
+    type Decoder = codec::decoder::Audio;
+
     fn is_applicable_stream(&self, stream: &Stream) -> bool {
-        self.audio_stream == stream.index()
+        self.frames.stream() == stream.index()
     }
 
     fn set_at_end(&mut self) {
@@ -339,72 +1313,31 @@ impl PauseDetector for SilenceDetector {
         self.at_end
     }
 
-    fn time_base(&self) -> Rational {
-        self.time_base
-    }
-    type FrameType = frame::Audio;
-
-    fn empty_frame() -> Self::FrameType {
-        frame::Audio::empty()
-    }
-
-    fn decode(
-        &mut self,
-        packet: &Packet,
-        mut frame: &mut Self::FrameType,
-    ) -> (Result<bool, ffmpeg::Error>, Option<i64>) {
-        let result = self.audio_decoder.decode(packet, &mut frame);
-        if let Ok(true) = result {
-            return (Ok(true), frame.timestamp());
-        }
-        (result, None)
-    }
-
-    fn filter_frame_in(&mut self, frame: &Self::FrameType) -> Result<(), ffmpeg::Error> {
-        self.audio_filter.get("in").unwrap().source().add(&frame)
-    }
-
-    fn filter_frame_output(
-        &mut self,
-        mut frame: &mut Self::FrameType,
-    ) -> Result<(), ffmpeg::Error> {
-        self.audio_filter
-            .get("out")
-            .unwrap()
-            .sink()
-            .frame(&mut frame)
+    fn frames(&mut self) -> (&mut Filtered<Self::Decoder>, &mut bool) {
+        (&mut self.frames, &mut self.inside_pause)
     }
 
-    fn frame_matches(&mut self, audio: &Self::FrameType) -> PauseMatch {
+    fn frame_matches(
+        inside_pause: &mut bool,
+        audio: &frame::Audio,
+        at: Option<Duration>,
+    ) -> PauseMatch {
         let meta = audio.metadata();
-        match (
-            self.inside_pause,
+        metadata::pause_from(
+            inside_pause,
             meta.get("lavfi.silence_start"),
             meta.get("lavfi.silence_duration"),
-            audio.timestamp(),
-        ) {
-            (_, None, None, _) => PauseMatch::None,
-            (_, Some(_), Some(_), _) => PauseMatch::None, // skip super short silences
-            (true, None, Some(_), Some(ts)) => {
-                self.inside_pause = false;
-                PauseMatch::End(to_duration(ts, self.time_base))
-            }
-            (false, Some(_), None, Some(ts)) => {
-                self.inside_pause = true;
-                PauseMatch::Start(to_duration(ts, self.time_base))
-            }
-            combo => {
-                panic!("Unclear combination of audio things: {:?}", combo);
-            }
-        }
+            at,
+        )
     }
 }
 
+/// How often to move a file's progress bar forward.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
 struct BlankDetector {
-    video_stream: usize,
-    time_base: Rational,
-    video_filter: filter::Graph,
-    video_decoder: codec::decoder::Video,
+    frames: Filtered<codec::decoder::Video>,
+    last_progress: Option<Instant>,
     at_end: bool,
     inside_pause: bool,
 }
@@ -412,15 +1345,12 @@ struct BlankDetector {
 impl BlankDetector {
     fn new(
         video_stream: usize,
-        mut video_filter: filter::Graph,
+        video_filter: filter::Graph,
         video_decoder: codec::decoder::Video,
     ) -> Self {
-        video_filter.validate().expect("video filter can't work!");
         Self {
-            video_stream,
-            time_base: video_decoder.time_base(),
-            video_filter,
-            video_decoder,
+            frames: Filtered::new(video_stream, video_decoder, video_filter),
+            last_progress: None,
             at_end: false,
             inside_pause: false,
         }
@@ -428,14 +1358,23 @@ impl BlankDetector {
 }
 
 impl PauseDetector for BlankDetector {
-    type FrameType = ffmpeg::frame::Video;
+    type Decoder = codec::decoder::Video;
 
-    fn update_progress(bar: &ProgressBar, position: u64) {
+    fn update_progress(&mut self, bar: &ProgressBar, position: u64) {
+        // redrawing many bars on every frame slows everything down:
+        if self
+            .last_progress
+            .map(|last| last.elapsed() < PROGRESS_INTERVAL)
+            .unwrap_or(false)
+        {
+            return;
+        }
+        self.last_progress = Some(Instant::now());
         bar.set_position(position);
     }
 
     fn is_applicable_stream(&self, stream: &Stream) -> bool {
-        stream.index() == self.video_stream
+        stream.index() == self.frames.stream()
     }
 
     fn set_at_end(&mut self) {
@@ -446,62 +1385,21 @@ impl PauseDetector for BlankDetector {
         self.at_end
     }
 
-    fn time_base(&self) -> Rational {
-        self.time_base
-    }
-
-    fn empty_frame() -> Self::FrameType {
-        ffmpeg::frame::Video::empty()
+    fn frames(&mut self) -> (&mut Filtered<Self::Decoder>, &mut bool) {
+        (&mut self.frames, &mut self.inside_pause)
     }
 
-    fn decode(
-        &mut self,
-        packet: &Packet,
-        mut frame: &mut Self::FrameType,
-    ) -> (Result<bool, ffmpeg::Error>, Option<i64>) {
-        let result = self.video_decoder.decode(packet, &mut frame);
-        if let Ok(true) = result {
-            return (Ok(true), frame.timestamp());
-        }
-        (result, None)
-    }
-
-    fn filter_frame_in(&mut self, frame: &Self::FrameType) -> Result<(), ffmpeg::Error> {
-        self.video_filter.get("in").unwrap().source().add(&frame)
-    }
-
-    fn filter_frame_output(
-        &mut self,
-        mut frame: &mut Self::FrameType,
-    ) -> Result<(), ffmpeg::Error> {
-        self.video_filter
-            .get("out")
-            .unwrap()
-            .sink()
-            .frame(&mut frame)
-    }
-
-    fn frame_matches(&mut self, video: &Self::FrameType) -> PauseMatch {
+    fn frame_matches(
+        inside_pause: &mut bool,
+        video: &frame::Video,
+        at: Option<Duration>,
+    ) -> PauseMatch {
         let meta = video.metadata();
-        match (
-            self.inside_pause,
+        metadata::pause_from(
+            inside_pause,
             meta.get("lavfi.black_start"),
             meta.get("lavfi.black_end"),
-            video.timestamp(),
-        ) {
-            (_, None, None, _) => PauseMatch::None,
-            (_, Some(_), Some(_), _) => PauseMatch::None, // skip super short silences
-            (true, None, Some(_), Some(ts)) => {
-                self.inside_pause = false;
-                PauseMatch::End(to_duration(ts, self.time_base))
-            }
-            (false, Some(_), None, Some(ts)) => {
-                self.inside_pause = true;
-                PauseMatch::Start(to_duration(ts, self.time_base))
-            }
-            combo => {
-                panic!("Unclear combination of audio things: {:?}", combo);
-            }
-        }
+            at,
+        )
     }
 }
@@ -0,0 +1,260 @@
+//! Just enough of EBML, which Matroska files are made of, to tell
+//! whether a file plays back through ordered chapters or linked
+//! segments.
+use std::io::{self, Read, Seek, SeekFrom};
+
+const SEGMENT: u32 = 0x1853_8067;
+const INFO: u32 = 0x1549_A966;
+const PREV_UID: u32 = 0x3C_B923;
+const NEXT_UID: u32 = 0x3E_B923;
+const CHAPTERS: u32 = 0x1043_A770;
+const EDITION_ENTRY: u32 = 0x45B9;
+const EDITION_FLAG_ORDERED: u32 = 0x45DD;
+const CHAPTER_ATOM: u32 = 0xB6;
+const CHAPTER_SEGMENT_UID: u32 = 0x6E67;
+
+/// Reads a variable-length integer of at most `max_len` bytes: the
+/// leading zero bits of the first byte say how many bytes follow. IDs
+/// keep the length marker, sizes don't. Returns the value and whether
+/// all its bits are set (which makes a size unknown), or `None` at the
+/// end of the file.
+fn vint<R: Read>(r: &mut R, max_len: u32, keep_marker: bool) -> io::Result<Option<(u64, bool)>> {
+    let mut first = [0u8];
+    match r.read_exact(&mut first) {
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        other => other?,
+    }
+    let len = first[0].leading_zeros() + 1;
+    if len > max_len {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not an EBML element",
+        ));
+    }
+    let mask = 0xFFu8.checked_shr(len).unwrap_or(0);
+    let mut all_set = first[0] & mask == mask;
+    let mut value = u64::from(if keep_marker {
+        first[0]
+    } else {
+        first[0] & mask
+    });
+    let mut rest = [0u8; 7];
+    let rest = &mut rest[..len as usize - 1];
+    match r.read_exact(rest) {
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        other => other?,
+    }
+    for byte in rest.iter() {
+        all_set &= *byte == 0xFF;
+        value = value << 8 | u64::from(*byte);
+    }
+    Ok(Some((value, all_set)))
+}
+
+/// Reads an element's ID and the size of its data, if that's known.
+/// Returns `None` at the end of the file, and for an element cut off
+/// by it.
+fn header<R: Read>(r: &mut R) -> io::Result<Option<(u32, Option<u64>)>> {
+    let id = match vint(r, 4, true)? {
+        Some((id, _)) => id as u32,
+        None => return Ok(None),
+    };
+    Ok(vint(r, 8, false)?.map(|(size, unknown)| (id, Some(size).filter(|_| !unknown))))
+}
+
+/// Goes through the elements from where `r` is up to `end`, calling
+/// `visit` with each one's ID and the position its data ends at, and
+/// returns whether any visit returned true. Whatever `visit` doesn't
+/// read is skipped.
+fn any_element<R: Read + Seek>(
+    r: &mut R,
+    end: u64,
+    visit: &mut dyn FnMut(&mut R, u32, u64) -> io::Result<bool>,
+) -> io::Result<bool> {
+    while r.stream_position()? < end {
+        let (id, size) = match header(r)? {
+            Some(header) => header,
+            None => return Ok(false),
+        };
+        let data = r.stream_position()?;
+        // an element of unknown size runs until its parent ends:
+        let element_end = size.map_or(end, |size| data + size);
+        if visit(r, id, element_end)? {
+            return Ok(true);
+        }
+        // if it's one that wasn't visited, its children come next, as
+        // if they were its siblings:
+        if size.is_some() {
+            r.seek(SeekFrom::Start(element_end))?;
+        }
+    }
+    Ok(false)
+}
+
+/// Reads an unsigned integer element's data.
+fn uint<R: Read + Seek>(r: &mut R, end: u64) -> io::Result<u64> {
+    let len = end.saturating_sub(r.stream_position()?).min(8) as usize;
+    let mut bytes = [0u8; 8];
+    r.read_exact(&mut bytes[..len])?;
+    Ok(bytes[..len]
+        .iter()
+        .fold(0, |value, byte| value << 8 | u64::from(*byte)))
+}
+
+/// Whether a chapter (or one of its sub-chapters) plays a part of
+/// another segment.
+fn links_segment<R: Read + Seek>(r: &mut R, end: u64) -> io::Result<bool> {
+    any_element(r, end, &mut |r, id, end| match id {
+        CHAPTER_SEGMENT_UID => Ok(true),
+        CHAPTER_ATOM => links_segment(r, end),
+        _ => Ok(false),
+    })
+}
+
+/// Whether the Matroska file `r` uses ordered chapters or links to
+/// other segments, wherever in the file its chapters and segment info
+/// are.
+pub(crate) fn uses_ordered_chapters<R: Read + Seek>(r: &mut R) -> io::Result<bool> {
+    let file_end = r.seek(SeekFrom::End(0))?;
+    r.seek(SeekFrom::Start(0))?;
+    any_element(r, file_end, &mut |r, id, end| match id {
+        SEGMENT => any_element(r, end, &mut |r, id, end| match id {
+            INFO => any_element(r, end, &mut |_, id, _| Ok(id == PREV_UID || id == NEXT_UID)),
+            CHAPTERS => any_element(r, end, &mut |r, id, end| match id {
+                EDITION_ENTRY => any_element(r, end, &mut |r, id, end| match id {
+                    EDITION_FLAG_ORDERED => Ok(uint(r, end)? != 0),
+                    CHAPTER_ATOM => links_segment(r, end),
+                    _ => Ok(false),
+                }),
+                _ => Ok(false),
+            }),
+            _ => Ok(false),
+        }),
+        _ => Ok(false),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Encodes an element holding `children`, with an 8-byte size.
+    fn element(id: u32, children: &[Vec<u8>]) -> Vec<u8> {
+        let data: Vec<u8> = children.concat();
+        let mut out: Vec<u8> = id
+            .to_be_bytes()
+            .iter()
+            .copied()
+            .skip_while(|b| *b == 0)
+            .collect();
+        out.push(0x01);
+        out.extend(&(data.len() as u64).to_be_bytes()[1..]);
+        out.extend(data);
+        out
+    }
+
+    fn uint_element(id: u32, value: u8) -> Vec<u8> {
+        let mut out = element(id, &[]);
+        out.truncate(out.len() - 8);
+        out.extend(&[0x81, value]);
+        out
+    }
+
+    /// An element whose size is unknown, as in files being recorded.
+    fn unsized_element(id: u32, children: &[Vec<u8>]) -> Vec<u8> {
+        let mut out: Vec<u8> = id
+            .to_be_bytes()
+            .iter()
+            .copied()
+            .skip_while(|b| *b == 0)
+            .collect();
+        out.push(0xFF);
+        out.extend(children.concat());
+        out
+    }
+
+    const EBML: u32 = 0x1A45_DFA3;
+    const CLUSTER: u32 = 0x1F43_B675;
+    const SIMPLE_BLOCK: u32 = 0xA3;
+    const ATTACHMENTS: u32 = 0x1941_A469;
+
+    fn ordered(file: Vec<u8>) -> bool {
+        uses_ordered_chapters(&mut Cursor::new(file)).unwrap()
+    }
+
+    fn edition(children: &[Vec<u8>]) -> Vec<u8> {
+        element(CHAPTERS, &[element(EDITION_ENTRY, children)])
+    }
+
+    #[test]
+    fn plain_chapters_arent_ordered() {
+        let file = [
+            element(EBML, &[]),
+            element(
+                SEGMENT,
+                &[
+                    element(INFO, &[]),
+                    edition(&[
+                        uint_element(EDITION_FLAG_ORDERED, 0),
+                        element(CHAPTER_ATOM, &[]),
+                    ]),
+                    element(CLUSTER, &[element(SIMPLE_BLOCK, &[])]),
+                ],
+            ),
+        ];
+        assert!(!ordered(file.concat()));
+    }
+
+    #[test]
+    fn finds_ordered_editions() {
+        let chapters = edition(&[uint_element(EDITION_FLAG_ORDERED, 1)]);
+        let file = [element(EBML, &[]), element(SEGMENT, &[chapters])];
+        assert!(ordered(file.concat()));
+    }
+
+    #[test]
+    fn finds_chapters_linking_other_segments() {
+        let atom = element(
+            CHAPTER_ATOM,
+            &[element(CHAPTER_ATOM, &[element(CHAPTER_SEGMENT_UID, &[])])],
+        );
+        let file = [element(EBML, &[]), element(SEGMENT, &[edition(&[atom])])];
+        assert!(ordered(file.concat()));
+    }
+
+    #[test]
+    fn finds_linked_segments() {
+        let info = element(INFO, &[element(NEXT_UID, &[])]);
+        let file = [element(EBML, &[]), element(SEGMENT, &[info])];
+        assert!(ordered(file.concat()));
+    }
+
+    #[test]
+    fn finds_chapters_after_the_clusters() {
+        let segment = unsized_element(
+            SEGMENT,
+            &[
+                unsized_element(CLUSTER, &[element(SIMPLE_BLOCK, &[])]),
+                edition(&[uint_element(EDITION_FLAG_ORDERED, 1)]),
+            ],
+        );
+        assert!(ordered([element(EBML, &[]), segment].concat()));
+    }
+
+    #[test]
+    fn ignores_ids_in_other_data() {
+        // the IDs it looks for, in an attachment (e.g. a font):
+        let font = vec![0x45, 0xDD, 0x81, 0x01, 0x3C, 0xB9, 0x23, 0x6E, 0x67, 0x90];
+        let attachments = element(ATTACHMENTS, &[font]);
+        let file = [element(EBML, &[]), element(SEGMENT, &[attachments])];
+        assert!(!ordered(file.concat()));
+    }
+
+    #[test]
+    fn stops_at_a_cut_off_file() {
+        let mut file = [element(EBML, &[]), element(SEGMENT, &[element(INFO, &[])])].concat();
+        file.truncate(file.len() - 3);
+        assert!(!ordered(file));
+    }
+}
@@ -0,0 +1,71 @@
+//! Edit decision lists, as written by comskip, MPlayer and various
+//! intro-skipping tools: one `START END ACTION` line per segment, with
+//! offsets in seconds.
+use crate::intro::ForcedIntro;
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct Segment {
+    pub(crate) span: Range<Duration>,
+    pub(crate) action: u32,
+}
+
+/// Returns the path of the EDL for `path`: `episode.edl` for
+/// `episode.mkv`.
+pub(crate) fn sidecar_path(path: &Path) -> PathBuf {
+    path.with_extension("edl")
+}
+
+fn parse_secs(value: &str) -> Result<Duration> {
+    let secs: f64 = value.parse().context(format!("invalid time {:?}", value))?;
+    Ok(Duration::try_from_secs_f64(secs)?)
+}
+
+pub(crate) fn parse(text: &str) -> Result<Vec<Segment>> {
+    let mut segments = vec![];
+    for line in text.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let (start, end, action) = match fields.as_slice() {
+            [] => continue,
+            [start, end] => (start, end, "0"),
+            [start, end, action] => (start, end, *action),
+            _ => bail!("unexpected line {:?}", line),
+        };
+        let span = parse_secs(start)?..parse_secs(end)?;
+        if span.end <= span.start {
+            bail!("segment {:?} ends before it starts", line);
+        }
+        let action = action
+            .parse()
+            .context(format!("invalid action {:?}", action))?;
+        segments.push(Segment { span, action });
+    }
+    segments.sort_by_key(|s| s.span.start);
+    Ok(segments)
+}
+
+/// Reads the segments of the EDL `path`, if it exists.
+pub(crate) fn load(path: &Path) -> Result<Option<Vec<Segment>>> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).context(format!("reading {:?}", path)),
+    };
+    parse(&text)
+        .context(format!("parsing {:?}", path))
+        .map(Some)
+}
+
+/// Finds the intro in the EDL next to `path`: intro-skipping tools
+/// write the intro as its only (or first) segment.
+pub(crate) fn intro_for(path: &Path) -> Result<Option<ForcedIntro>> {
+    let segments = load(&sidecar_path(path))?.unwrap_or_default();
+    Ok(segments.first().map(|segment| ForcedIntro {
+        start: segment.span.start,
+        end: segment.span.end,
+    }))
+}
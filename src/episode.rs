@@ -0,0 +1,142 @@
+//! Work out which episode a file is from its name.
+
+/// Finds the season and episode number in a file name, written either
+/// as `S01E02` (in any case) or as `1x02`.
+pub(crate) fn season_and_episode(name: &str) -> Option<(u32, u32)> {
+    find_episode(name).map(|(_, _, season, episode)| (season, episode))
+}
+
+/// Like `season_and_episode`, but also returns the indices of the
+/// characters where the episode identifier starts and ends.
+fn find_episode(name: &str) -> Option<(usize, usize, u32, u32)> {
+    let chars: Vec<char> = name.chars().collect();
+    let digits_at = |from: usize| -> (Option<u32>, usize) {
+        let len = chars[from..]
+            .iter()
+            .take_while(|c| c.is_ascii_digit())
+            .count();
+        let number: String = chars[from..from + len].iter().collect();
+        (number.parse().ok(), from + len)
+    };
+    for i in 0..chars.len() {
+        let preceded_by_alnum = i > 0 && chars[i - 1].is_alphanumeric();
+        if chars[i].eq_ignore_ascii_case(&'s') && !preceded_by_alnum {
+            if let (Some(season), end) = digits_at(i + 1) {
+                if end < chars.len() && chars[end].eq_ignore_ascii_case(&'e') {
+                    if let (Some(episode), after) = digits_at(end + 1) {
+                        return Some((i, after, season, episode));
+                    }
+                }
+            }
+        }
+        if chars[i].is_ascii_digit() && !preceded_by_alnum {
+            // short numbers only, so 1920x1080 isn't an episode:
+            if let (Some(season), end) = digits_at(i) {
+                if end - i <= 2 && end < chars.len() && chars[end].eq_ignore_ascii_case(&'x') {
+                    if let (Some(episode), after) = digits_at(end + 1) {
+                        if (2..=3).contains(&(after - end - 1)) {
+                            return Some((i, after, season, episode));
+                        }
+                    }
+                }
+            }
+        }
+    }
+    None
+}
+
+/// How many episodes the file holds, judging by its name: 2 for
+/// `S01E01E02` or `S01E01-E02`, 1 for anything else.
+pub(crate) fn episode_count(path: &std::path::Path) -> usize {
+    let name = match path.file_name() {
+        Some(name) => name.to_string_lossy(),
+        None => return 1,
+    };
+    let rest: Vec<char> = match find_episode(&name) {
+        Some((_, end, _, _)) => name.chars().skip(end).collect(),
+        None => return 1,
+    };
+    let mut count = 1;
+    let mut i = 0;
+    loop {
+        if rest.get(i) == Some(&'-') {
+            i += 1;
+        }
+        if !rest
+            .get(i)
+            .map(|c| c.eq_ignore_ascii_case(&'e'))
+            .unwrap_or(false)
+        {
+            break;
+        }
+        let digits = rest[i + 1..]
+            .iter()
+            .take_while(|c| c.is_ascii_digit())
+            .count();
+        if digits == 0 {
+            break;
+        }
+        count += 1;
+        i += 1 + digits;
+    }
+    count
+}
+
+/// Returns the episode as `S01E02`, if the file name says which it is.
+pub(crate) fn label(path: &std::path::Path) -> Option<String> {
+    let name = path.file_name()?.to_string_lossy();
+    let (season, episode) = season_and_episode(&name)?;
+    Some(format!("S{:02}E{:02}", season, episode))
+}
+
+/// Guesses which series and season a file belongs to: the series is
+/// named by what comes before the episode identifier, or by the
+/// directory the file is in if nothing does (skipping `Season N`
+/// directories).
+pub(crate) fn series_and_season(path: &std::path::Path) -> Option<(String, u32)> {
+    let name = path.file_name()?.to_string_lossy();
+    let (at, _, season, _) = find_episode(&name)?;
+    let prefix: String = name
+        .chars()
+        .take(at)
+        .map(|c| if c == '.' || c == '_' { ' ' } else { c })
+        .collect();
+    let mut series = prefix
+        .trim_end_matches(|c: char| c.is_whitespace() || "-[(".contains(c))
+        .to_string();
+    if series.is_empty() {
+        series = path
+            .ancestors()
+            .skip(1)
+            .filter_map(|dir| dir.file_name())
+            .map(|dir| dir.to_string_lossy().into_owned())
+            .find(|dir| !dir.to_lowercase().starts_with("season"))?;
+    }
+    Some((series, season))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn finds_seasons_and_episodes() {
+        assert_eq!(season_and_episode("Show.S01E02.mkv"), Some((1, 2)));
+        assert_eq!(season_and_episode("show s3e14 - title.mkv"), Some((3, 14)));
+        assert_eq!(season_and_episode("Show - 2x05.mkv"), Some((2, 5)));
+        assert_eq!(season_and_episode("Show 1920x1080.mkv"), None);
+        assert_eq!(season_and_episode("Boss01E02.mkv"), None);
+        assert_eq!(season_and_episode("Movie (2019).mkv"), None);
+    }
+
+    #[test]
+    fn counts_episodes_in_a_file() {
+        let count = |name: &str| episode_count(Path::new(name));
+        assert_eq!(count("/tv/Show S01E01.mkv"), 1);
+        assert_eq!(count("/tv/Show S01E01E02.mkv"), 2);
+        assert_eq!(count("/tv/Show S01E01-E02-E03.mkv"), 3);
+        assert_eq!(count("/tv/Show S01E01-Extended.mkv"), 1);
+        assert_eq!(count("/tv/Movie.mkv"), 1);
+    }
+}
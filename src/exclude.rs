@@ -0,0 +1,58 @@
+//! Time ranges of a file that never contain breaks worth marking.
+use anyhow::{bail, Context};
+use serde::de::{self, Deserializer};
+use serde::Deserialize;
+use std::ops::Range;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Parts of a file in which candidates are ignored, e.g. distributor
+/// logos at the start.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Exclusions(Vec<Range<Duration>>);
+
+impl Exclusions {
+    pub(crate) fn contains(&self, offset: Duration) -> bool {
+        self.0.iter().any(|range| range.contains(&offset))
+    }
+
+    /// Adds the ranges in `other` to these.
+    pub(crate) fn extend(&mut self, other: &Exclusions) {
+        self.0.extend(other.0.iter().cloned());
+    }
+}
+
+impl FromStr for Exclusions {
+    type Err = anyhow::Error;
+
+    /// Parses comma-separated `START..END` ranges, either end of which
+    /// may be left out, e.g. `0s..20s,41m..`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut ranges = vec![];
+        for range in s.split(',').map(str::trim).filter(|r| !r.is_empty()) {
+            let (start, end) = range
+                .split_once("..")
+                .with_context(|| format!("expected START..END in {:?}", range))?;
+            let start = match start.trim() {
+                "" => Duration::from_secs(0),
+                start => humantime::parse_duration(start).context("parsing START")?,
+            };
+            let end = match end.trim() {
+                "" => Duration::MAX,
+                end => humantime::parse_duration(end).context("parsing END")?,
+            };
+            if end <= start {
+                bail!("{:?} ends before it starts", range);
+            }
+            ranges.push(start..end);
+        }
+        Ok(Exclusions(ranges))
+    }
+}
+
+impl<'de> Deserialize<'de> for Exclusions {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
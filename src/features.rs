@@ -0,0 +1,138 @@
+//! Per-frame measurements of loudness, brightness and scene changes,
+//! for calibrating detection and for exporting as training data.
+use crate::detect::{
+    audio_decoder, audio_filter, video_decoder, video_filter, AudioPreference, VideoSettings,
+};
+use crate::frames::Filtered;
+use crate::metadata;
+use crate::util::{self, to_duration};
+use anyhow::{Context, Result};
+use serde_derive::Serialize;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+/// Height to scale video down to before measuring; none of the
+/// measurements need the detail.
+const MEASURE_HEIGHT: u32 = 360;
+
+#[derive(Debug, Serialize)]
+pub(crate) struct AudioFrame {
+    /// Offset into the file, in seconds, as players show it.
+    pub(crate) time: f64,
+
+    /// RMS level in dBFS; missing for digital silence.
+    pub(crate) rms_db: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct VideoFrame {
+    /// Offset into the file, in seconds, as players show it.
+    pub(crate) time: f64,
+
+    /// Average luma, as a fraction of the luma range.
+    pub(crate) luma: Option<f64>,
+
+    /// How different the frame is from the previous one, from 0 to 1.
+    pub(crate) scene: Option<f64>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Features {
+    pub(crate) audio: Vec<AudioFrame>,
+    pub(crate) video: Vec<VideoFrame>,
+}
+
+/// Decodes the first `window` of `path` and measures every frame of its
+/// video and of the `audio` track in it.
+pub(crate) fn measure(path: &Path, window: Duration, audio: &AudioPreference) -> Result<Features> {
+    let mut ictx =
+        ffmpeg::format::input(&path).context(format!("opening input file {:?}", &path))?;
+    let origin = util::stream_origin(&ictx);
+    let (audio_stream, audio_decoder) = audio_decoder(&ictx, audio)?;
+    let audio_graph = audio_filter(&audio_decoder, "astats=metadata=1:reset=1")?;
+    let mut audio_frames = Filtered::new(audio_stream, audio_decoder, audio_graph);
+    let (video_stream, video_decoder) = video_decoder(&ictx, &VideoSettings::with_threads(1))?;
+    let video_graph = video_filter(
+        &video_decoder,
+        Some(MEASURE_HEIGHT),
+        "signalstats,select=gte(scene\\,0)",
+    )?;
+    let mut video_frames = Filtered::new(video_stream, video_decoder, video_graph);
+
+    let mut features = Features::default();
+    let mut audio_done = false;
+    let mut video_done = false;
+    for (stream, mut packet) in ictx.packets() {
+        if stream.index() == audio_frames.stream() && !audio_done {
+            let time_base = audio_frames.time_base();
+            audio_frames.feed(stream.time_base(), &mut packet, |frame| {
+                let at = match frame.timestamp() {
+                    Some(ts) => to_duration(ts, time_base),
+                    None => return,
+                };
+                audio_done = at > window;
+                features.audio.push(AudioFrame {
+                    time: util::displayed_offset(at, origin).as_secs_f64(),
+                    rms_db: frame
+                        .metadata()
+                        .get("lavfi.astats.Overall.RMS_level")
+                        .and_then(metadata::parse_number),
+                });
+            })?;
+        } else if stream.index() == video_frames.stream() && !video_done {
+            let time_base = video_frames.time_base();
+            video_frames.feed(stream.time_base(), &mut packet, |frame| {
+                let at = match frame.timestamp() {
+                    Some(ts) => to_duration(ts, time_base),
+                    None => return,
+                };
+                video_done = at > window;
+                let metadata = frame.metadata();
+                let number = |key| metadata.get(key).and_then(metadata::parse_number);
+                let depth = number("lavfi.signalstats.YBITDEPTH").unwrap_or(8.0);
+                features.video.push(VideoFrame {
+                    time: util::displayed_offset(at, origin).as_secs_f64(),
+                    luma: number("lavfi.signalstats.YAVG")
+                        .map(|avg| avg / (2f64.powf(depth) - 1.0)),
+                    scene: number("lavfi.scene_score"),
+                });
+            })?;
+        }
+        if audio_done && video_done {
+            break;
+        }
+    }
+    Ok(features)
+}
+
+/// Measures the first `window` of `path` and writes the audio and
+/// video frames' features to `NAME.HASH.audio.csv` and
+/// `NAME.HASH.video.csv` in `dir` (see `util::output_name`).
+pub(crate) fn dump(
+    path: &Path,
+    window: Duration,
+    audio: &AudioPreference,
+    dir: &Path,
+) -> Result<()> {
+    let features = measure(path, window, audio)?;
+    fs::create_dir_all(dir).context(format!("creating {:?}", dir))?;
+    let name = util::output_name(path);
+
+    let audio_path = dir.join(format!("{}.audio.csv", name));
+    let mut audio =
+        csv::Writer::from_path(&audio_path).context(format!("creating {:?}", &audio_path))?;
+    for frame in &features.audio {
+        audio.serialize(frame)?;
+    }
+    audio.flush()?;
+
+    let video_path = dir.join(format!("{}.video.csv", name));
+    let mut video =
+        csv::Writer::from_path(&video_path).context(format!("creating {:?}", &video_path))?;
+    for frame in &features.video {
+        video.serialize(frame)?;
+    }
+    video.flush()?;
+    Ok(())
+}
@@ -0,0 +1,183 @@
+//! A scanning backend for where the linked ffmpeg libraries can't do
+//! the job: runs the ffmpeg command with the same detection filters,
+//! and reads the pauses from what they log.
+use crate::detect::{self, StreamRoute, Thresholds};
+use crate::metadata::{self, PauseMatch};
+use anyhow::{bail, Context, Result};
+use indicatif::ProgressBar;
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader};
+use std::ops::Range;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+/// How many of ffmpeg's last lines of output to show when it fails.
+const ERROR_LINES: usize = 5;
+
+/// Returns the offset logged right after `key` in `line`, if any.
+fn offset_after(line: &str, key: &str) -> Option<Duration> {
+    let rest = &line[line.find(key)? + key.len()..];
+    let value = rest
+        .trim_start()
+        .split(|c: char| c.is_whitespace() || c == '|')
+        .next()?;
+    let secs = metadata::parse_number(value)?;
+    // silences at the very start get logged slightly before it:
+    Duration::try_from_secs_f64(secs.max(0.0)).ok()
+}
+
+/// Parses a line silencedetect or blackdetect logged into the pauses it
+/// reports, `from` the start of the scan. silencedetect logs the start
+/// and end of a silence as they happen; blackdetect logs both once the
+/// black is over. Other lines (like the file's metadata, which ffmpeg
+/// shows too) are never taken for pauses.
+fn pauses_in(line: &str, from: Duration) -> Vec<(StreamRoute, PauseMatch)> {
+    let at = |key| offset_after(line, key).map(|offset| from + offset);
+    if line.starts_with("[silencedetect @") {
+        if let Some(start) = at("silence_start:") {
+            return vec![(StreamRoute::Audio, PauseMatch::Start(start))];
+        }
+        if let Some(end) = at("silence_end:") {
+            return vec![(StreamRoute::Audio, PauseMatch::End(end))];
+        }
+    } else if line.starts_with("[blackdetect @") {
+        if let (Some(start), Some(end)) = (at("black_start:"), at("black_end:")) {
+            return vec![
+                (StreamRoute::Video, PauseMatch::Start(start)),
+                (StreamRoute::Video, PauseMatch::End(end)),
+            ];
+        }
+    }
+    vec![]
+}
+
+fn secs(offset: Duration) -> String {
+    format!("{:.3}", offset.as_secs_f64())
+}
+
+/// Scans the `span` of `path` by running `program`, returning the
+/// candidates found there, as `Markers` would. `streams` are the
+/// indices of the audio and video stream to scan (see
+/// `detect::scanned_streams`).
+pub(crate) fn markers(
+    program: &Path,
+    path: &Path,
+    span: Range<Duration>,
+    streams: (usize, usize),
+    thresholds: &Thresholds,
+    bar: &ProgressBar,
+) -> Result<detect::Markers<'static>> {
+    let (audio, video) = streams;
+    let mut child = Command::new(program)
+        .args(["-hide_banner", "-nostdin", "-nostats"])
+        .arg("-ss")
+        .arg(secs(span.start))
+        .arg("-t")
+        .arg(secs(span.end.saturating_sub(span.start)))
+        .arg("-i")
+        .arg(path)
+        .arg("-map")
+        .arg(format!("0:{}", audio))
+        .arg("-map")
+        .arg(format!("0:{}", video))
+        .arg("-af")
+        .arg(thresholds.silence_filter())
+        .arg("-vf")
+        .arg(thresholds.black_filter())
+        .args(["-f", "null", "-"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("running {:?}", program))?;
+    let stderr = child.stderr.take().context("reading ffmpeg's output")?;
+    let mut pauses = vec![];
+    let mut last_lines = VecDeque::new();
+    // metadata in the file may not be UTF-8:
+    for line in BufReader::new(stderr).split(b'\n') {
+        let line = line.context("reading ffmpeg's output")?;
+        let line = String::from_utf8_lossy(&line);
+        let found = pauses_in(&line, span.start);
+        if found.is_empty() {
+            last_lines.push_back(line.trim().to_string());
+            if last_lines.len() > ERROR_LINES {
+                last_lines.pop_front();
+            }
+        }
+        if let Some(at) = found.last().and_then(|(_, pause)| pause.at()) {
+            bar.set_position(at.as_millis() as u64);
+        }
+        pauses.extend(found);
+    }
+    let status = child
+        .wait()
+        .with_context(|| format!("running {:?}", program))?;
+    if !status.success() {
+        bail!(
+            "{:?} failed ({}): {}",
+            program,
+            status,
+            Vec::from(last_lines).join(" / ")
+        );
+    }
+    // blackdetect's pauses come in late, and the sort is stable, so
+    // each detector's own stay in order:
+    pauses.sort_by_key(|(_, pause)| pause.at());
+    let (candidates, video_only) = detect::fuse_pauses(pauses, bar);
+    Ok(detect::Markers::Cli {
+        candidates: candidates.into_iter(),
+        video_only,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_pauses_from_the_log() {
+        let from = Duration::from_secs(100);
+        let ms = |ms| from + Duration::from_millis(ms);
+        assert_eq!(
+            pauses_in("[silencedetect @ 0x55d0] silence_start: 12.5", from),
+            vec![(StreamRoute::Audio, PauseMatch::Start(ms(12_500)))]
+        );
+        assert_eq!(
+            pauses_in(
+                "[silencedetect @ 0x55d0] silence_end: 13.75 | silence_duration: 1.25",
+                from
+            ),
+            vec![(StreamRoute::Audio, PauseMatch::End(ms(13_750)))]
+        );
+        assert_eq!(
+            pauses_in(
+                "[blackdetect @ 0x55d0] black_start:12.4 black_end:13.8 black_duration:1.4",
+                from
+            ),
+            vec![
+                (StreamRoute::Video, PauseMatch::Start(ms(12_400))),
+                (StreamRoute::Video, PauseMatch::End(ms(13_800))),
+            ]
+        );
+        // logged just before the start of the file:
+        assert_eq!(
+            pauses_in("[silencedetect @ 0x55d0] silence_start: -0.02", from),
+            vec![(StreamRoute::Audio, PauseMatch::Start(from))]
+        );
+    }
+
+    #[test]
+    fn ignores_other_lines() {
+        let from = Duration::from_secs(0);
+        for line in &[
+            "frame=  250 fps=0.0 q=-0.0 size=N/A time=00:00:10.00",
+            "[silencedetect @ 0x55d0] silence_start: NaN",
+            "[blackdetect @ 0x55d0] black_start:12.4",
+            "    title           : silence_start: 5",
+            "  comment         : [blackdetect @ 0] black_start:1 black_end:2",
+        ] {
+            assert_eq!(pauses_in(line, from), vec![], "{:?}", line);
+        }
+    }
+}
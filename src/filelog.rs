@@ -0,0 +1,59 @@
+//! Per-file logs of what detection and writing did, to reconstruct
+//! later why a file got the chapters it did.
+use crate::{service, util};
+use anyhow::{Context, Result};
+use indicatif::ProgressBar;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// A log file for one media file; does nothing if logging is off.
+pub(crate) struct FileLog {
+    file: Option<Mutex<BufWriter<File>>>,
+}
+
+impl FileLog {
+    pub(crate) fn disabled() -> Self {
+        FileLog { file: None }
+    }
+
+    /// Starts `NAME.HASH.log` in `dir` for the media file `path` (see
+    /// `util::output_name`), replacing the log of any earlier run.
+    pub(crate) fn create(dir: &Path, path: &Path) -> Result<Self> {
+        std::fs::create_dir_all(dir).context(format!("creating {:?}", dir))?;
+        let log_path = dir.join(format!("{}.log", util::output_name(path)));
+        let file = File::create(&log_path).context(format!("creating {:?}", &log_path))?;
+        let log = FileLog {
+            file: Some(Mutex::new(BufWriter::new(file))),
+        };
+        log.record(format!("processing {:?}", path));
+        Ok(log)
+    }
+
+    /// Adds a timestamped line to the log.
+    pub(crate) fn record(&self, message: impl AsRef<str>) {
+        if let Some(file) = &self.file {
+            let mut file = file.lock().unwrap_or_else(|e| e.into_inner());
+            // a broken log shouldn't fail the file:
+            let _ = writeln!(
+                file,
+                "{} {}",
+                humantime::format_rfc3339_seconds(SystemTime::now()),
+                message.as_ref()
+            );
+        }
+    }
+
+    /// Prints `message` above the progress bars (or just to stderr, if
+    /// they're hidden), and records it.
+    pub(crate) fn say(&self, bar: &ProgressBar, message: String) {
+        self.record(&message);
+        if bar.is_hidden() {
+            service::info(message);
+        } else {
+            bar.println(message);
+        }
+    }
+}
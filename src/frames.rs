@@ -0,0 +1,120 @@
+//! Decoding the packets of one stream and running the frames through a
+//! filter graph, as every scan does.
+use anyhow::{Context, Result};
+use ffmpeg::{codec, filter, frame, Packet, Rational};
+use std::ops::DerefMut;
+
+/// A decoder of audio or video, and the frames it decodes into.
+pub(crate) trait Decoder {
+    type Frame: DerefMut<Target = frame::Frame>;
+
+    fn empty_frame() -> Self::Frame;
+
+    fn time_base(&self) -> Rational;
+
+    fn decode(&mut self, packet: &Packet, frame: &mut Self::Frame) -> Result<bool, ffmpeg::Error>;
+}
+
+impl Decoder for codec::decoder::Audio {
+    type Frame = frame::Audio;
+
+    fn empty_frame() -> Self::Frame {
+        frame::Audio::empty()
+    }
+
+    fn time_base(&self) -> Rational {
+        codec::decoder::Decoder::time_base(self)
+    }
+
+    fn decode(&mut self, packet: &Packet, frame: &mut Self::Frame) -> Result<bool, ffmpeg::Error> {
+        codec::decoder::Audio::decode(self, packet, frame)
+    }
+}
+
+impl Decoder for codec::decoder::Video {
+    type Frame = frame::Video;
+
+    fn empty_frame() -> Self::Frame {
+        frame::Video::empty()
+    }
+
+    fn time_base(&self) -> Rational {
+        codec::decoder::Decoder::time_base(self)
+    }
+
+    fn decode(&mut self, packet: &Packet, frame: &mut Self::Frame) -> Result<bool, ffmpeg::Error> {
+        codec::decoder::Video::decode(self, packet, frame)
+    }
+}
+
+/// The frames of one stream, decoded and then filtered by a graph with
+/// an "in" source and an "out" sink (as `detect::audio_filter` and
+/// `detect::video_filter` build them).
+pub(crate) struct Filtered<D: Decoder> {
+    stream: usize,
+    decoder: D,
+    graph: filter::Graph,
+
+    /// Reused for every frame, so frames aren't reallocated for every
+    /// packet.
+    frame: D::Frame,
+}
+
+impl<D: Decoder> Filtered<D> {
+    pub(crate) fn new(stream: usize, decoder: D, graph: filter::Graph) -> Self {
+        Filtered {
+            stream,
+            decoder,
+            graph,
+            frame: D::empty_frame(),
+        }
+    }
+
+    /// The index of the stream whose packets this takes.
+    pub(crate) fn stream(&self) -> usize {
+        self.stream
+    }
+
+    /// The time base of the frames' timestamps.
+    pub(crate) fn time_base(&self) -> Rational {
+        self.decoder.time_base()
+    }
+
+    /// Decodes `packet` (of a stream in `stream_time_base`) and calls
+    /// `each` with every frame the graph puts out for it. Returns the
+    /// timestamp of the frame decoded from the packet, if any. Packets
+    /// that don't decode (e.g. corrupt ones) are skipped; the graph
+    /// failing is an error.
+    pub(crate) fn feed(
+        &mut self,
+        stream_time_base: Rational,
+        packet: &mut Packet,
+        mut each: impl FnMut(&D::Frame),
+    ) -> Result<Option<i64>> {
+        packet.rescale_ts(stream_time_base, self.decoder.time_base());
+        match self.decoder.decode(packet, &mut self.frame) {
+            Ok(true) => {}
+            _ => return Ok(None),
+        }
+        let timestamp = self.frame.timestamp();
+        self.graph
+            .get("in")
+            .context("the filter graph has no \"in\" source")?
+            .source()
+            .add(&self.frame)
+            .context("filtering a frame")?;
+        loop {
+            let filtered = self
+                .graph
+                .get("out")
+                .context("the filter graph has no \"out\" sink")?
+                .sink()
+                .frame(&mut self.frame);
+            if filtered.is_err() {
+                break;
+            }
+            each(&self.frame);
+        }
+        Ok(timestamp)
+    }
+}
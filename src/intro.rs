@@ -0,0 +1,211 @@
+//! Recognize which of the detected breaks delimit the intro
+use crate::offset;
+use anyhow::{bail, Context};
+use serde_derive::Deserialize;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// How long intros typically run.
+pub(crate) const DEFAULT_INTRO_LENGTH: RangeInclusive<Duration> =
+    Duration::from_secs(20)..=Duration::from_secs(150);
+
+/// How long anime opening and ending themes typically run.
+const ANIME_THEME_LENGTH: RangeInclusive<Duration> =
+    Duration::from_secs(85)..=Duration::from_secs(95);
+
+/// How far from the end of an anime episode to look for the ending
+/// theme (which is usually followed by a short preview).
+pub(crate) const ANIME_ENDING_WINDOW: Duration = Duration::from_secs(5 * 60);
+
+/// Predefined detection settings for a kind of show.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Profile {
+    Anime,
+}
+
+/// How long previews of the next episode, after the ending theme,
+/// typically run.
+const ANIME_PREVIEW_LENGTH: RangeInclusive<Duration> =
+    Duration::from_secs(10)..=Duration::from_secs(60);
+
+impl Profile {
+    /// How long the themes run.
+    pub(crate) fn theme_length(&self) -> RangeInclusive<Duration> {
+        match self {
+            Profile::Anime => ANIME_THEME_LENGTH,
+        }
+    }
+
+    /// How long a preview at the very end of the file runs, if this
+    /// profile looks for one.
+    pub(crate) fn preview_length(&self) -> Option<RangeInclusive<Duration>> {
+        match self {
+            Profile::Anime => Some(ANIME_PREVIEW_LENGTH),
+        }
+    }
+}
+
+impl FromStr for Profile {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "anime" => Ok(Profile::Anime),
+            _ => bail!("unknown profile {:?} (known: anime)", s),
+        }
+    }
+}
+
+/// Returns the start and end of the first pair of consecutive breaks
+/// that are spaced like an intro of a length within `length`.
+pub(crate) fn find_intro(
+    breaks: &[Duration],
+    length: &RangeInclusive<Duration>,
+) -> Option<(Duration, Duration)> {
+    breaks
+        .windows(2)
+        .map(|pair| (pair[0], pair[1]))
+        .find(|(start, end)| length.contains(&(*end - *start)))
+}
+
+/// What the intros of a show looked like so far: where they started
+/// and how long they ran, give or take a spread.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Expected {
+    pub(crate) start: Duration,
+    pub(crate) start_spread: Duration,
+    pub(crate) length: Duration,
+    pub(crate) length_spread: Duration,
+}
+
+/// Intros deviating more than this many spreads from what's expected
+/// in either start or length aren't considered.
+const MAX_DEVIATION: f64 = 3.0;
+
+/// Returns the start and end of the pair of breaks that is closest to
+/// an `expected` intro, if any of them is close enough.
+pub(crate) fn find_expected_intro(
+    breaks: &[Duration],
+    expected: &Expected,
+) -> Option<(Duration, Duration)> {
+    let deviation = |value: Duration, mean: Duration, spread: Duration| {
+        (value.as_secs_f64() - mean.as_secs_f64()).abs() / spread.as_secs_f64().max(0.001)
+    };
+    let mut best: Option<(f64, (Duration, Duration))> = None;
+    for (i, start) in breaks.iter().enumerate() {
+        for end in &breaks[i + 1..] {
+            let from_start = deviation(*start, expected.start, expected.start_spread);
+            let from_length = deviation(*end - *start, expected.length, expected.length_spread);
+            if from_start > MAX_DEVIATION || from_length > MAX_DEVIATION {
+                continue;
+            }
+            let score = from_start + from_length;
+            if best.map(|(best, _)| score < best).unwrap_or(true) {
+                best = Some((score, (*start, *end)));
+            }
+        }
+    }
+    best.map(|(_, intro)| intro)
+}
+
+/// Returns the first of the `breaks` (not before `after`) that leaves
+/// a stretch of `length` until the end of the file, which is where a
+/// preview of the next episode would start.
+pub(crate) fn find_preview(
+    breaks: &[Duration],
+    after: Option<Duration>,
+    file_duration: Duration,
+    length: &RangeInclusive<Duration>,
+) -> Option<Duration> {
+    breaks
+        .iter()
+        .copied()
+        .filter(|b| after.map(|after| *b >= after).unwrap_or(true) && *b < file_duration)
+        .find(|b| length.contains(&(file_duration - *b)))
+}
+
+/// How far into a file to scan when its duration isn't known.
+const FALLBACK_SCAN_LENGTH: Duration = Duration::from_secs(10 * 60);
+
+/// Kinds of content, guessed from the running time, and how much of
+/// each to scan: (longest running time, fraction to scan, cap).
+const SCAN_PROFILES: &[(Duration, f64, Duration)] = &[
+    // sitcoms and other short episodes:
+    (
+        Duration::from_secs(35 * 60),
+        0.25,
+        Duration::from_secs(8 * 60),
+    ),
+    // dramas:
+    (
+        Duration::from_secs(75 * 60),
+        0.2,
+        Duration::from_secs(12 * 60),
+    ),
+    // movies:
+    (Duration::MAX, 0.1, Duration::from_secs(15 * 60)),
+];
+
+/// How far into the beginning of a file to look for breaks.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ScanLength {
+    Fixed(Duration),
+
+    /// Depending on the file's duration.
+    Auto,
+}
+
+impl ScanLength {
+    /// Returns how far to scan into a file of the given duration.
+    pub(crate) fn for_duration(&self, file_duration: Option<Duration>) -> Duration {
+        match (self, file_duration) {
+            (ScanLength::Fixed(until), _) => *until,
+            (ScanLength::Auto, None) => FALLBACK_SCAN_LENGTH,
+            (ScanLength::Auto, Some(duration)) => {
+                let (_, fraction, cap) = SCAN_PROFILES
+                    .iter()
+                    .find(|(longest, _, _)| duration <= *longest)
+                    .unwrap_or(&SCAN_PROFILES[SCAN_PROFILES.len() - 1]);
+                duration.mul_f64(*fraction).min(*cap)
+            }
+        }
+    }
+}
+
+impl FromStr for ScanLength {
+    type Err = humantime::DurationError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "auto" {
+            return Ok(ScanLength::Auto);
+        }
+        humantime::parse_duration(s).map(ScanLength::Fixed)
+    }
+}
+
+/// Where the intro is, when it's known instead of detected.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ForcedIntro {
+    #[serde(deserialize_with = "crate::sidecar::duration")]
+    pub(crate) start: Duration,
+
+    #[serde(deserialize_with = "crate::sidecar::duration")]
+    pub(crate) end: Duration,
+}
+
+impl FromStr for ForcedIntro {
+    type Err = anyhow::Error;
+
+    /// Parses `START..END`, e.g. `1m30s..2m45s` or `1:30..2:45`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = s.split_once("..").context("expected START..END")?;
+        let start = offset::parse_offset(start).context("parsing START")?;
+        let end = offset::parse_offset(end).context("parsing END")?;
+        if end <= start {
+            bail!("intro end is not after its start");
+        }
+        Ok(ForcedIntro { start, end })
+    }
+}
@@ -0,0 +1,239 @@
+//! What the intros of each show looked like in the files whose chapters
+//! were confirmed (written by "apply"), kept under the show's profile in
+//! the --config file so that later detections for the same show can go
+//! by them:
+//!
+//! ```toml
+//! [[profiles."Some Show".learned]]
+//! path = "/media/Some Show/Season 1/Some Show S01E01.mkv"
+//! start = 92.5
+//! length = 61.2
+//! ```
+use crate::config::Config;
+use crate::episode;
+use crate::intro::Expected;
+use crate::planfile;
+use anyhow::{Context, Result};
+use serde_derive::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Shows with fewer confirmed intros than this have nothing to go by.
+const MIN_INTROS: usize = 3;
+
+/// Only the latest this many intros of a show are kept, so a show that
+/// changed its intro is learned anew.
+const MAX_INTROS: usize = 30;
+
+/// Expected starts and lengths are never tighter than this, as intros
+/// are rarely placed to the frame.
+const MIN_START_SPREAD: Duration = Duration::from_secs(5);
+const MIN_LENGTH_SPREAD: Duration = Duration::from_secs(2);
+
+/// A confirmed intro of the file at `path`, in seconds.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Intro {
+    path: PathBuf,
+    start: f64,
+    length: f64,
+}
+
+/// Returns the mean and standard deviation of `values`.
+fn mean_and_spread(values: impl Iterator<Item = f64> + Clone) -> (f64, f64) {
+    let n = values.clone().count() as f64;
+    let mean = values.clone().sum::<f64>() / n;
+    let variance = values.map(|v| (v - mean) * (v - mean)).sum::<f64>() / n;
+    (mean, variance.sqrt())
+}
+
+/// What the intro of an episode should look like, going by the other
+/// episodes of its show, if enough of them were confirmed.
+pub(crate) fn expected(intros: &[Intro]) -> Option<Expected> {
+    if intros.len() < MIN_INTROS {
+        return None;
+    }
+    let (start, start_spread) = mean_and_spread(intros.iter().map(|i| i.start));
+    let (length, length_spread) = mean_and_spread(intros.iter().map(|i| i.length));
+    let secs = |secs: f64| Duration::try_from_secs_f64(secs).ok();
+    Some(Expected {
+        start: secs(start)?,
+        start_spread: secs(start_spread)?.max(MIN_START_SPREAD),
+        length: secs(length)?,
+        length_spread: secs(length_spread)?.max(MIN_LENGTH_SPREAD),
+    })
+}
+
+/// The header of the entries learned for the profile called `name`.
+fn header(name: &str) -> String {
+    format!("[[profiles.{}.learned]]", planfile::quote(name))
+}
+
+/// Adds `intros` (by file, with their start and end) to what was learned
+/// about their shows in `text`, the contents of the config file that
+/// `config` was loaded from. A file's earlier intro is replaced; files of
+/// shows without a profile get one, and files whose show isn't known at
+/// all are left out. Everything else in the file stays as it is.
+fn with_intros(text: &str, config: &Config, intros: &[(&Path, (Duration, Duration))]) -> String {
+    let mut shows: BTreeMap<String, Vec<Intro>> = BTreeMap::new();
+    for (path, (start, end)) in intros {
+        let name = match config.profile_named_for(path) {
+            Some((name, _)) => name.to_string(),
+            None => match episode::series_and_season(path) {
+                Some((series, _)) => series,
+                None => continue,
+            },
+        };
+        let learned = shows.entry(name).or_insert_with(|| {
+            config
+                .profile_for(path)
+                .map(|profile| profile.learned.clone())
+                .unwrap_or_default()
+        });
+        learned.retain(|intro| intro.path != *path);
+        learned.push(Intro {
+            path: path.to_path_buf(),
+            start: start.as_secs_f64(),
+            length: (*end - *start).as_secs_f64(),
+        });
+        if learned.len() > MAX_INTROS {
+            learned.drain(..learned.len() - MAX_INTROS);
+        }
+    }
+    let shows: BTreeMap<String, Vec<Intro>> = shows
+        .into_iter()
+        .map(|(name, intros)| (header(&name), intros))
+        .collect();
+
+    // the entries of those shows that were there before go, along with
+    // the blank line after each:
+    let mut out = String::new();
+    let mut ours = false;
+    for line in text.lines() {
+        let line_start = line.trim();
+        if line_start.starts_with('[') {
+            ours = shows.contains_key(line_start);
+        }
+        if !ours {
+            out.push_str(line);
+            out.push('\n');
+        }
+    }
+    for (header, intros) in &shows {
+        for intro in intros {
+            let path = match intro.path.to_str() {
+                Some(path) => path,
+                None => continue,
+            };
+            let _ = writeln!(out, "{}", header);
+            let _ = writeln!(out, "path = {}", planfile::quote(path));
+            let _ = writeln!(out, "start = {:?}", intro.start);
+            let _ = writeln!(out, "length = {:?}\n", intro.length);
+        }
+    }
+    out
+}
+
+/// Remembers the confirmed `intros` (by file, with their start and end)
+/// under the profiles of their shows in the --config file at `path`,
+/// creating it if needed.
+pub(crate) fn record(path: &Path, intros: &[(&Path, (Duration, Duration))]) -> Result<()> {
+    let (text, config) = match fs::read_to_string(path) {
+        Ok(text) => (text, Config::load(path)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => (String::new(), Config::default()),
+        Err(e) => return Err(e).context(format!("reading {:?}", path)),
+    };
+    let text = with_intros(&text, &config, intros);
+    // written next to the old one first, so it's never left half-written:
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, text).context(format!("writing {:?}", &tmp))?;
+    fs::rename(&tmp, path).context(format!("replacing {:?}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"# my shows
+[profiles."Some Show"]
+threshold = "500ms"
+"#;
+
+    fn apply(text: &str, intros: &[(&str, u64, u64)]) -> String {
+        let config: Config = toml::from_str(text).unwrap();
+        let intros: Vec<_> = intros
+            .iter()
+            .map(|(path, start, end)| {
+                let span = (Duration::from_secs(*start), Duration::from_secs(*end));
+                (Path::new(*path), span)
+            })
+            .collect();
+        with_intros(text, &config, &intros)
+    }
+
+    fn learned(text: &str, path: &str) -> Vec<Intro> {
+        let config: Config = toml::from_str(text).unwrap();
+        config.profile_for(Path::new(path)).unwrap().learned.clone()
+    }
+
+    #[test]
+    fn learns_under_the_shows_profile() {
+        let text = apply(CONFIG, &[("/tv/Some Show/Some Show S01E01.mkv", 60, 90)]);
+        assert!(text.starts_with(CONFIG));
+        let config: Config = toml::from_str(&text).unwrap();
+        let profile = config
+            .profile_for(Path::new("/tv/Some Show/Some Show S01E02.mkv"))
+            .unwrap();
+        assert_eq!(profile.threshold, Some(Duration::from_millis(500)));
+        assert_eq!(
+            profile.learned,
+            vec![Intro {
+                path: PathBuf::from("/tv/Some Show/Some Show S01E01.mkv"),
+                start: 60.0,
+                length: 30.0
+            }]
+        );
+    }
+
+    #[test]
+    fn replaces_intros_applied_again() {
+        let e01 = "/tv/Some Show/Some Show S01E01.mkv";
+        let e02 = "/tv/Some Show/Some Show S01E02.mkv";
+        let text = apply(CONFIG, &[(e01, 60, 90), (e02, 62, 92)]);
+        let text = apply(&text, &[(e01, 61, 91)]);
+        let text = apply(&text, &[(e01, 61, 91)]);
+        let intros = learned(&text, e01);
+        assert_eq!(intros.len(), 2);
+        assert_eq!(intros[0].path, PathBuf::from(e02));
+        assert_eq!((intros[1].start, intros[1].length), (61.0, 30.0));
+        assert!(text.starts_with(CONFIG));
+        assert!(!text.contains("\n\n\n"));
+    }
+
+    #[test]
+    fn adds_profiles_for_new_shows() {
+        let path = "/tv/Other Show/Other Show S02E03.mkv";
+        let text = apply(CONFIG, &[(path, 10, 70), ("/tv/home video.mkv", 0, 30)]);
+        assert_eq!(learned(&text, path).len(), 1);
+        assert!(!text.contains("home video"));
+    }
+
+    #[test]
+    fn expects_intros_like_the_learned_ones() {
+        let intro = |start, length| Intro {
+            path: PathBuf::new(),
+            start,
+            length,
+        };
+        assert_eq!(expected(&[intro(60.0, 30.0), intro(62.0, 30.0)]), None);
+        let expected =
+            expected(&[intro(60.0, 30.0), intro(62.0, 30.0), intro(64.0, 30.0)]).unwrap();
+        assert_eq!(expected.start, Duration::from_secs(62));
+        assert_eq!(expected.start_spread, MIN_START_SPREAD);
+        assert_eq!(expected.length, Duration::from_secs(30));
+        assert_eq!(expected.length_spread, MIN_LENGTH_SPREAD);
+    }
+}
@@ -0,0 +1,61 @@
+//! Measure audio loudness around a point in an input file
+use crate::detect::{audio_decoder, audio_filter, AudioPreference};
+use crate::frames::Filtered;
+use crate::metadata;
+use crate::util::{self, to_duration};
+use anyhow::Result;
+use ffmpeg::format;
+use std::time::Duration;
+
+/// Scans forward from `from` for the first frame of the `audio` track
+/// whose RMS level exceeds `threshold_db` (in dBFS), giving up after
+/// `window`. Offsets are as players show them (see
+/// `util::stream_origin`).
+pub(crate) fn first_loud_frame(
+    ictx: &mut format::context::Input,
+    from: Duration,
+    window: Duration,
+    threshold_db: f64,
+    audio: &AudioPreference,
+) -> Result<Option<Duration>> {
+    let (audio_stream, decoder) = audio_decoder(ictx, audio)?;
+    let graph = audio_filter(&decoder, "astats=metadata=1:reset=1")?;
+    let mut frames = Filtered::new(audio_stream, decoder, graph);
+    let time_base = frames.time_base();
+    let origin = util::stream_origin(ictx);
+
+    let seek_to = ((from.as_secs_f64() + origin) * 1e6).max(0.0) as i64;
+    ictx.seek(seek_to, ..seek_to)?;
+
+    for (stream, mut packet) in ictx.packets() {
+        if stream.index() != audio_stream {
+            continue;
+        }
+        // set by the first frame past `from` that settles it:
+        let mut found = None;
+        frames.feed(stream.time_base(), &mut packet, |frame| {
+            let at = match frame.timestamp() {
+                Some(ts) => util::displayed_offset(to_duration(ts, time_base), origin),
+                None => return,
+            };
+            if found.is_some() || at < from {
+                return;
+            }
+            if at > from + window {
+                found = Some(None);
+                return;
+            }
+            let level = frame
+                .metadata()
+                .get("lavfi.astats.Overall.RMS_level")
+                .and_then(metadata::parse_number);
+            if level.map(|db| db > threshold_db).unwrap_or(false) {
+                found = Some(Some(at));
+            }
+        })?;
+        if let Some(found) = found {
+            return Ok(found);
+        }
+    }
+    Ok(None)
+}
@@ -1,26 +1,68 @@
 extern crate ffmpeg4 as ffmpeg;
 use anyhow::{self, bail, Context};
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-use mktemp::Temp;
+use chapters::{existing_chapters, set_chapters, Chapter};
+use indicatif::ProgressBar;
 use rayon::prelude::*;
-use serde_derive::*;
-use std::fmt;
-use std::fs::{self, File};
-use std::io::{self, BufWriter, Write};
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::num::NonZeroUsize;
+use std::ops::{Range, RangeInclusive};
 use std::path::{Path, PathBuf};
-use std::process::Command;
-use std::{thread, time::Duration};
+use std::time::Duration;
 
+mod bench;
+mod calibrate;
+mod candidates;
+mod chapters;
+mod checkpoint;
+mod compare;
+mod comskip;
+mod config;
 mod detect;
+mod ebml;
+mod edl;
+mod episode;
+mod exclude;
+mod features;
+mod ffmpeg_cli;
+mod filelog;
+mod frames;
+mod intro;
+mod learned;
+mod loudness;
+mod metadata;
+mod offset;
+mod pathmap;
+mod planfile;
+mod prefilter;
+mod probe;
+mod progress;
+mod provenance;
+mod remux;
+mod runlock;
+mod scenes;
+mod season;
+mod service;
+mod shutdown;
+mod sidecar;
+mod sweep;
+mod title_info;
+mod trace;
+mod tune;
 mod util;
+mod webhook;
+mod xml;
 
-#[derive(Debug, PartialEq, Deserialize)]
-struct TitleInfo {
-    location: PathBuf,
-    theme_start: f64,
-    theme_end: f64,
-}
+/// How far past the given "End of intro" to look for the first loud
+/// audio frame.
+const LOUDNESS_SCAN_WINDOW: Duration = Duration::from_secs(10);
+
+/// With --compilation, segments shorter than this are merged into the
+/// one before, so only major breaks become chapters.
+const MIN_SEGMENT_LENGTH: Duration = Duration::from_secs(3 * 60);
 
+// parsed once, so the size of a variant doesn't matter:
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, structopt::StructOpt)]
 #[structopt(
     name = "intro_chapter_adder",
@@ -28,14 +70,195 @@ struct TitleInfo {
 )]
 enum Options {
     /// Add chapter markers from a CSV file.
-    AddChapterMarkers,
+    AddChapterMarkers {
+        /// Move each "End of intro" marker forward to the first audio
+        /// frame louder than this many dBFS (e.g. -30)
+        #[structopt(long = "--snap-end-to-sound", allow_hyphen_values = true)]
+        snap_end_to_sound: Option<f64>,
+
+        /// Which audio track --snap-end-to-sound listens to
+        #[structopt(flatten)]
+        audio: AudioOptions,
+
+        #[structopt(flatten)]
+        paths: PathMapOptions,
+    },
+
+    /// Listen for playback webhooks (e.g. from Tautulli or Jellyfin) and
+    /// detect intros in played files that don't have them marked yet
+    Serve {
+        /// The address to listen on
+        #[structopt(long = "--listen", default_value = "127.0.0.1:8405")]
+        listen: String,
+
+        /// Files or directories to detect intros in while no played file
+        /// is waiting; played files always go first
+        #[structopt(parse(from_os_str))]
+        backlog: Vec<PathBuf>,
+
+        #[structopt(flatten)]
+        paths: PathMapOptions,
+
+        #[structopt(flatten)]
+        opts: DetectOptions,
+    },
 
     /// Detect silences in the first few minutes and add markers for them
     DetectSilence {
-        /// The MKV files to treat
+        /// The MKV, MP4 (or related) files to treat; directories are
+        /// expanded to the files inside them
+        #[structopt(parse(from_os_str))]
+        paths: Vec<PathBuf>,
+
+        /// Show the progress of at most this many files at a time, below
+        /// a bar for the whole batch (default: one per worker thread)
+        #[structopt(long = "--display-bars")]
+        display_bars: Option<usize>,
+
+        /// Only detect on this many files, picked at random, and show
+        /// what would be written; to try settings on part of a library
+        #[structopt(long = "--sample", conflicts_with = "do-it")]
+        sample: Option<usize>,
+
+        /// Pick the --sample files with this seed, to get the same ones
+        /// as an earlier run
+        #[structopt(long = "--sample-seed", requires = "sample")]
+        sample_seed: Option<u64>,
+
+        #[structopt(flatten)]
+        opts: DetectOptions,
+    },
+
+    /// Write the chapters from a file saved by "detect-silence --emit"
+    /// or "manifest" (also available as "apply-manifest"), or from a
+    /// plan file saved by "detect-silence --plan"
+    #[structopt(alias = "apply-manifest")]
+    Apply {
+        #[structopt(parse(from_os_str), required_unless = "plan")]
+        candidates: Option<PathBuf>,
+
+        /// Read the chapters from this plan file instead
+        #[structopt(long = "--plan", parse(from_os_str), conflicts_with = "candidates")]
+        plan: Option<PathBuf>,
+
+        /// Remember the intros written under their shows' profiles in
+        /// this --config file (replacing what was remembered about the
+        /// same files), for detection to go by
+        #[structopt(long = "--learn-into", parse(from_os_str))]
+        learn_into: Option<PathBuf>,
+
+        #[structopt(flatten)]
+        write: WriteOptions,
+    },
+
+    /// Describe the chapters and intros the files already have, in the
+    /// format "apply" reads
+    Manifest {
+        /// The files to describe; directories are expanded to the
+        /// files inside them
+        #[structopt(parse(from_os_str))]
+        paths: Vec<PathBuf>,
+
+        /// Write the manifest here instead of to stdout
+        #[structopt(long = "--output", short = "-o", parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+
+    /// Show how the results of two runs saved with "detect-silence
+    /// --emit" differ
+    Compare {
+        #[structopt(parse(from_os_str))]
+        before: PathBuf,
+
+        #[structopt(parse(from_os_str))]
+        after: PathBuf,
+    },
+
+    /// Turn the commercial breaks comskip found in recordings (in the
+    /// .edl or .txt files next to them) into chapters
+    ImportComskip {
+        /// The recordings; directories are expanded to the files
+        /// inside them
         #[structopt(parse(from_os_str))]
         paths: Vec<PathBuf>,
 
+        /// For recordings whose container can't hold chapters (e.g. .ts),
+        /// copy their streams into a new .mkv next to them and put the
+        /// chapters there
+        #[structopt(long = "--remux-to-mkv")]
+        remux_to_mkv: bool,
+
+        #[structopt(flatten)]
+        write: WriteOptions,
+    },
+
+    /// Compare the speed and results of different detector settings
+    /// on the beginning of a file
+    Bench {
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+
+        /// Detection settings, as for detect-silence (but decoding with
+        /// all CPUs unless --decoder-threads says otherwise)
+        #[structopt(flatten)]
+        opts: DetectOptions,
+    },
+
+    /// Given where the intro of a file really is, find detection
+    /// settings that find it there too, and make them a profile for
+    /// its show
+    Calibrate {
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+
+        /// Where the intro really is, as START..END (e.g. 1:02..2:32)
+        #[structopt(long = "--intro")]
+        intro: intro::ForcedIntro,
+
+        /// Add the profile to this --config file instead of printing it
+        #[structopt(long = "--append-to", parse(from_os_str))]
+        append_to: Option<PathBuf>,
+
+        /// The other detection settings, as for detect-silence (but
+        /// decoding with all CPUs unless --decoder-threads says otherwise)
+        #[structopt(flatten)]
+        opts: DetectOptions,
+    },
+
+    /// Scan the beginning of a file once for each setting of one
+    /// detection parameter, and show the breaks each finds
+    Sweep {
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+
+        /// The parameter and its settings, as NAME=FROM..TO [step STEP],
+        /// e.g. silence-noise=-60..-35 step 5 (known: silence-noise,
+        /// black-pixel, min-pause)
+        #[structopt(
+            long = "--param",
+            allow_hyphen_values = true,
+            min_values = 1,
+            max_values = 3,
+            required = true
+        )]
+        param: Vec<String>,
+
+        /// The other detection settings, as for detect-silence (but
+        /// decoding with all CPUs unless --decoder-threads says otherwise)
+        #[structopt(flatten)]
+        opts: DetectOptions,
+    },
+
+    /// Print every event one detection signal reports (silence, black,
+    /// loudness or scenes), to see why a break was missed
+    Probe {
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+
+        /// The signal to print
+        #[structopt(long = "--signal", default_value = "silence")]
+        signal: probe::Signal,
+
         /// Scan this long into the beginning of the file
         #[structopt(
             long = "--until",
@@ -44,162 +267,2237 @@ enum Options {
         )]
         until: Duration,
 
-        /// Only consider pauses this long or longer as real "breaks"
-        #[structopt(
-            long = "--threshold",
-            default_value = "200ms",
-            parse(try_from_str = humantime::parse_duration)
-        )]
-        threshold: Duration,
-
-        /// Take only this many pauses
-        #[structopt(long = "--only")]
-        only: Option<usize>,
+        /// Use the thresholds --auto-tune would pick for the file
+        /// instead of the fixed ones
+        #[structopt(long = "--auto-tune")]
+        auto_tune: bool,
 
-        /// Name the pause markers like this (appends a space and the
-        /// number of the pause to the marker name)
-        #[structopt(long = "--name", default_value = "Pause")]
-        name: String,
+        /// Instead of events, draw a timeline of audio and video levels
+        #[structopt(long = "--levels")]
+        levels: bool,
 
-        /// Actually write chapter markers. NOTE: This overwrites any existing chapters.
-        #[structopt(long = "--do-it", short = "-f")]
-        do_it: bool,
+        #[structopt(flatten)]
+        audio: AudioOptions,
     },
 }
 
+#[derive(Debug, Clone, structopt::StructOpt)]
+struct DetectOptions {
+    /// Scan this long into the beginning of the file, or "auto" to
+    /// pick a length depending on how long the file runs
+    #[structopt(long = "--until", default_value = "10m")]
+    until: intro::ScanLength,
+
+    /// Don't detect anything, but put intro chapters at these offsets
+    /// (e.g. 1m30s..2m45s) into every file, in place of any intro
+    /// chapters it has and next to its other chapters
+    #[structopt(long = "--force-intro")]
+    force_intro: Option<intro::ForcedIntro>,
+
+    /// Don't detect anything on files whose intro is already marked
+    /// (by intro chapters, SKIP_START/SKIP_END tags or an .edl file
+    /// next to them), but write chapters for that intro; skip files an
+    /// earlier run's --provenance-tags mark as having no intro
+    #[structopt(long = "--trust-existing")]
+    trust_existing: bool,
+
+    /// Use built-in settings for a kind of show: "anime" looks for
+    /// 85-95s opening and ending themes at the head and the tail of
+    /// each episode, and marks both
+    #[structopt(long = "--profile")]
+    profile: Option<intro::Profile>,
+
+    /// Don't take a pair of breaks less than this far apart for the
+    /// intro (default: 20s, or the profile's theme length)
+    #[structopt(long = "--intro-min", parse(try_from_str = humantime::parse_duration))]
+    intro_min: Option<Duration>,
+
+    /// Don't take a pair of breaks more than this far apart for the
+    /// intro; longer "intros" usually span the cold open (default: 2m30s,
+    /// or the profile's theme length)
+    #[structopt(long = "--intro-max", parse(try_from_str = humantime::parse_duration))]
+    intro_max: Option<Duration>,
+
+    /// Never consider breaks in these time ranges (e.g. 0s..20s,41m..),
+    /// in addition to any given in a file's sidecar
+    #[structopt(long = "--exclude", default_value = "")]
+    exclude: exclude::Exclusions,
+
+    /// Only consider pauses this long or longer as real "breaks"
+    #[structopt(
+        long = "--threshold",
+        default_value = "200ms",
+        parse(try_from_str = humantime::parse_duration)
+    )]
+    threshold: Duration,
+
+    /// Take only this many pauses
+    #[structopt(long = "--only")]
+    only: Option<usize>,
+
+    /// Name the pause markers like this (appends a space and the
+    /// number of the pause to the marker name)
+    #[structopt(long = "--name", default_value = "Pause")]
+    name: String,
+
+    /// Skip files whose chapters were written by this version of the
+    /// tool or a later one, as recorded by --provenance-tags (files
+    /// without those tags are always handled)
+    #[structopt(long = "--only-if-older-than-version")]
+    only_if_older_than_version: Option<String>,
+
+    /// Before scanning a file, wait until its size hasn't changed for
+    /// this long (skips files that are still being copied)
+    #[structopt(long = "--settle", parse(try_from_str = humantime::parse_duration))]
+    settle: Option<Duration>,
+
+    /// Where in each pause its marker goes: "start" (where audio and
+    /// video are first both quiet and black), "middle" or "end"
+    #[structopt(long = "--anchor", default_value = "start")]
+    anchor: detect::Anchor,
+
+    /// Move each marker back to the nearest preceding video keyframe
+    #[structopt(long = "--snap-to-keyframe")]
+    snap_to_keyframe: bool,
+
+    /// Move each marker to the start of the video frame nearest to it,
+    /// so chapters begin on a real frame
+    #[structopt(long = "--exact-pts")]
+    exact_pts: bool,
+
+    /// Name the detected breaks as a complete chapter list ("Cold
+    /// open", "Intro", "Act 1".., "Credits") instead of numbered pauses;
+    /// the final tenth of each file is scanned for the credits too
+    #[structopt(long = "--full-chapters")]
+    full_chapters: bool,
+
+    /// Treat files as compilations of several segments: scan all of
+    /// each file and write a chapter for every segment, named after this
+    /// template ("{n}" is the segment's number, e.g. "Segment {n}")
+    #[structopt(long = "--compilation", conflicts_with = "profile")]
+    compilation: Option<String>,
+
+    /// Add a chapter with this name at 00:00 unless one starts there already
+    #[structopt(long = "--chapter-at-zero")]
+    chapter_at_zero: Option<String>,
+
+    /// Keep a file's existing chapters, and only add detected ones
+    /// further than this (e.g. 5s) from any of them
+    #[structopt(long = "--merge-existing", parse(try_from_str = humantime::parse_duration))]
+    merge_existing: Option<Duration>,
+
+    /// Move each detected intro to the median one of its season when
+    /// its start and length are within this (e.g. 3s) of it, and treat
+    /// those that aren't as outliers, so all episodes skip alike
+    #[structopt(
+        long = "--enforce-consistency",
+        parse(try_from_str = humantime::parse_duration)
+    )]
+    enforce_consistency: Option<Duration>,
+
+    /// For files whose container can't hold chapters (e.g. .ts or
+    /// .avi), copy their streams into a new .mkv next to them and put
+    /// the chapters there
+    #[structopt(long = "--remux-to-mkv")]
+    remux_to_mkv: bool,
+
+    /// Decode video with this many threads per file (default: the
+    /// number of CPUs divided among the files being scanned)
+    #[structopt(long = "--decoder-threads")]
+    decoder_threads: Option<usize>,
+
+    #[structopt(flatten)]
+    audio: AudioOptions,
+
+    /// Stop scanning a file as soon as two breaks look like the start
+    /// and end of an intro
+    #[structopt(long = "--stop-after-intro")]
+    stop_after_intro: bool,
+
+    /// With --stop-after-intro, scan at least this far (e.g. 3m) into
+    /// each file before stopping, so an early pair of breaks that only
+    /// looks like an intro doesn't cut the scan short
+    #[structopt(
+        long = "--min-scan",
+        requires = "stop-after-intro",
+        parse(try_from_str = humantime::parse_duration)
+    )]
+    min_scan: Option<Duration>,
+
+    /// Read path mappings and per-series settings from this TOML file
+    #[structopt(long = "--config", parse(from_os_str))]
+    config: Option<PathBuf>,
+
+    /// Work on files in this order: newest-first, smallest-first or
+    /// path (default: in the order given)
+    #[structopt(long = "--order")]
+    order: Option<util::Order>,
+
+    /// Save progress on long scans this often, and resume from the
+    /// saved progress when scanning the same file again
+    #[structopt(
+        long = "--checkpoint-every",
+        parse(try_from_str = humantime::parse_duration)
+    )]
+    checkpoint_every: Option<Duration>,
+
+    /// Before decoding, look at packet sizes to guess where breaks
+    /// could be, and only decode around those spots. Much faster, but
+    /// can miss breaks in files where black frames aren't compressed
+    /// into small packets
+    #[structopt(long = "--prefilter")]
+    prefilter: bool,
+
+    /// Run the audio and video detectors on separate threads, which
+    /// speeds up scanning when there are fewer files than CPUs
+    #[structopt(long = "--pipeline")]
+    pipeline: bool,
+
+    /// Scan with the linked ffmpeg libraries ("libav"), or by running
+    /// the ffmpeg command ("cli"), for when those can't; the latter
+    /// can't resume from checkpoints, snap to keyframes or frames, or
+    /// pick an audio track
+    #[structopt(long = "--backend", default_value = "libav")]
+    backend: detect::Backend,
+
+    /// The ffmpeg command that --backend=cli runs
+    #[structopt(long = "--ffmpeg-path", default_value = "ffmpeg", parse(from_os_str))]
+    ffmpeg_path: PathBuf,
+
+    /// Measure how noisy and how bright the start of each file is, and
+    /// pick silence and blackness thresholds relative to that instead
+    /// of using fixed ones
+    #[structopt(long = "--auto-tune")]
+    auto_tune: bool,
+
+    /// Count audio quieter than this many dBFS as silent (default: -50,
+    /// or what --auto-tune measures)
+    #[structopt(long = "--silence-noise", allow_hyphen_values = true)]
+    silence_noise: Option<f64>,
+
+    /// Count pixels darker than this fraction of the luma range as
+    /// black (default: 0.1, or what --auto-tune measures)
+    #[structopt(long = "--black-pixel")]
+    black_pixel: Option<f64>,
+
+    /// Only scan as many files at once as are expected to fit into
+    /// this much memory (e.g. 4G), based on their video frame size
+    #[structopt(long = "--max-memory", parse(try_from_str = util::parse_size))]
+    max_memory: Option<u64>,
+
+    /// If no breaks are found, fall back to placing chapters where the
+    /// pace of scene cuts changes the most (rough, but better than
+    /// nothing on content without silences or black frames)
+    #[structopt(long = "--scene-cuts")]
+    scene_cuts: bool,
+
+    /// Write a log for each file to this directory, with the breaks
+    /// found, the decisions made and what the writer said
+    #[structopt(long = "--log-dir", parse(from_os_str))]
+    log_dir: Option<PathBuf>,
+
+    /// Record every pause the detectors report, and how it changed the
+    /// detection state, to this file (as JSON lines)
+    #[structopt(long = "--trace-detections", parse(from_os_str))]
+    trace_detections: Option<PathBuf>,
+
+    /// Write the loudness, brightness and scene change score of every
+    /// frame in the scan window to CSV files in this directory
+    #[structopt(long = "--dump-features", parse(from_os_str))]
+    dump_features: Option<PathBuf>,
+
+    /// With --emit, also save a fingerprint of each file, so that
+    /// "apply" refuses files that were replaced in the meantime
+    #[structopt(long = "--checksum", requires = "emit")]
+    checksum: bool,
+
+    /// Don't write any chapters, but save what was detected to this
+    /// file, for review and for the "apply" command
+    #[structopt(long = "--emit", parse(from_os_str), conflicts_with = "do-it")]
+    emit: Option<PathBuf>,
+
+    /// Don't write any chapters, but save what was detected to this
+    /// TOML file, with the breaks that didn't make a chapter as
+    /// comments, for editing and for "apply --plan"
+    #[structopt(long = "--plan", parse(from_os_str), conflicts_with = "do-it")]
+    plan: Option<PathBuf>,
+
+    #[structopt(flatten)]
+    write: WriteOptions,
+}
+
+#[derive(Debug, Clone, structopt::StructOpt)]
+struct WriteOptions {
+    /// Move every chapter (except one at the very start) by this much
+    /// when writing, e.g. -0.25s for players that land late on seeks;
+    /// chapters moved out of the file are dropped
+    #[structopt(long = "--offset-adjust", allow_hyphen_values = true)]
+    offset_adjust: Option<util::Shift>,
+
+    /// Write chapters to files even if their intro differs a lot from
+    /// the rest of the season
+    #[structopt(long = "--apply-outliers")]
+    apply_outliers: bool,
+
+    /// Run this mkvpropedit binary to write Matroska chapters
+    #[structopt(
+        long = "--mkvpropedit-path",
+        default_value = "mkvpropedit",
+        parse(from_os_str)
+    )]
+    mkvpropedit_path: PathBuf,
+
+    /// Pass this option on to mkvpropedit, e.g. --writer-arg=--parse-mode
+    /// --writer-arg=full for damaged files (can be given repeatedly)
+    #[structopt(
+        long = "--writer-arg",
+        allow_hyphen_values = true,
+        number_of_values = 1
+    )]
+    writer_args: Vec<String>,
+
+    /// Put temporary files (chapter lists for mkvpropedit, remuxed
+    /// copies) in this directory instead of the system's temporary
+    /// directory or next to the file
+    #[structopt(long = "--temp-dir", parse(from_os_str))]
+    temp_dir: Option<PathBuf>,
+
+    /// Treat warnings from mkvpropedit as failures. It only warns once
+    /// it has written the file, so files it warns about are still left
+    /// with the new chapters
+    #[structopt(long = "--strict")]
+    strict: bool,
+
+    /// Tag each chapter with how it was detected (length of the break,
+    /// the signals that found it and the version of this tool). Needs
+    /// mkvextract next to mkvpropedit; Matroska only
+    #[structopt(long = "--chapter-tags")]
+    chapter_tags: bool,
+
+    /// Tag the file with the version of this tool, the run that wrote its
+    /// chapters, a hash of the detection settings and whether detection
+    /// found an intro, for a later --only-if-older-than-version (and so
+    /// that files without an intro aren't scanned again by "serve" or
+    /// with --trust-existing). Keeps the file's other tags if
+    /// mkvextract is next to mkvpropedit; Matroska only
+    #[structopt(long = "--provenance-tags")]
+    provenance_tags: bool,
+
+    /// Refuse to touch files outside this directory (can be given
+    /// repeatedly)
+    #[structopt(long = "--restrict-to", parse(from_os_str), number_of_values = 1)]
+    restrict_to: Vec<PathBuf>,
+
+    /// If another run is writing chapters in the same directories, wait
+    /// for it to finish instead of failing
+    #[structopt(long = "--wait")]
+    wait: bool,
+
+    /// Number generated chapter names (e.g. "Pause 1") from this (at
+    /// least 1), and shift the IDs in simple chapter files to match: the
+    /// first chapter is CHAPTER00 by default, CHAPTER01 with 2
+    #[structopt(long = "--start-index", default_value = "1")]
+    start_index: NonZeroUsize,
+
+    /// Pad chapter numbers with zeros to this many digits (IDs in
+    /// simple chapter files default to 2, as in CHAPTER01)
+    #[structopt(long = "--index-width")]
+    index_width: Option<usize>,
+
+    /// How to show the chapters that would be written without --do-it:
+    /// "ogm", "table", "json" or "xml"
+    #[structopt(long = "--preview-format", default_value = "ogm")]
+    preview_format: chapters::PreviewFormat,
+
+    /// Actually write chapter markers. NOTE: This overwrites any existing chapters.
+    #[structopt(long = "--do-it", short = "-f")]
+    do_it: bool,
+}
+
+#[derive(Debug, Clone, structopt::StructOpt)]
+struct PathMapOptions {
+    /// Find the server's files by replacing the path prefix SERVER with
+    /// LOCAL (SERVER=LOCAL); may be given several times
+    #[structopt(long = "--map-path", number_of_values = 1)]
+    map_path: Vec<pathmap::Rule>,
+
+    /// Read more path mappings from this TOML file of
+    /// "SERVER" = "LOCAL" lines
+    #[structopt(long = "--path-map", parse(from_os_str))]
+    path_map: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, structopt::StructOpt)]
+struct AudioOptions {
+    /// Look for silence in the audio track marked as the default,
+    /// rather than the one ffmpeg thinks is best
+    #[structopt(long = "--prefer-default-track")]
+    prefer_default_track: bool,
+
+    /// Look for silence in the audio track in the original language
+    /// (marked as such, or matching the video's language); takes
+    /// precedence over --prefer-default-track
+    #[structopt(long = "--prefer-original-language")]
+    prefer_original_language: bool,
+}
+
+impl AudioOptions {
+    fn preference(&self) -> detect::AudioPreference {
+        detect::AudioPreference {
+            default_track: self.prefer_default_track,
+            original_language: self.prefer_original_language,
+        }
+    }
+}
+
+impl PathMapOptions {
+    /// Builds the mapping, using `default` if none was given.
+    fn load(&self, default: Option<&str>) -> anyhow::Result<pathmap::PathMap> {
+        let mut rules = self.map_path.clone();
+        if rules.is_empty() && self.path_map.is_none() {
+            rules.extend(default.map(str::parse).transpose()?);
+        }
+        let mut paths = pathmap::PathMap::new(rules);
+        if let Some(file) = &self.path_map {
+            paths.load(file)?;
+        }
+        Ok(paths)
+    }
+}
+
+impl WriteOptions {
+    /// Fails unless all of `paths` are inside the --restrict-to
+    /// directories. Checked before anything (locking included) touches
+    /// the files or their directories.
+    fn check_within<'a>(&self, paths: impl IntoIterator<Item = &'a Path>) -> anyhow::Result<()> {
+        for path in paths {
+            util::check_within(&self.restrict_to, path)?;
+        }
+        Ok(())
+    }
+
+    /// Locks the directories of `paths` against other runs, if we're
+    /// going to write to them.
+    fn lock<'a>(
+        &self,
+        paths: impl IntoIterator<Item = &'a Path>,
+    ) -> anyhow::Result<Option<runlock::DirLocks>> {
+        if !self.do_it {
+            return Ok(None);
+        }
+        runlock::lock_dirs(paths, self.wait).map(Some)
+    }
+
+    fn mkvpropedit(&self) -> chapters::Mkvpropedit {
+        chapters::Mkvpropedit {
+            program: self.mkvpropedit_path.clone(),
+            args: self.writer_args.clone(),
+            strict: self.strict,
+            tags: self.chapter_tags,
+            numbering: self.numbering(),
+        }
+    }
+
+    fn numbering(&self) -> chapters::Numbering {
+        chapters::Numbering {
+            start: self.start_index.get(),
+            width: self.index_width,
+        }
+    }
+}
+
 #[paw::main]
 fn main(args: Options) -> anyhow::Result<()> {
-    let base = PathBuf::from("/Volumes/Media");
-
     ffmpeg::init()?;
+    shutdown::install();
     unsafe {
         ffmpeg::ffi::av_log_set_level(ffmpeg::ffi::AV_LOG_WARNING);
     }
 
     match args {
-        Options::AddChapterMarkers => {
+        Options::AddChapterMarkers {
+            snap_end_to_sound,
+            audio,
+            paths,
+        } => {
+            // where the CSV importer always looked:
+            let paths = paths.load(Some("/media=/Volumes/Media"))?;
             let mut rdr = csv::Reader::from_reader(io::stdin());
-            for result in rdr.deserialize() {
-                let record: TitleInfo = result?;
-                println!("{:?}", record);
-                adjust_tags_on(&base, record)?;
+            let mut failed = 0;
+            for (row, result) in rdr.deserialize().enumerate() {
+                if shutdown::interrupted() {
+                    bail!("interrupted after {} rows", row);
+                }
+                let outcome = result.map_err(anyhow::Error::from).and_then(
+                    |record: title_info::TitleInfo| {
+                        println!("{:?}", record);
+                        adjust_tags_on(&paths, record, snap_end_to_sound, &audio.preference())
+                    },
+                );
+                if let Err(e) = outcome {
+                    service::error(format!("row {}: {:#}", row + 1, e));
+                    failed += 1;
+                }
+            }
+            if failed > 0 {
+                bail!("{} rows could not be applied", failed);
             }
             Ok(())
         }
         Options::DetectSilence {
             paths,
-            until,
-            threshold,
-            do_it,
-            only,
-            name,
+            display_bars,
+            sample,
+            sample_seed,
+            mut opts,
         } => {
-            let multibar = MultiProgress::new();
-            let sty = ProgressStyle::default_bar().template(
-                "[{prefix}:{elapsed_precise}] {bar:30.cyan/blue} {pos:>7}ms/{len:7}ms [ETA:{eta}]",
+            let loaded = opts.prepare()?;
+            let extensions = extensions(opts.remux_to_mkv);
+            let mut paths = util::dedup_paths(util::expand_dirs(paths, &extensions)?);
+            opts.write
+                .check_within(paths.iter().map(PathBuf::as_path))?;
+            paths.retain(|path| opts.is_outdated(path) && !opts.known_without_intro(path));
+            if let Some(n) = sample {
+                let of = paths.len();
+                let seed = util::sample(&mut paths, n, sample_seed);
+                println!(
+                    "sampled {} of {} files (--sample-seed {})",
+                    paths.len(),
+                    of,
+                    seed
+                );
+            }
+            if let Some(order) = opts.order {
+                order.sort(&mut paths);
+            }
+            if opts.decoder_threads.is_none() {
+                let per_file = rayon::current_num_threads() / paths.len().max(1);
+                opts.decoder_threads = Some(per_file.max(1));
+            }
+            let _dir_locks = opts.write.lock(paths.iter().map(PathBuf::as_path))?;
+            let locks = util::FileLocks::default();
+            let budget = opts.max_memory.map(util::MemoryBudget::new);
+            let trace = match &opts.trace_detections {
+                Some(path) => Some(trace::Trace::create(path)?),
+                None => None,
+            };
+            let progress = progress::Progress::new(
+                paths.len(),
+                display_bars.unwrap_or_else(rayon::current_num_threads),
+                service::interactive(),
+                "scanned",
             );
-            let progress_paths: Vec<(ProgressBar, &PathBuf)> = paths
+            // bridged, so workers pick up files in order:
+            let mut plans = paths
                 .iter()
-                .map(|path| {
-                    let bar = multibar.add(ProgressBar::new(until.as_millis() as u64));
-                    bar.set_style(sty.clone());
-                    if let Some(name) = path.file_name().and_then(|s| s.to_str()) {
-                        let name: String = name.chars().take(50).collect();
-                        bar.set_prefix(&name);
+                .enumerate()
+                .par_bridge()
+                .map(|(n, path)| {
+                    if let Err(e) = shutdown::check() {
+                        return (n, (path, filelog::FileLog::disabled(), Err(e.into())));
+                    }
+                    let bar = progress.start(path);
+                    let log = match &opts.log_dir {
+                        Some(dir) => filelog::FileLog::create(dir, path),
+                        None => Ok(filelog::FileLog::disabled()),
+                    };
+                    let (log, plan) = match log {
+                        Ok(log) => {
+                            let scan = Scan {
+                                bar: &bar,
+                                log: &log,
+                                budget: budget.as_ref(),
+                                trace: trace.as_ref(),
+                                media: Some(bar.media()),
+                            };
+                            let plan = plan_chapters(path, &opts, &loaded, &scan);
+                            (log, plan)
+                        }
+                        Err(e) => (filelog::FileLog::disabled(), Err(e)),
+                    };
+                    if let Err(e) = &plan {
+                        log.record(format!("failed: {:#}", e));
                     }
-                    bar
+                    (n, (path, log, plan))
                 })
-                .zip(paths.iter())
-                .collect();
-            thread::spawn(move || multibar.join_and_clear());
-            progress_paths
+                .collect::<Vec<_>>();
+            plans.sort_by_key(|(n, _)| *n);
+            let mut plans: Vec<_> = plans.into_iter().map(|(_, plan)| plan).collect();
+            flag_outliers(&mut plans);
+            if let Some(tolerance) = opts.enforce_consistency {
+                enforce_consistency(&mut plans, tolerance);
+            }
+            if opts.emit.is_some() || opts.plan.is_some() {
+                let mut entries = vec![];
+                let results = plans
+                    .into_iter()
+                    .map(|(path, _, plan)| {
+                        let result = plan.and_then(|mut plan| {
+                            let outcome = plan.outcome();
+                            let unused = std::mem::take(&mut plan.unused);
+                            let mut entry = plan.into_entry(path);
+                            if opts.checksum {
+                                entry.checksum = Some(util::fingerprint(path)?);
+                            }
+                            entries.push((entry, unused));
+                            Ok(outcome)
+                        });
+                        (path, result)
+                    })
+                    .collect::<Vec<_>>();
+                progress.finish();
+                if let Some(plan) = &opts.plan {
+                    planfile::write(plan, &entries)?;
+                    println!("saved a plan for {} files to {:?}", entries.len(), plan);
+                }
+                if let Some(emit) = &opts.emit {
+                    let entries: Vec<_> = entries.into_iter().map(|(entry, _)| entry).collect();
+                    candidates::write(emit, &entries)?;
+                    println!("saved chapters for {} files to {:?}", entries.len(), emit);
+                }
+                return report_by_season(results);
+            }
+            progress.restart("written");
+            let results = plans
                 .into_par_iter()
-                .map(|(bar, path)| {
-                    let mut ictx = ffmpeg::format::input(&path)
-                        .context(format!("opening input file {:?}", &path))?;
-                    let detector = detect::detector(&mut ictx)?;
-                    let chapters: Vec<Chapter> = detector
-                        .markers(&mut ictx, until, &bar)?
-                        .filter(|cand| {
-                            cand.offset > Duration::from_secs(1) && cand.length > threshold
-                        })
-                        .enumerate()
-                        .take_while(|(n, _)| only.map(|only| n < &only).unwrap_or(true))
-                        .map(|(n, c)| Chapter::new(n, c.offset, format!("{} {}", name, n + 1)))
-                        .collect();
-                    if do_it {
-                        set_chapters(&path, chapters)
-                    } else {
-                        bar.println(format!("would set chapters on {:?}:", &path));
-                        for c in chapters {
-                            bar.println(format!("{}", c));
+                .map(|(path, log, plan)| {
+                    let result = plan.and_then(|plan| {
+                        let outcome =
+                            apply_plan(path, plan, &opts.write, progress.sink(), &log, &locks)?;
+                        if opts.checkpoint_every.is_some() && outcome != Outcome::Outlier {
+                            checkpoint::Checkpoint::remove(path)?;
+                        }
+                        Ok(outcome)
+                    });
+                    match &result {
+                        Ok(outcome) => log.record(format!("done: {:?}", outcome)),
+                        Err(e) => log.record(format!("failed: {:#}", e)),
+                    }
+                    progress.done(Duration::from_secs(0));
+                    (path, result)
+                })
+                .collect::<Vec<_>>();
+            progress.finish();
+            report_by_season(results)
+        }
+        Options::Serve {
+            listen,
+            backlog,
+            paths,
+            opts,
+        } => {
+            let loaded = opts.prepare()?;
+            let backlog =
+                util::dedup_paths(util::expand_dirs(backlog, chapters::SUPPORTED_EXTENSIONS)?);
+            opts.write
+                .check_within(backlog.iter().map(PathBuf::as_path))?;
+            let mut paths = paths.load(None)?;
+            paths.extend(loaded.config.paths.clone());
+            let locks = util::FileLocks::default();
+            let budget = opts.max_memory.map(util::MemoryBudget::new);
+            let trace = match &opts.trace_detections {
+                Some(path) => Some(trace::Trace::create(path)?),
+                None => None,
+            };
+            // files handled so far, and their fingerprints afterwards:
+            let mut handled: HashMap<PathBuf, String> = HashMap::new();
+            webhook::serve(&listen, backlog.into(), |job| {
+                let (file, local) = match job {
+                    webhook::Job::Played(file) => {
+                        let local = paths.to_local(&file);
+                        (file, local)
+                    }
+                    webhook::Job::Queued(path) => (path.clone(), Ok(path)),
+                };
+                let result = local.and_then(|path| {
+                    // paths from webhooks are checked before we even
+                    // look at them:
+                    util::check_within(&opts.write.restrict_to, &path)?;
+                    let fingerprint = util::fingerprint(&path)?;
+                    match handled.get(&path) {
+                        Some(seen) if *seen == fingerprint => {
+                            println!("{:?}: already handled", &path);
+                            return Ok(());
                         }
-                        Ok(())
+                        Some(_) => {
+                            println!("{:?}: was replaced, detecting again", &path);
+                            checkpoint::Checkpoint::remove(&path)?;
+                        }
+                        None => {}
                     }
+                    let log = match &opts.log_dir {
+                        Some(dir) => filelog::FileLog::create(dir, &path)?,
+                        None => filelog::FileLog::disabled(),
+                    };
+                    let scan = Scan {
+                        bar: &ProgressBar::hidden(),
+                        log: &log,
+                        budget: budget.as_ref(),
+                        trace: trace.as_ref(),
+                        media: None,
+                    };
+                    let _dir_locks = opts.write.lock(std::iter::once(path.as_path()))?;
+                    detect_on_demand(&path, &opts, &loaded, &scan, &locks)?;
+                    handled.insert(path.clone(), util::fingerprint(&path)?);
+                    Ok(())
+                });
+                if let Err(e) = result {
+                    service::error(format!("{:?}: {:#}", &file, e));
+                }
+            })
+        }
+        Options::Apply {
+            candidates,
+            plan,
+            learn_into,
+            write,
+        } => {
+            let entries = match (&plan, &candidates) {
+                (Some(plan), _) => planfile::read(plan)?,
+                (None, Some(candidates)) => candidates::read(candidates)?,
+                (None, None) => bail!("no candidates file or plan given"),
+            };
+            write.check_within(
+                entries
+                    .iter()
+                    .flat_map(|e| std::iter::once(&e.path).chain(&e.remux_to))
+                    .map(PathBuf::as_path),
+            )?;
+            let _dir_locks = write.lock(entries.iter().map(|e| e.path.as_path()))?;
+            let locks = util::FileLocks::default();
+            let bar = ProgressBar::new(entries.len() as u64);
+            let results = entries
+                .into_par_iter()
+                .map(|entry| {
+                    let path = entry.path.clone();
+                    let plan = shutdown::check()
+                        .map_err(anyhow::Error::from)
+                        .and_then(|_| Plan::from_entry(entry));
+                    let result = plan.and_then(|plan| {
+                        apply_plan(
+                            &path,
+                            plan,
+                            &write,
+                            &bar,
+                            &filelog::FileLog::disabled(),
+                            &locks,
+                        )
+                    });
+                    bar.inc(1);
+                    (path, result)
+                })
+                .collect::<Vec<_>>();
+            bar.finish_and_clear();
+            if let (Some(config), true) = (&learn_into, write.do_it) {
+                let intros: Vec<_> = results
+                    .iter()
+                    .filter_map(|(path, result)| match result {
+                        Ok(Outcome::Intro(start, end)) => Some((path.as_path(), (*start, *end))),
+                        _ => None,
+                    })
+                    .collect();
+                learned::record(config, &intros)?;
+            }
+            report_by_season(results)
+        }
+        Options::Manifest { paths, output } => {
+            let paths =
+                util::dedup_paths(util::expand_dirs(paths, chapters::SUPPORTED_EXTENSIONS)?);
+            let results: Vec<_> = paths
+                .par_iter()
+                .map(|path| (path, manifest_entry(path)))
+                .collect();
+            // files that can't be read are left out of the manifest; the
+            // report goes to stderr, as it may go to stdout:
+            let mut failed = 0;
+            let mut entries = vec![];
+            for (path, result) in results {
+                match result {
+                    Ok(entry) => entries.push(entry),
+                    Err(e) => {
+                        service::error(format!("{:?}: {:#}", path, e));
+                        failed += 1;
+                    }
+                }
+            }
+            match &output {
+                Some(output) => candidates::write(output, &entries)?,
+                None => candidates::print(&entries)?,
+            }
+            if failed > 0 {
+                bail!("{} files could not be described", failed);
+            }
+            Ok(())
+        }
+        Options::Compare { before, after } => compare::compare(&before, &after),
+        Options::ImportComskip {
+            paths,
+            remux_to_mkv,
+            write,
+        } => {
+            let paths = util::dedup_paths(util::expand_dirs(paths, &extensions(remux_to_mkv))?);
+            let targets: Vec<PathBuf> = paths
+                .iter()
+                .filter_map(|path| remux_target(path, remux_to_mkv))
+                .collect();
+            write.check_within(paths.iter().chain(&targets).map(PathBuf::as_path))?;
+            let _dir_locks = write.lock(paths.iter().map(PathBuf::as_path))?;
+            let locks = util::FileLocks::default();
+            let bar = ProgressBar::new(paths.len() as u64);
+            let results = paths
+                .par_iter()
+                .map(|path| {
+                    let breaks = shutdown::check()
+                        .map_err(anyhow::Error::from)
+                        .and_then(|_| comskip::breaks_for(path));
+                    let result = breaks.and_then(|breaks| {
+                        let duration = util::file_duration(path)?;
+                        let plan = Plan {
+                            chapters: chapters::normalize(comskip::chapters(&breaks), duration),
+                            remux_target: remux_target(path, remux_to_mkv),
+                            intro: None,
+                            detected: false,
+                            outlier: false,
+                            params: None,
+                            unused: vec![],
+                        };
+                        apply_plan(
+                            path,
+                            plan,
+                            &write,
+                            &bar,
+                            &filelog::FileLog::disabled(),
+                            &locks,
+                        )
+                    });
+                    bar.inc(1);
+                    (path, result)
+                })
+                .collect::<Vec<_>>();
+            bar.finish_and_clear();
+            report_by_season(results)
+        }
+        Options::Bench { path, opts } => {
+            let opts = opts.for_tool(&path)?;
+            if opts.backend != detect::Backend::Libav {
+                bail!("bench compares ways of decoding with the linked ffmpeg libraries; it needs --backend=libav");
+            }
+            bench::bench(opts.decoder_threads.unwrap_or(1), |video| {
+                scan_head(&path, &opts, video)
+            })
+        }
+        Options::Calibrate {
+            path,
+            intro,
+            append_to,
+            opts,
+        } => {
+            let opts = opts.for_tool(&path)?;
+            let calibration = calibrate::calibrate(&intro, &opts.intro_length(), |overrides| {
+                Ok(scan_head(&path, &with_overrides(&opts, overrides), &opts.video())?.1)
+            })?
+            .with_context(|| {
+                format!(
+                    "no settings tried find the intro at {:.1}s..{:.1}s",
+                    intro.start.as_secs_f64(),
+                    intro.end.as_secs_f64()
+                )
+            })?;
+            service::info(format!(
+                "{} of {} settings tried find the intro; these find it at {:.1}s..{:.1}s",
+                calibration.hits,
+                calibration.tried,
+                calibration.found.0.as_secs_f64(),
+                calibration.found.1.as_secs_f64()
+            ));
+            let profile = calibration.profile(&path, &intro);
+            match append_to {
+                Some(config) => {
+                    calibrate::append_profile(&config, &path, &profile)?;
+                    service::info(format!("added the profile to {:?}", config));
+                }
+                None => print!("{}", profile),
+            }
+            Ok(())
+        }
+        Options::Sweep { path, param, opts } => {
+            let sweep: sweep::Sweep = param.join(" ").parse().context("--param")?;
+            let opts = opts.for_tool(&path)?;
+            sweep::sweep(&sweep, &opts.intro_length(), |overrides| {
+                Ok(scan_head(&path, &with_overrides(&opts, overrides), &opts.video())?.1)
+            })
+        }
+        Options::Probe {
+            path,
+            signal,
+            until,
+            auto_tune,
+            levels,
+            audio,
+        } => {
+            let audio = audio.preference();
+            let thresholds = if auto_tune {
+                tune::auto_thresholds(&path, &audio)?
+            } else {
+                detect::Thresholds::default()
+            };
+            if levels {
+                probe::levels(&path, until, &thresholds, &audio)
+            } else {
+                probe::probe(&path, until, signal, &thresholds, &audio)
+            }
+        }
+    }
+}
+
+/// What detection made of a file.
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Outcome {
+    /// An intro starting and ending at these offsets.
+    Intro(Duration, Duration),
+
+    /// No pair of breaks looked like an intro (common on premieres and
+    /// finales).
+    NoIntro,
+
+    /// The intro looked too different from the rest of the season, so
+    /// nothing was written.
+    Outlier,
+}
+
+/// The chapters detection came up with for a file, to be written once
+/// all files are scanned.
+struct Plan {
+    chapters: Vec<Chapter>,
+    remux_target: Option<PathBuf>,
+
+    /// Where the intro is, if one was found.
+    intro: Option<(Duration, Duration)>,
+
+    /// Whether the intro was detected (rather than given, or not
+    /// looked for at all).
+    detected: bool,
+
+    outlier: bool,
+
+    /// The settings the chapters were found with, recorded (hashed) in
+    /// the file's tags; unknown for chapters that came from elsewhere.
+    params: Option<String>,
+
+    /// The breaks that didn't make a chapter, for plan files.
+    unused: Vec<planfile::Unused>,
+}
+
+impl Plan {
+    /// What writing this plan will amount to.
+    fn outcome(&self) -> Outcome {
+        if self.outlier {
+            Outcome::Outlier
+        } else if let Some((start, end)) = self.intro {
+            Outcome::Intro(start, end)
+        } else {
+            Outcome::NoIntro
+        }
+    }
+
+    /// Moves the intro to `start`..`end`, along with the chapters at
+    /// its old start and end.
+    fn move_intro(&mut self, start: Duration, end: Duration) {
+        if let Some((old_start, old_end)) = self.intro {
+            for chapter in &mut self.chapters {
+                if chapter.start == old_start {
+                    chapter.start = start;
+                } else if chapter.start == old_end {
+                    chapter.start = end;
+                }
+            }
+        }
+        self.intro = Some((start, end));
+        self.chapters = chapters::normalize(std::mem::take(&mut self.chapters), None);
+    }
+
+    /// Whether the file has an intro, if that's known: it isn't when
+    /// none was given and detection didn't look for one.
+    fn found_intro(&self) -> Option<bool> {
+        match (self.intro, self.detected) {
+            (Some(_), _) => Some(true),
+            (None, true) => Some(false),
+            (None, false) => None,
+        }
+    }
+
+    fn into_entry(self, path: &Path) -> candidates::Entry {
+        let status = match (self.intro, self.detected) {
+            (Some(_), true) => "detected",
+            (Some(_), false) => "given",
+            (None, true) => "no intro found",
+            (None, false) => "no intro looked for",
+        };
+        candidates::Entry {
+            path: path.to_owned(),
+            episode: episode::label(path),
+            status: Some(status.to_string()),
+            remux_to: self.remux_target,
+            intro: self.intro.map(|(start, end)| candidates::Span {
+                start: start.as_secs_f64(),
+                end: end.as_secs_f64(),
+            }),
+            detected: self.detected,
+            outlier: self.outlier,
+            chapters: self
+                .chapters
+                .into_iter()
+                .map(|ch| candidates::Marker {
+                    start: ch.start.as_secs_f64(),
+                    name: ch.name,
+                    pause: ch.pause.map(|pause| pause.as_secs_f64()),
+                })
+                .collect(),
+            checksum: None,
+        }
+    }
+
+    fn from_entry(entry: candidates::Entry) -> anyhow::Result<Self> {
+        if let Some(checksum) = &entry.checksum {
+            if util::fingerprint(&entry.path)? != *checksum {
+                bail!(
+                    "{:?} was replaced since it was scanned, detect its intro again",
+                    &entry.path
+                );
+            }
+        }
+        let secs = |secs: f64| {
+            Duration::try_from_secs_f64(secs).with_context(|| format!("invalid offset {}", secs))
+        };
+        let intro = match entry.intro {
+            Some(span) => Some((secs(span.start)?, secs(span.end)?)),
+            None => None,
+        };
+        let mut chapters = entry
+            .chapters
+            .into_iter()
+            .map(|marker| {
+                let mut chapter = Chapter::new(0, secs(marker.start)?, marker.name);
+                chapter.pause = marker.pause.map(secs).transpose()?;
+                Ok(chapter)
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        if let Some((start, end)) = intro {
+            if end <= start {
+                bail!("{:?}: intro end is not after its start", &entry.path);
+            }
+            // the intro may have been edited by hand, or added to
+            // chapters that don't mark one; the intro chapters follow it:
+            let marked = chapters::existing_intro(&chapters);
+            let moved = marked.map(|m| (m.start, m.end) != (start, end));
+            if moved != Some(false) {
+                chapters = with_intro(chapters, start, end);
+            }
+        }
+        Ok(Plan {
+            chapters: chapters::normalize(chapters, util::file_duration(&entry.path)?),
+            remux_target: entry.remux_to,
+            intro,
+            detected: entry.detected,
+            outlier: entry.outlier,
+            params: None,
+            unused: vec![],
+        })
+    }
+}
+
+/// Describes the chapters and intro that the file at `path` already
+/// has. Intros only marked by tags or an EDL file get intro chapters
+/// added, so applying the entry turns them into chapters.
+fn manifest_entry(path: &Path) -> anyhow::Result<candidates::Entry> {
+    let ictx = ffmpeg::format::input(&path).context("opening input file")?;
+    let mut chapters = existing_chapters(&ictx);
+    let (intro, status) = if let Some(intro) = chapters::existing_intro(&chapters) {
+        (Some(intro), "intro chapters")
+    } else if let Some(intro) = chapters::tagged_intro(&ictx) {
+        chapters.extend(intro_chapters(intro.start, intro.end));
+        (Some(intro), "SKIP_START/SKIP_END tags")
+    } else if let Some(intro) = edl::intro_for(path)? {
+        chapters.extend(intro_chapters(intro.start, intro.end));
+        (Some(intro), "EDL file")
+    } else {
+        (None, "no intro marked")
+    };
+    let plan = Plan {
+        chapters: chapters::normalize(chapters, util::container_duration(&ictx)),
+        remux_target: None,
+        intro: intro.map(|intro| (intro.start, intro.end)),
+        detected: false,
+        outlier: false,
+        params: None,
+        unused: vec![],
+    };
+    let mut entry = plan.into_entry(path);
+    entry.status = Some(status.to_string());
+    Ok(entry)
+}
+
+/// A file, and what detection made of it.
+type Planned<'a> = (&'a PathBuf, filelog::FileLog, anyhow::Result<Plan>);
+
+/// Groups the plans that could be made by the directory of their file
+/// (usually a season).
+fn by_dir<'a, 'b>(
+    plans: &'b mut [Planned<'a>],
+) -> BTreeMap<&'a Path, Vec<(&'b filelog::FileLog, &'b mut Plan)>> {
+    let mut by_dir: BTreeMap<&Path, Vec<_>> = BTreeMap::new();
+    for (path, log, plan) in plans.iter_mut() {
+        if let Ok(plan) = plan {
+            let dir = path.parent().unwrap_or_else(|| Path::new("."));
+            by_dir.entry(dir).or_default().push((&*log, plan));
+        }
+    }
+    by_dir
+}
+
+/// Marks the plans whose detected intro is far off from the others in
+/// the same directory (usually a season).
+fn flag_outliers(plans: &mut [Planned]) {
+    for season in by_dir(plans).values_mut() {
+        let intros: Vec<Option<(Duration, Duration)>> =
+            season.iter().map(|(_, p)| p.intro).collect();
+        for ((_, plan), outlier) in season.iter_mut().zip(season::outliers(&intros)) {
+            plan.outlier = outlier && plan.detected;
+        }
+    }
+}
+
+/// Moves the detected intros of each directory's files to the median
+/// one where they're within `tolerance` of it, and marks the others as
+/// outliers, so that every episode of a season skips the same way.
+fn enforce_consistency(plans: &mut [Planned], tolerance: Duration) {
+    for season in by_dir(plans).values_mut() {
+        let intros: Vec<Option<(Duration, Duration)>> =
+            season.iter().map(|(_, p)| p.intro).collect();
+        for ((log, plan), consistency) in
+            season.iter_mut().zip(season::reconcile(&intros, tolerance))
+        {
+            if !plan.detected {
+                continue;
+            }
+            match consistency {
+                season::Consistency::Keep => {}
+                season::Consistency::Snap(start, end) => {
+                    log.record(format!(
+                        "moved intro to the season's median, {:.3}s to {:.3}s",
+                        start.as_secs_f64(),
+                        end.as_secs_f64()
+                    ));
+                    plan.move_intro(start, end);
+                    // where the season has it, it's no outlier anymore:
+                    plan.outlier = false;
+                }
+                season::Consistency::Irreconcilable => {
+                    log.record("intro too far off the season's median to move it there");
+                    plan.outlier = true;
+                }
+            }
+        }
+    }
+}
+
+/// How the files of one season (or directory) fared.
+#[derive(Default)]
+struct GroupReport {
+    handled: usize,
+    no_intro: usize,
+    outliers: usize,
+    failed: usize,
+    interrupted: usize,
+    intros: Vec<(Duration, Duration)>,
+}
+
+/// Prints how many files of each season were handled, grouping files
+/// without a recognizable episode number by their directory.
+fn report_by_season<P: AsRef<Path>>(
+    results: Vec<(P, anyhow::Result<Outcome>)>,
+) -> anyhow::Result<()> {
+    let mut groups: BTreeMap<String, GroupReport> = BTreeMap::new();
+    for (path, result) in &results {
+        let path = path.as_ref();
+        let key = match episode::series_and_season(path) {
+            Some((series, season)) => format!("{} season {}", series, season),
+            None => format!("{:?}", path.parent().unwrap_or_else(|| Path::new("."))),
+        };
+        let group = groups.entry(key).or_default();
+        match result {
+            Ok(Outcome::Outlier) => group.outliers += 1,
+            Ok(outcome) => {
+                group.handled += 1;
+                match *outcome {
+                    Outcome::Intro(start, end) => group.intros.push((start, end)),
+                    _ => group.no_intro += 1,
+                }
+            }
+            Err(e) if e.is::<shutdown::Interrupted>() => group.interrupted += 1,
+            Err(e) => {
+                service::error(format!("{:?}: {:#}", path, e));
+                group.failed += 1;
+            }
+        }
+    }
+    let mut total_failed = 0;
+    let mut total_interrupted = 0;
+    for (key, group) in groups {
+        let total = group.handled + group.outliers + group.failed + group.interrupted;
+        let mut line = format!(
+            "{}: {} of {} files handled ({}%, {} without an intro), {} skipped as outliers, {} failed",
+            key,
+            group.handled,
+            total,
+            group.handled * 100 / total,
+            group.no_intro,
+            group.outliers,
+            group.failed
+        );
+        if group.interrupted > 0 {
+            line.push_str(&format!(", {} not started", group.interrupted));
+        }
+        if !group.intros.is_empty() {
+            let start = season::median(group.intros.iter().map(|(s, _)| *s).collect());
+            let length = season::median(group.intros.iter().map(|(s, e)| *e - *s).collect());
+            line.push_str(&format!(
+                "; median intro at {} running {}",
+                humantime::format_duration(Duration::from_secs(start.as_secs())),
+                humantime::format_duration(Duration::from_secs(length.as_secs()))
+            ));
+        }
+        println!("{}", line);
+        total_failed += group.failed;
+        total_interrupted += group.interrupted;
+    }
+    if total_interrupted > 0 {
+        bail!(
+            "interrupted: {} files not started, {} failed",
+            total_interrupted,
+            total_failed
+        );
+    }
+    if total_failed > 0 {
+        bail!("{} files could not be handled", total_failed);
+    }
+    Ok(())
+}
+
+impl DetectOptions {
+    /// Checks what structopt can't (and what the linked ffmpeg can do),
+    /// and reads the --config file, if one was given.
+    fn prepare(&self) -> anyhow::Result<Loaded> {
+        if self.intro_length().is_empty() {
+            bail!("--intro-min is longer than --intro-max");
+        }
+        if self.backend == detect::Backend::Libav {
+            for missing in detect::missing_filters()? {
+                service::warn(missing);
+            }
+        }
+        let config = match &self.config {
+            Some(path) => config::Config::load(path)?,
+            None => config::Config::default(),
+        };
+        Ok(Loaded { config })
+    }
+
+    /// `self` with the settings of the profile for `path`'s show and of
+    /// its sidecar applied.
+    fn for_file(&self, loaded: &Loaded, path: &Path) -> anyhow::Result<DetectOptions> {
+        let overrides = sidecar::Overrides::load(path)?;
+        Ok(match loaded.config.profile_for(path) {
+            Some(profile) => with_overrides(&with_overrides(self, profile), &overrides),
+            None => with_overrides(self, &overrides),
+        })
+    }
+
+    /// Prepares the options of a command that scans the single file
+    /// `path` to tune detection (bench, calibrate and sweep), which
+    /// decodes with all CPUs by default.
+    fn for_tool(self, path: &Path) -> anyhow::Result<DetectOptions> {
+        let loaded = self.prepare()?;
+        let mut opts = self.for_file(&loaded, path)?;
+        opts.decoder_threads = opts
+            .decoder_threads
+            .or_else(|| Some(rayon::current_num_threads()));
+        Ok(opts)
+    }
+
+    /// How to decode video for detection.
+    fn video(&self) -> detect::VideoSettings {
+        detect::VideoSettings::with_threads(self.decoder_threads.unwrap_or(1))
+    }
+
+    /// How long the intro (or, for anime, each theme) is expected to be.
+    fn intro_length(&self) -> RangeInclusive<Duration> {
+        let default = match self.profile {
+            Some(profile) => profile.theme_length(),
+            None => intro::DEFAULT_INTRO_LENGTH,
+        };
+        let min = self.intro_min.unwrap_or(*default.start());
+        let max = self.intro_max.unwrap_or(*default.end());
+        min..=max
+    }
+
+    /// Picks the intro of `path` among its `breaks`: the one closest to
+    /// its show's confirmed intros if its profile has them, the first
+    /// one of a fitting length otherwise.
+    fn find_intro(
+        &self,
+        loaded: &Loaded,
+        path: &Path,
+        breaks: &[Duration],
+    ) -> Option<(Duration, Duration)> {
+        match loaded.expected_intro(path) {
+            Some(expected) => intro::find_expected_intro(breaks, &expected),
+            None => intro::find_intro(breaks, &self.intro_length()),
+        }
+    }
+
+    /// Describes the settings that affect which chapters detection
+    /// comes up with for `path`.
+    fn params(&self, loaded: &Loaded, path: &Path) -> String {
+        format!(
+            "until={:?} threshold={:?} only={:?} name={:?} profile={:?} intro={:?} exclude={:?} \
+             anchor={:?} keyframe={} exact-pts={} full={} compilation={:?} scene-cuts={} prefilter={} auto-tune={} silence-noise={:?} black-pixel={:?} merge={:?} learned={:?} backend={:?}",
+            self.until,
+            self.threshold,
+            self.only,
+            self.name,
+            self.profile,
+            self.intro_length(),
+            self.exclude,
+            self.anchor,
+            self.snap_to_keyframe,
+            self.exact_pts,
+            self.full_chapters,
+            self.compilation,
+            self.scene_cuts,
+            self.prefilter,
+            self.auto_tune,
+            self.silence_noise,
+            self.black_pixel,
+            self.merge_existing,
+            loaded.expected_intro(path),
+            self.backend,
+        )
+    }
+
+    /// Whether `path` needs handling, given --only-if-older-than-version.
+    fn is_outdated(&self, path: &Path) -> bool {
+        let than = match &self.only_if_older_than_version {
+            Some(than) => than,
+            None => return true,
+        };
+        match provenance::written_version(path) {
+            Ok(Some(version)) if !provenance::older_than(&version, than) => {
+                println!("{:?}: written by version {}, skipping", path, version);
+                false
+            }
+            // if we can't tell, detection will say what's wrong:
+            _ => true,
+        }
+    }
+
+    /// Whether `path` is to be skipped because --trust-existing takes an
+    /// earlier run's word that it has no intro.
+    fn known_without_intro(&self, path: &Path) -> bool {
+        if !self.trust_existing {
+            return false;
+        }
+        match provenance::without_intro(path) {
+            Ok(true) => {
+                println!("{:?}: tagged as having no intro, skipping", path);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// What `DetectOptions::prepare` loads for a run.
+struct Loaded {
+    /// The contents of --config.
+    config: config::Config,
+}
+
+impl Loaded {
+    /// What the intro of `path` should look like, going by the confirmed
+    /// intros in its show's profile (as saved by "apply --learn-into").
+    fn expected_intro(&self, path: &Path) -> Option<intro::Expected> {
+        learned::expected(&self.config.profile_for(path)?.learned)
+    }
+}
+
+/// Returns `opts` with the settings from a file's sidecar applied.
+fn with_overrides(opts: &DetectOptions, overrides: &sidecar::Overrides) -> DetectOptions {
+    let mut opts = opts.clone();
+    if let Some(until) = overrides.until {
+        opts.until = until;
+    }
+    if let Some(threshold) = overrides.threshold {
+        opts.threshold = threshold;
+    }
+    if let Some(only) = overrides.only {
+        opts.only = Some(only);
+    }
+    if let Some(silence_noise) = overrides.silence_noise {
+        opts.silence_noise = Some(silence_noise);
+    }
+    if let Some(black_pixel) = overrides.black_pixel {
+        opts.black_pixel = Some(black_pixel);
+    }
+    if let Some(name) = &overrides.name {
+        opts.name = name.clone();
+    }
+    if let Some(intro) = overrides.intro {
+        opts.force_intro = Some(intro);
+    }
+    if let Some(exclude) = &overrides.exclude {
+        opts.exclude.extend(exclude);
+    }
+    opts
+}
+
+/// Where a file's scan reports to, and what it shares with the others.
+#[derive(Clone, Copy)]
+struct Scan<'a> {
+    bar: &'a ProgressBar,
+    log: &'a filelog::FileLog,
+    budget: Option<&'a util::MemoryBudget>,
+    trace: Option<&'a trace::Trace>,
+
+    /// Where to count the media that the scans work through, if its
+    /// speed is shown.
+    media: Option<&'a progress::MediaCount>,
+}
+
+/// The extensions of the files to work on: those chapters can be
+/// written to, and with `remux_to_mkv`, those that can be remuxed into
+/// a file that can hold them.
+fn extensions(remux_to_mkv: bool) -> Vec<&'static str> {
+    if remux_to_mkv {
+        chapters::SUPPORTED_EXTENSIONS
+            .iter()
+            .chain(remux::REMUX_SOURCE_EXTENSIONS)
+            .copied()
+            .collect()
+    } else {
+        chapters::SUPPORTED_EXTENSIONS.to_vec()
+    }
+}
+
+/// The .mkv to remux `path` into and put its chapters in, with
+/// `remux_to_mkv` and a container that can't hold chapters.
+fn remux_target(path: &Path, remux_to_mkv: bool) -> Option<PathBuf> {
+    if remux_to_mkv && chapters::Writer::for_path(path).is_err() {
+        Some(path.with_extension("mkv"))
+    } else {
+        None
+    }
+}
+
+/// Detects breaks in a file and works out the chapters to give it.
+fn plan_chapters(
+    path: &Path,
+    opts: &DetectOptions,
+    loaded: &Loaded,
+    scan: &Scan,
+) -> anyhow::Result<Plan> {
+    let Scan { bar, log, .. } = *scan;
+    let remux_target = remux_target(path, opts.remux_to_mkv);
+    util::check_within(&opts.write.restrict_to, path)?;
+    if let Some(target) = &remux_target {
+        util::check_within(&opts.write.restrict_to, target)?;
+    }
+    if opts.write.do_it && remux_target.is_none() {
+        // fail before spending time on detection if we can't write the result:
+        chapters::Writer::for_path(path)?;
+    }
+    if chapters::uses_ordered_chapters(path)? {
+        bail!("uses ordered chapters or linked segments, so detected offsets wouldn't match what players show; not touching it");
+    }
+    let mut opts = opts.for_file(loaded, path)?;
+    if let Some(settle) = opts.settle {
+        bar.set_message("waiting for file to settle");
+        util::wait_until_stable(path, settle)?;
+    }
+    let mut ictx =
+        ffmpeg::format::input(&path).context(format!("opening input file {:?}", &path))?;
+    let duration = util::container_duration(&ictx);
+    if opts.trust_existing && opts.force_intro.is_none() {
+        opts.force_intro = chapters::existing_intro(&existing_chapters(&ictx))
+            .or_else(|| chapters::tagged_intro(&ictx));
+        if opts.force_intro.is_none() {
+            opts.force_intro = edl::intro_for(path)?;
+        }
+        if opts.force_intro.is_some() {
+            log.say(
+                bar,
+                format!("{:?} already has its intro marked, keeping it", &path),
+            );
+        }
+    }
+    let opts = &opts;
+    let until = match (&opts.compilation, duration) {
+        (Some(_), Some(duration)) => duration,
+        _ => opts.until.for_duration(duration),
+    };
+    bar.set_length(until.as_millis() as u64);
+    if let Some(dir) = &opts.dump_features {
+        bar.set_message("dumping features");
+        features::dump(path, until, &opts.audio.preference(), dir)?;
+        bar.set_message("");
+    }
+    let zero = Duration::from_secs(0);
+    let episodes = episode::episode_count(path);
+    let mut intro = opts.force_intro.map(|forced| (forced.start, forced.end));
+    let mut found: Vec<Break> = vec![];
+    let mut chapters: Vec<Chapter> = match (opts.force_intro, opts.profile) {
+        (Some(forced), _) if opts.full_chapters => {
+            chapter_skeleton(&[forced.start, forced.end], duration)
+        }
+        (Some(forced), _) => with_intro(existing_chapters(&ictx), forced.start, forced.end),
+        (None, Some(profile)) => {
+            let length = opts.intro_length();
+            let head = detect_breaks(path, &mut ictx, zero..until, opts, scan)?;
+            found.extend(&head);
+            let head = starts_of(&head);
+            let opening = opts.find_intro(loaded, path, &head);
+            let (ending, preview) = match duration {
+                Some(duration) => {
+                    bar.set_length(duration.as_millis() as u64);
+                    let from = duration
+                        .saturating_sub(intro::ANIME_ENDING_WINDOW)
+                        .max(until);
+                    let tail = detect_breaks(path, &mut ictx, from..duration, opts, scan)?;
+                    found.extend(&tail);
+                    let tail = starts_of(&tail);
+                    let ending = intro::find_intro(&tail, &length);
+                    let preview = profile.preview_length().and_then(|preview_length| {
+                        let after = ending.map(|(_, end)| end);
+                        intro::find_preview(&tail, after, duration, &preview_length)
+                    });
+                    (ending, preview)
+                }
+                None => (None, None),
+            };
+            intro = opening;
+            anime_chapters(opening, ending, preview)
+        }
+        (None, None) if opts.compilation.is_some() => {
+            let template = opts.compilation.as_deref().unwrap_or_default();
+            let starts = detect_breaks(path, &mut ictx, zero..until, opts, scan)?;
+            found.extend(&starts);
+            let starts = starts_of(&starts);
+            let segments = major_breaks(&starts, MIN_SEGMENT_LENGTH);
+            log.record(format!("{} segments", segments.len() + 1));
+            std::iter::once(zero)
+                .chain(segments)
+                .enumerate()
+                .map(|(n, start)| {
+                    let number = opts.write.numbering().label(n);
+                    Chapter::new(n, start, template.replace("{n}", &number))
                 })
                 .collect()
         }
+        (None, None) if episodes > 1 && duration.is_some() && !opts.full_chapters => {
+            let duration = duration.unwrap_or_default();
+            bar.set_length(duration.as_millis() as u64);
+            let length = opts.intro_length();
+            let mut intros = vec![];
+            for part in 0..episodes {
+                // later episodes start around their share of the file;
+                // look for their intro from a bit before that:
+                let from = if part == 0 {
+                    zero
+                } else {
+                    (duration * part as u32 / episodes as u32).saturating_sub(until / 2)
+                };
+                let to = (from + until).min(duration);
+                let breaks = detect_breaks(path, &mut ictx, from..to, opts, scan)?;
+                found.extend(&breaks);
+                let breaks = starts_of(&breaks);
+                intros.push(intro::find_intro(&breaks, &length));
+            }
+            log.record(format!(
+                "{} episodes in one file: intros at {:?}",
+                episodes, &intros
+            ));
+            intro = intros[0];
+            multi_episode_chapters(&intros)
+        }
+        (None, None) => {
+            let breaks = detect_breaks(path, &mut ictx, zero..until, opts, scan)?;
+            found.extend(&breaks);
+            let mut starts = starts_of(&breaks);
+            if starts.is_empty() && opts.scene_cuts {
+                bar.set_message("looking at scene cuts");
+                starts = scenes::boundaries(
+                    &features::measure(path, until, &opts.audio.preference())?.video,
+                );
+                starts.retain(|start| !opts.exclude.contains(*start));
+                bar.set_message("");
+                log.say(
+                    bar,
+                    format!(
+                        "{:?}: no breaks found, using changes in the pace of scene cuts",
+                        &path
+                    ),
+                );
+            }
+            intro = opts.find_intro(loaded, path, &starts);
+            if opts.full_chapters {
+                // the credits are past the usual scan window, so the
+                // end of the file gets scanned for them too:
+                if let Some(duration) = duration {
+                    let from = credits_start(duration).max(until);
+                    if from < duration {
+                        bar.set_length(duration.as_millis() as u64);
+                        let tail = detect_breaks(path, &mut ictx, from..duration, opts, scan)?;
+                        found.extend(&tail);
+                        starts.extend(starts_of(&tail));
+                    }
+                }
+                chapter_skeleton(&starts, duration)
+            } else {
+                starts
+                    .into_iter()
+                    .enumerate()
+                    .map(|(n, start)| {
+                        let number = opts.write.numbering().label(n);
+                        Chapter::new(n, start, format!("{} {}", opts.name, number))
+                    })
+                    .collect()
+            }
+        }
+    };
+    match intro {
+        Some((start, end)) => log.record(format!(
+            "intro from {:.3}s to {:.3}s",
+            start.as_secs_f64(),
+            end.as_secs_f64()
+        )),
+        None => log.say(bar, format!("{:?}: no intro detected", &path)),
+    }
+    if let Some(zero_name) = &opts.chapter_at_zero {
+        if chapters
+            .first()
+            .map(|c| c.start > Duration::from_secs(0))
+            .unwrap_or(true)
+        {
+            chapters.insert(
+                0,
+                Chapter::new(0, Duration::from_secs(0), zero_name.clone()),
+            );
+        }
+    }
+    let mut chapters = tag_chapters(chapters, &found);
+    // a forced intro already kept the existing chapters, and replaces
+    // the intro chapters among them:
+    if let Some(window) = opts.merge_existing.filter(|_| opts.force_intro.is_none()) {
+        let (merged, suppressed) = chapters::merge(existing_chapters(&ictx), chapters, window);
+        if suppressed > 0 {
+            log.say(
+                bar,
+                format!(
+                    "{:?}: left out {} chapters close to existing ones",
+                    &path, suppressed
+                ),
+            );
+        }
+        chapters = merged;
     }
+    let chapters = chapters::normalize(chapters, duration);
+    let mut unused: Vec<planfile::Unused> = found
+        .iter()
+        .filter(|b| {
+            !chapters
+                .iter()
+                .any(|c| c.start.max(b.start) - c.start.min(b.start) < chapters::DUPLICATE_WINDOW)
+        })
+        .map(|b| planfile::Unused {
+            start: b.start,
+            pause: b.length,
+            signals: b.signals,
+        })
+        .collect();
+    unused.sort_by_key(|u| u.start);
+    Ok(Plan {
+        chapters,
+        remux_target,
+        intro,
+        detected: opts.force_intro.is_none() && opts.compilation.is_none(),
+        outlier: false,
+        params: Some(opts.params(loaded, path)),
+        unused,
+    })
+}
+
+/// Detects and writes chapters for a file that was just started
+/// playing, unless it already has its intro marked (or an earlier run
+/// tagged it as having none).
+fn detect_on_demand(
+    path: &Path,
+    opts: &DetectOptions,
+    loaded: &Loaded,
+    scan: &Scan,
+    locks: &util::FileLocks,
+) -> anyhow::Result<()> {
+    let marked = {
+        let ictx =
+            ffmpeg::format::input(&path).context(format!("opening input file {:?}", &path))?;
+        chapters::existing_intro(&existing_chapters(&ictx)).is_some()
+            || chapters::tagged_intro(&ictx).is_some()
+    };
+    if marked || edl::intro_for(path)?.is_some() {
+        scan.log
+            .say(scan.bar, format!("{:?}: intro already marked", &path));
+        return Ok(());
+    }
+    if provenance::without_intro(path)? {
+        scan.log.say(
+            scan.bar,
+            format!("{:?}: tagged as having no intro, not detecting", &path),
+        );
+        return Ok(());
+    }
+    if !opts.is_outdated(path) {
+        return Ok(());
+    }
+    println!("{:?}: detecting", &path);
+    let plan = plan_chapters(path, opts, loaded, scan)?;
+    let outcome = apply_plan(path, plan, &opts.write, scan.bar, scan.log, locks)?;
+    scan.log.record(format!("done: {:?}", outcome));
+    println!("{:?}: {:?}", &path, outcome);
+    Ok(())
 }
 
-#[derive(PartialEq, Debug)]
-struct Chapter {
-    id: usize,
+/// Writes (or with --do-it not given, shows) the planned chapters. This
+/// happens even after Ctrl-C: it's the scans that stop, and their plans
+/// are still worth writing.
+fn apply_plan(
+    path: &Path,
+    plan: Plan,
+    opts: &WriteOptions,
+    bar: &ProgressBar,
+    log: &filelog::FileLog,
+    locks: &util::FileLocks,
+) -> anyhow::Result<Outcome> {
+    util::check_within(&opts.restrict_to, path)?;
+    if let Some(target) = &plan.remux_target {
+        util::check_within(&opts.restrict_to, target)?;
+    }
+    if plan.outlier && !opts.apply_outliers {
+        let (start, end) = plan.intro.unwrap_or_default();
+        log.say(
+            bar,
+            format!(
+            "{:?}: intro at {} (running {}) differs a lot from the rest of its season, skipping",
+            &path,
+            humantime::format_duration(start),
+            humantime::format_duration(end - start)
+        ),
+        );
+        return Ok(Outcome::Outlier);
+    }
+    let file_tags = match opts.provenance_tags {
+        true => provenance::tags(plan.params.as_deref(), plan.found_intro()),
+        false => vec![],
+    };
+    let mut chapters = plan.chapters;
+    if let Some(shift) = opts.offset_adjust {
+        let duration = ffmpeg::format::input(&path)
+            .ok()
+            .and_then(|ictx| util::container_duration(&ictx));
+        chapters = chapters::shifted(chapters, shift, duration);
+    }
+    for c in &chapters {
+        log.record(format!(
+            "chapter at {:.3}s{}: {}",
+            c.start.as_secs_f64(),
+            c.pause
+                .map(|pause| format!(" (after {:.3}s of pause)", pause.as_secs_f64()))
+                .unwrap_or_default(),
+            c.name
+        ));
+    }
+    let written = match (opts.do_it, plan.remux_target) {
+        (true, Some(target)) => locks.with_lock(&target, || {
+            remux::remux_into_new(
+                path,
+                &target,
+                &chapters,
+                &file_tags,
+                opts.temp_dir.as_deref(),
+            )
+        }),
+        (true, None) => locks
+            .with_lock(path, || {
+                set_chapters(
+                    path,
+                    chapters,
+                    &file_tags,
+                    &opts.mkvpropedit(),
+                    opts.temp_dir.as_deref(),
+                )
+            })
+            .map(|written| match written {
+                chapters::Written::Unchanged => {
+                    log.say(bar, format!("{:?}: chapters unchanged, not writing", &path))
+                }
+                chapters::Written::Changed(warnings) => {
+                    for warning in warnings {
+                        log.say(bar, format!("{:?}: mkvpropedit: {}", &path, warning));
+                    }
+                }
+            }),
+        (false, target) => {
+            match target {
+                Some(target) => log.say(
+                    bar,
+                    format!("would remux {:?} into {:?} with chapters:", &path, &target),
+                ),
+                None => log.say(bar, format!("would set chapters on {:?}:", &path)),
+            }
+            let duration = ffmpeg::format::input(&path)
+                .ok()
+                .and_then(|ictx| util::container_duration(&ictx));
+            log.say(
+                bar,
+                opts.preview_format
+                    .render(&chapters, duration, opts.numbering()),
+            );
+            Ok(())
+        }
+    };
+    written?;
+    Ok(match plan.intro {
+        Some((start, end)) => Outcome::Intro(start, end),
+        None => Outcome::NoIntro,
+    })
+}
+
+/// A break that looks like a chapter start, and how it was found.
+#[derive(Clone, Copy, Debug)]
+struct Break {
     start: Duration,
-    name: String,
+    length: Duration,
+    signals: &'static str,
+}
+
+fn starts_of(breaks: &[Break]) -> Vec<Duration> {
+    breaks.iter().map(|b| b.start).collect()
 }
 
-impl Chapter {
-    fn from_ffmpeg(id: usize, chapter: ffmpeg::format::chapter::Chapter) -> Self {
-        Chapter {
-            id,
-            start: util::to_duration(chapter.start(), chapter.time_base()),
-            name: chapter
-                .metadata()
-                .get("title")
-                .unwrap_or("untitled")
-                .to_string(),
+/// Tags the chapters that start at one of the `found` breaks with how
+/// it was detected (and notes how long its pause was), and all of them
+/// with our version.
+fn tag_chapters(chapters: Vec<Chapter>, found: &[Break]) -> Vec<Chapter> {
+    chapters
+        .into_iter()
+        .map(|mut ch| {
+            let ch = match found.iter().find(|b| b.start == ch.start) {
+                Some(b) => {
+                    ch.pause = Some(b.length);
+                    ch.with_tag("ICA_BREAK_LENGTH", format!("{:.3}", b.length.as_secs_f64()))
+                        .with_tag("ICA_SIGNALS", b.signals.to_string())
+                }
+                None => ch,
+            };
+            ch.with_tag(provenance::VERSION_TAG, provenance::VERSION.to_string())
+        })
+        .collect()
+}
+
+/// Scans the beginning of `path` as detection would with `opts`
+/// (already applied to the file, see `DetectOptions::for_file`),
+/// decoding video with `video`, and returns how much of the file that
+/// covered along with the breaks it would consider.
+fn scan_head(
+    path: &Path,
+    opts: &DetectOptions,
+    video: &detect::VideoSettings,
+) -> anyhow::Result<(Duration, Vec<detect::Candidate>)> {
+    let mut ictx =
+        ffmpeg::format::input(&path).context(format!("opening input file {:?}", &path))?;
+    let duration = util::container_duration(&ictx);
+    let until = opts.until.for_duration(duration);
+    let bar = ProgressBar::hidden();
+    let log = filelog::FileLog::disabled();
+    let scan = Scan {
+        bar: &bar,
+        log: &log,
+        budget: None,
+        trace: None,
+        media: None,
+    };
+    let zero = Duration::from_secs(0);
+    let breaks = scan_breaks(path, &mut ictx, zero..until, opts, &scan, video)?;
+    let scanned = duration.map_or(until, |duration| duration.min(until));
+    let candidates = breaks
+        .iter()
+        .map(|b| detect::Candidate::new(b.start, b.length))
+        .collect();
+    Ok((scanned, candidates))
+}
+
+/// Scans the `span` of `ictx` and returns the breaks in it that look
+/// like chapter starts. Only scans of the beginning of a
+/// file (starting at zero) get checkpointed.
+fn detect_breaks(
+    path: &Path,
+    ictx: &mut ffmpeg::format::context::Input,
+    span: Range<Duration>,
+    opts: &DetectOptions,
+    scan: &Scan,
+) -> anyhow::Result<Vec<Break>> {
+    scan_breaks(path, ictx, span, opts, scan, &opts.video())
+}
+
+/// Does what `detect_breaks` does, decoding video with `video`.
+fn scan_breaks(
+    path: &Path,
+    ictx: &mut ffmpeg::format::context::Input,
+    span: Range<Duration>,
+    opts: &DetectOptions,
+    scan: &Scan,
+    video: &detect::VideoSettings,
+) -> anyhow::Result<Vec<Break>> {
+    let Scan {
+        bar,
+        log,
+        budget,
+        trace,
+        media,
+    } = *scan;
+    let until = span.end;
+    // how far the scan gets, unless it stops early:
+    let mut reached = util::container_duration(ictx).map_or(until, |d| d.min(until));
+    let checkpoint_every = opts
+        .checkpoint_every
+        .filter(|_| span.start == Duration::from_secs(0));
+    let checkpoint = match checkpoint_every {
+        Some(_) => checkpoint::Checkpoint::load(path, until)?,
+        None => None,
+    };
+    if span.start > Duration::from_secs(0) {
+        let seek_to = span.start.as_micros() as i64;
+        ictx.seek(seek_to, ..seek_to)?;
+    }
+    if let Some(checkpoint) = &checkpoint {
+        log.say(
+            bar,
+            format!(
+                "resuming {:?} from {}",
+                &path,
+                humantime::format_duration(checkpoint.position)
+            ),
+        );
+        let seek_to = checkpoint
+            .position
+            .saturating_sub(checkpoint::RESUME_MARGIN);
+        let seek_to = seek_to.as_micros() as i64;
+        ictx.seek(seek_to, ..seek_to)?;
+    }
+    let mut origin = util::stream_origin(ictx);
+    // reserved before anything is decoded, and held until scanning is
+    // done:
+    let _reservation = budget
+        .filter(|_| opts.backend == detect::Backend::Libav || opts.auto_tune)
+        .map(|budget| {
+            bar.set_message("waiting for memory");
+            let reservation = budget.reserve(detect::estimated_memory(ictx, video.threads));
+            bar.set_message("");
+            reservation
+        });
+    let audio = opts.audio.preference();
+    let mut thresholds = if opts.auto_tune {
+        bar.set_message("measuring noise floor and brightness");
+        let thresholds = tune::auto_thresholds(path, &audio)?;
+        bar.set_message("");
+        thresholds
+    } else {
+        detect::Thresholds::default()
+    };
+    if let Some(silence_noise) = opts.silence_noise {
+        thresholds.silence_db = silence_noise;
+    }
+    if let Some(black_pixel) = opts.black_pixel {
+        thresholds.black_pixel = black_pixel;
+    }
+    log.record(format!(
+        "scanning {}..{} with silence below {}dB, black below {}",
+        humantime::format_duration(span.start),
+        humantime::format_duration(until),
+        thresholds.silence_db,
+        thresholds.black_pixel
+    ));
+    let mut markers = match opts.backend {
+        detect::Backend::Cli => {
+            // the command's timestamps already start at 0:
+            origin = 0.0;
+            let streams = detect::scanned_streams(ictx, &audio)?;
+            ffmpeg_cli::markers(
+                &opts.ffmpeg_path,
+                path,
+                span.clone(),
+                streams,
+                &thresholds,
+                bar,
+            )?
         }
+        detect::Backend::Libav => {
+            let detector = detect::detector(ictx, video, &thresholds, &audio)?;
+            let mut markers = if opts.pipeline {
+                detector.pipelined_markers(ictx, until, bar)?
+            } else {
+                detector.markers(ictx, until, bar)?
+            };
+            if let Some(checkpoint) = checkpoint {
+                markers.resume_from(checkpoint);
+            }
+            if let Some(trace) = trace {
+                markers.trace_to(trace, path);
+            }
+            if opts.exact_pts {
+                markers.keep_frame_times();
+            }
+            if opts.prefilter {
+                bar.set_message("looking for small packets");
+                markers.only_within(prefilter::quiet_windows(path, until)?);
+                bar.set_message("");
+            }
+            if let Some(every) = checkpoint_every {
+                markers.save_checkpoints(checkpoint::Checkpointer::new(path, until, every)?);
+            }
+            detect::Markers::Libav(markers)
+        }
+    };
+    let mut candidates: Vec<detect::Candidate> = vec![];
+    let mut offsets: Vec<Duration> = vec![];
+    for cand in markers.by_ref() {
+        // --exclude spans are given as players show the file:
+        let shown = util::displayed_offset(cand.offset, origin);
+        let ignored = shown <= Duration::from_secs(1)
+            || cand.length <= opts.threshold
+            || opts.exclude.contains(shown);
+        log.record(format!(
+            "break at {:.3}s, lasting {:.3}s{}",
+            shown.as_secs_f64(),
+            cand.length.as_secs_f64(),
+            if ignored { " (ignored)" } else { "" }
+        ));
+        if ignored {
+            continue;
+        }
+        let scanned = cand.offset;
+        offsets.push(cand.at(opts.anchor));
+        candidates.push(cand);
+        if opts
+            .only
+            .map(|only| candidates.len() >= only)
+            .unwrap_or(false)
+        {
+            reached = scanned;
+            break;
+        }
+        let scanned_enough = opts
+            .min_scan
+            .is_none_or(|min_scan| scanned >= span.start + min_scan);
+        if opts.stop_after_intro
+            && scanned_enough
+            && intro::find_intro(&offsets, &opts.intro_length()).is_some()
+        {
+            bar.set_message("found the intro");
+            reached = scanned;
+            break;
+        }
+    }
+    markers.check()?;
+    if let Some(media) = media {
+        media.add(reached.saturating_sub(span.start));
+    }
+    if markers.used_video_only() {
+        log.say(
+            bar,
+            format!("{:?}: no silence found, using black frames alone", &path),
+        );
     }
+    let signals = if markers.used_video_only() {
+        "black"
+    } else {
+        "silence+black"
+    };
+    let breaks: Vec<Break> = candidates
+        .into_iter()
+        .map(|c| {
+            let at = c.at(opts.anchor);
+            let offset = if opts.snap_to_keyframe {
+                markers.preceding_keyframe(at).unwrap_or(at)
+            } else if opts.exact_pts {
+                markers.nearest_frame(at).unwrap_or(at)
+            } else {
+                at
+            };
+            let start = util::displayed_offset(offset, origin);
+            let start = if opts.exact_pts {
+                util::ceil_to_millis(start)
+            } else {
+                start
+            };
+            Break {
+                start,
+                length: c.length,
+                signals,
+            }
+        })
+        .collect();
+    Ok(breaks)
+}
+
+/// Returns the "Start of intro" and "End of intro" chapters.
+fn intro_chapters(start: Duration, end: Duration) -> Vec<Chapter> {
+    vec![
+        Chapter::new(0, start, "Start of intro".to_string()),
+        Chapter::new(1, end, "End of intro".to_string()),
+    ]
+}
 
-    fn new(id: usize, start: Duration, name: String) -> Self {
-        Chapter { id, start, name }
+/// Returns `chapters` with their intro chapters (if any) replaced by
+/// ones for the intro from `start` to `end`. The intro chapters come
+/// first, so `chapters::normalize` keeps them over others at the same
+/// time.
+fn with_intro(chapters: Vec<Chapter>, start: Duration, end: Duration) -> Vec<Chapter> {
+    let mut with_intro = intro_chapters(start, end);
+    with_intro.extend(
+        chapters
+            .into_iter()
+            .filter(|c| !chapters::is_intro_chapter(c)),
+    );
+    with_intro
+}
+
+/// Keeps only the breaks that start a segment at least `min_length`
+/// after the previous one (or the start of the file).
+fn major_breaks(breaks: &[Duration], min_length: Duration) -> Vec<Duration> {
+    let mut kept: Vec<Duration> = vec![];
+    for start in breaks {
+        let previous = kept.last().copied().unwrap_or_default();
+        if *start >= previous + min_length {
+            kept.push(*start);
+        }
     }
+    kept
 }
 
-impl fmt::Display for Chapter {
-    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        let secs = self.start.as_secs();
-        writeln!(
-            f,
-            "CHAPTER{:0>2}={:0>2}:{:0>2}:{:0>2}.{}",
-            self.id,
-            secs / 60 / 60,
-            secs / 60,
-            secs % 60,
-            self.start.subsec_millis()
-        )?;
-        write!(f, "CHAPTER{:0>2}NAME={}", self.id, self.name)?;
-        Ok(())
+/// Returns "Intro (Part N)" and "End of intro (Part N)" chapters for
+/// the intro of each episode in a file that holds several, where one
+/// was found.
+fn multi_episode_chapters(intros: &[Option<(Duration, Duration)>]) -> Vec<Chapter> {
+    let mut chapters = vec![];
+    for (part, intro) in intros.iter().enumerate() {
+        if let Some((start, end)) = intro {
+            chapters.push(Chapter::new(
+                chapters.len(),
+                *start,
+                format!("Intro (Part {})", part + 1),
+            ));
+            chapters.push(Chapter::new(
+                chapters.len(),
+                *end,
+                format!("End of intro (Part {})", part + 1),
+            ));
+        }
     }
+    chapters
 }
 
-fn existing_chapters(path: &Path) -> anyhow::Result<Vec<Chapter>> {
-    let ictx = ffmpeg::format::input(&path)?;
-    Ok(ictx
-        .chapters()
-        .enumerate()
-        .map(|(i, chapter)| Chapter::from_ffmpeg(i, chapter))
-        .collect())
+/// Returns chapters for the start and end of an anime episode's
+/// opening (OP) and ending (ED) themes, and the preview of the next
+/// episode, where those were found.
+fn anime_chapters(
+    opening: Option<(Duration, Duration)>,
+    ending: Option<(Duration, Duration)>,
+    preview: Option<Duration>,
+) -> Vec<Chapter> {
+    let mut chapters = vec![];
+    for (theme, span) in &[("OP", opening), ("ED", ending)] {
+        if let Some((start, end)) = span {
+            chapters.push(Chapter::new(
+                chapters.len(),
+                *start,
+                format!("Start of {}", theme),
+            ));
+            chapters.push(Chapter::new(
+                chapters.len(),
+                *end,
+                format!("End of {}", theme),
+            ));
+        }
+    }
+    if let Some(preview) = preview {
+        // replaces "End of ED" if the preview starts right there:
+        chapters.retain(|c| c.start != preview);
+        chapters.push(Chapter::new(chapters.len(), preview, "Preview".to_string()));
+    }
+    chapters
 }
 
-fn adjust_tags_on(base: &Path, title_info: TitleInfo) -> anyhow::Result<()> {
-    let input = base.join(title_info.location.strip_prefix("/media")?);
-    let mut chapters = existing_chapters(&input)?;
-    let (theme_start, theme_end) = (
-        Duration::from_secs_f64(title_info.theme_start),
-        Duration::from_secs_f64(title_info.theme_end),
-    );
+/// Where the final tenth of a file of `duration`, in which its credits
+/// are expected, starts.
+fn credits_start(duration: Duration) -> Duration {
+    duration.mul_f64(0.9)
+}
+
+/// Names the detected breaks as a chapter list for the whole episode:
+/// a cold open from the start of the file, the intro, the acts that
+/// follow it and, if the last break falls into the final tenth of the
+/// file, the credits.
+fn chapter_skeleton(breaks: &[Duration], duration: Option<Duration>) -> Vec<Chapter> {
+    let mut chapters = vec![Chapter::new(
+        0,
+        Duration::from_secs(0),
+        "Cold open".to_string(),
+    )];
+    for (n, start) in breaks.iter().enumerate() {
+        let is_credits =
+            n > 0 && n == breaks.len() - 1 && duration.is_some_and(|d| *start >= credits_start(d));
+        let name = match n {
+            0 => "Intro".to_string(),
+            _ if is_credits => "Credits".to_string(),
+            n => format!("Act {}", n),
+        };
+        chapters.push(Chapter::new(chapters.len(), *start, name));
+    }
+    chapters
+}
+
+fn adjust_tags_on(
+    paths: &pathmap::PathMap,
+    title_info: title_info::TitleInfo,
+    snap_end_to_sound: Option<f64>,
+    audio: &detect::AudioPreference,
+) -> anyhow::Result<()> {
+    let input = paths.to_local(&title_info.location)?;
+    let mut ictx = ffmpeg::format::input(&input).context(format!("opening {:?}", &input))?;
+    let mut chapters = existing_chapters(&ictx);
+    let starts: Vec<Duration> = chapters.iter().map(|c| c.start).collect();
+    let (theme_start, mut theme_end) =
+        title_info.theme_range(&starts, util::container_duration(&ictx))?;
+    if let Some(threshold) = snap_end_to_sound {
+        if let Some(loud) = loudness::first_loud_frame(
+            &mut ictx,
+            theme_end,
+            LOUDNESS_SCAN_WINDOW,
+            threshold,
+            audio,
+        )? {
+            theme_end = loud;
+        }
+    }
 
     chapters.push(Chapter::new(
         chapters.len(),
@@ -211,35 +2509,15 @@ fn adjust_tags_on(base: &Path, title_info: TitleInfo) -> anyhow::Result<()> {
         theme_end,
         "End of intro".to_string(),
     ));
-    set_chapters(&input, chapters)
-}
-
-fn set_chapters(
-    mkv_file: &Path,
-    chapters: impl IntoIterator<Item = Chapter>,
-) -> anyhow::Result<()> {
-    let tmpfile = Temp::new_file()?;
-    let f = File::create(tmpfile.as_path())?;
-    let mut w = BufWriter::new(f);
-    for ch in chapters.into_iter() {
-        writeln!(&mut w, "{}", ch)?;
-    }
-    w.into_inner()?.sync_all()?;
-
-    let output = Command::new("mkvpropedit")
-        .arg(&mkv_file)
-        .arg("--chapters")
-        .arg(tmpfile.as_path())
-        .output()?;
-    if !output.status.success() {
-        bail!(
-            "unsuccessful for {:?} - mkv chapter contents:\n{:?}\n\nmkvpropedit stdout:\n{:?}\nstderr:\n{:?}",
-            mkv_file,
-            fs::read_to_string(tmpfile.as_path()).unwrap_or("unreadable".to_string()),
-            output.stdout,
-            output.stderr
-        );
+    match set_chapters(&input, chapters, &[], &Default::default(), None)? {
+        chapters::Written::Unchanged => {
+            println!("{:?}: chapters unchanged, not writing", &input)
+        }
+        chapters::Written::Changed(warnings) => {
+            for warning in warnings {
+                println!("{:?}: mkvpropedit: {}", &input, warning);
+            }
+        }
     }
-
     Ok(())
 }
@@ -0,0 +1,99 @@
+//! Reading the values that `silencedetect` and `blackdetect` attach to
+//! filtered frames. This only uses the standard library, so the fuzz
+//! targets in `fuzz/` can build it on its own.
+use std::time::Duration;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub(crate) enum PauseMatch {
+    None,
+    Start(Duration),
+    End(Duration),
+}
+
+impl PauseMatch {
+    pub(crate) fn at(&self) -> Option<Duration> {
+        match self {
+            PauseMatch::None => None,
+            PauseMatch::Start(at) | PauseMatch::End(at) => Some(*at),
+        }
+    }
+}
+
+/// Interprets the start/end metadata keys that `silencedetect` and
+/// `blackdetect` attach to a filtered frame shown at `timestamp`.
+///
+/// The values come straight out of ffmpeg and may be missing,
+/// non-numeric or arrive out of order (e.g. an end marker without a
+/// start); anything that doesn't form a sensible start/end sequence is
+/// ignored rather than treated as a fatal condition.
+pub(crate) fn pause_from(
+    inside_pause: &mut bool,
+    start: Option<&str>,
+    end: Option<&str>,
+    timestamp: Option<Duration>,
+) -> PauseMatch {
+    let start = start.and_then(parse_number);
+    let end = end.and_then(parse_number);
+    match (*inside_pause, start, end, timestamp) {
+        (_, None, None, _) => PauseMatch::None,
+        (_, Some(_), Some(_), _) => PauseMatch::None, // skip super short silences
+        (true, None, Some(_), Some(ts)) => {
+            *inside_pause = false;
+            PauseMatch::End(ts)
+        }
+        (false, Some(_), None, Some(ts)) => {
+            *inside_pause = true;
+            PauseMatch::Start(ts)
+        }
+        _ => PauseMatch::None,
+    }
+}
+
+/// Parses a numeric value as written into frame metadata by the
+/// detection filters, rejecting anything that isn't a finite number.
+pub(crate) fn parse_number(value: &str) -> Option<f64> {
+    value.trim().parse::<f64>().ok().filter(|v| v.is_finite())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pauses_start_and_end() {
+        let mut inside = false;
+        let at = Some(Duration::from_secs(5));
+        assert_eq!(
+            pause_from(&mut inside, Some("4.9"), None, at),
+            PauseMatch::Start(Duration::from_secs(5))
+        );
+        assert!(inside);
+        assert_eq!(
+            pause_from(&mut inside, None, Some("0.5"), at),
+            PauseMatch::End(Duration::from_secs(5))
+        );
+        assert!(!inside);
+    }
+
+    #[test]
+    fn ignores_malformed_values() {
+        let mut inside = false;
+        let at = Some(Duration::from_secs(5));
+        for value in &["", "abc", "NaN", "inf", "-inf", "1e999"] {
+            assert_eq!(
+                pause_from(&mut inside, Some(value), None, at),
+                PauseMatch::None
+            );
+        }
+        // an end without a start, and a start without a time:
+        assert_eq!(
+            pause_from(&mut inside, None, Some("1"), at),
+            PauseMatch::None
+        );
+        assert_eq!(
+            pause_from(&mut inside, Some("1"), None, None),
+            PauseMatch::None
+        );
+        assert!(!inside);
+    }
+}
@@ -0,0 +1,229 @@
+//! Offsets into a file, as CSV rows, plan files and options give them.
+//! This doesn't use ffmpeg, so the fuzz targets in `fuzz/` can build it
+//! on its own.
+use anyhow::{bail, Context};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Parses an offset given in seconds (`83.5`), as `MM:SS.mmm` or
+/// `HH:MM:SS.mmm`, or as a humantime duration (`1m23s`).
+pub(crate) fn parse_offset(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let mut secs = 0.0;
+    for part in value.split(':') {
+        match part.parse::<f64>() {
+            Ok(part) => secs = secs * 60.0 + part,
+            Err(_) => return humantime::parse_duration(value).ok(),
+        }
+    }
+    Duration::try_from_secs_f64(secs).ok()
+}
+
+/// Deserializes an offset in seconds from a number, or from a string
+/// in any form `parse_offset` takes.
+pub(crate) fn offset_secs<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<f64, D::Error> {
+    struct Offset;
+
+    impl<'de> serde::de::Visitor<'de> for Offset {
+        type Value = f64;
+
+        fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            write!(f, "an offset in seconds, as HH:MM:SS.mmm or like 1m32s")
+        }
+
+        fn visit_f64<E: serde::de::Error>(self, value: f64) -> Result<f64, E> {
+            Ok(value)
+        }
+
+        fn visit_i64<E: serde::de::Error>(self, value: i64) -> Result<f64, E> {
+            Ok(value as f64)
+        }
+
+        fn visit_u64<E: serde::de::Error>(self, value: u64) -> Result<f64, E> {
+            Ok(value as f64)
+        }
+
+        fn visit_str<E: serde::de::Error>(self, value: &str) -> Result<f64, E> {
+            parse_offset(value)
+                .map(|offset| offset.as_secs_f64())
+                .ok_or_else(|| E::custom(format!("invalid offset {:?}", value)))
+        }
+    }
+
+    deserializer.deserialize_any(Offset)
+}
+
+/// An offset into a file: either absolute, or relative to the start of
+/// one of its existing chapters, counted from 1 as on a DVD
+/// (`chapter:2`, `chapter:2+00:05`, `chapter:3-1.5s`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Offset {
+    Absolute(Duration),
+    FromChapter {
+        chapter: usize,
+        after: Duration,
+        before: Duration,
+    },
+}
+
+impl Offset {
+    /// Returns the absolute offset, looking up the chapter it's
+    /// relative to among the `chapter_starts` of the file.
+    pub(crate) fn resolve(&self, chapter_starts: &[Duration]) -> anyhow::Result<Duration> {
+        let (chapter, after, before) = match *self {
+            Offset::Absolute(offset) => return Ok(offset),
+            Offset::FromChapter {
+                chapter,
+                after,
+                before,
+            } => (chapter, after, before),
+        };
+        let mut starts = chapter_starts.to_vec();
+        starts.sort();
+        let start = match chapter.checked_sub(1).and_then(|i| starts.get(i)) {
+            Some(start) => *start,
+            None => bail!("no chapter {} (the file has {})", chapter, starts.len()),
+        };
+        (start + after)
+            .checked_sub(before)
+            .with_context(|| format!("{} is before the start of the file", self))
+    }
+}
+
+impl fmt::Display for Offset {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Offset::Absolute(offset) => write!(f, "{:.3}", offset.as_secs_f64()),
+            Offset::FromChapter {
+                chapter,
+                after,
+                before,
+            } => {
+                write!(f, "chapter:{}", chapter)?;
+                if *after > Duration::from_secs(0) {
+                    write!(f, "+{:.3}", after.as_secs_f64())?;
+                }
+                if *before > Duration::from_secs(0) {
+                    write!(f, "-{:.3}", before.as_secs_f64())?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl FromStr for Offset {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let relative = match s.trim().strip_prefix("chapter:") {
+            None => {
+                return parse_offset(s)
+                    .map(Offset::Absolute)
+                    .with_context(|| format!("invalid offset {:?}", s))
+            }
+            Some(relative) => relative,
+        };
+        let (chapter, delta) = match relative.find(['+', '-']) {
+            Some(at) => relative.split_at(at),
+            None => (relative, ""),
+        };
+        let chapter = chapter
+            .trim()
+            .parse()
+            .with_context(|| format!("invalid chapter number in {:?}", s))?;
+        let (mut after, mut before) = (Duration::from_secs(0), Duration::from_secs(0));
+        if let Some(sign) = delta.chars().next() {
+            let delta =
+                parse_offset(&delta[1..]).with_context(|| format!("invalid offset {:?}", s))?;
+            match sign {
+                '+' => after = delta,
+                _ => before = delta,
+            }
+        }
+        Ok(Offset::FromChapter {
+            chapter,
+            after,
+            before,
+        })
+    }
+}
+
+/// Offsets are read as seconds, or from strings in any form `FromStr`
+/// takes.
+impl<'de> serde::Deserialize<'de> for Offset {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl<'de> serde::de::Visitor<'de> for Visitor {
+            type Value = Offset;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "an offset, like 92.5, 01:32.5, 1m32s or chapter:2+00:05")
+            }
+
+            fn visit_f64<E: serde::de::Error>(self, value: f64) -> Result<Offset, E> {
+                Duration::try_from_secs_f64(value)
+                    .map(Offset::Absolute)
+                    .map_err(E::custom)
+            }
+
+            fn visit_i64<E: serde::de::Error>(self, value: i64) -> Result<Offset, E> {
+                self.visit_f64(value as f64)
+            }
+
+            fn visit_u64<E: serde::de::Error>(self, value: u64) -> Result<Offset, E> {
+                Ok(Offset::Absolute(Duration::from_secs(value)))
+            }
+
+            fn visit_str<E: serde::de::Error>(self, value: &str) -> Result<Offset, E> {
+                value.parse().map_err(|e| E::custom(format!("{:#}", e)))
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_offsets() {
+        let ms = Duration::from_millis;
+        assert_eq!(parse_offset("83.5"), Some(ms(83_500)));
+        assert_eq!(parse_offset(" 01:23.5 "), Some(ms(83_500)));
+        assert_eq!(parse_offset("1:02:03"), Some(ms(3_723_000)));
+        assert_eq!(parse_offset("1m23s"), Some(ms(83_000)));
+        for invalid in &["", "abc", "-5", "1:xx", "NaN"] {
+            assert_eq!(parse_offset(invalid), None, "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn resolves_offsets() {
+        let secs = Duration::from_secs;
+        let chapters = [secs(300), secs(0)];
+        let resolve = |s: &str| s.parse::<Offset>().unwrap().resolve(&chapters).unwrap();
+        assert_eq!(resolve("92.5"), Duration::from_millis(92_500));
+        assert_eq!(resolve("01:32"), secs(92));
+        assert_eq!(resolve("chapter:2"), secs(300));
+        assert_eq!(resolve("chapter:2+00:05"), secs(305));
+        assert_eq!(resolve("chapter:2-1m"), secs(240));
+        assert_eq!(
+            "chapter:2+5".parse::<Offset>().unwrap().to_string(),
+            "chapter:2+5.000"
+        );
+        for invalid in &["soon", "chapter:", "chapter:two", "chapter:2+x"] {
+            assert!(invalid.parse::<Offset>().is_err(), "{:?}", invalid);
+        }
+        for unresolvable in &["chapter:0", "chapter:3", "chapter:1-5"] {
+            let offset: Offset = unresolvable.parse().unwrap();
+            assert!(offset.resolve(&chapters).is_err(), "{:?}", unresolvable);
+        }
+    }
+}
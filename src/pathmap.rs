@@ -0,0 +1,118 @@
+//! Translate between the paths a media server sees and the paths the
+//! same files have where we run.
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// One server path prefix and the local path it's mounted at.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Rule {
+    server: PathBuf,
+    local: PathBuf,
+}
+
+impl FromStr for Rule {
+    type Err = anyhow::Error;
+
+    /// Parses `SERVER=LOCAL`, e.g. `/media=/Volumes/Media`.
+    fn from_str(s: &str) -> Result<Self> {
+        let (server, local) = s.split_once('=').context("expected SERVER=LOCAL")?;
+        Ok(Rule {
+            server: PathBuf::from(server),
+            local: PathBuf::from(local),
+        })
+    }
+}
+
+/// A set of rules; paths are translated by the rule with the longest
+/// matching prefix. Without any rules, paths are the same on both sides.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PathMap {
+    rules: Vec<Rule>,
+}
+
+/// Rewrites `path` from under one of the `(from, to)` prefixes to under
+/// the other, picking the longest `from` that matches.
+fn translate<'a>(
+    path: &Path,
+    pairs: impl Iterator<Item = (&'a Path, &'a Path)>,
+) -> Option<PathBuf> {
+    pairs
+        .filter_map(|(from, to)| {
+            let rest = path.strip_prefix(from).ok()?;
+            Some((from.components().count(), to.join(rest)))
+        })
+        .max_by_key(|(len, _)| *len)
+        .map(|(_, translated)| translated)
+}
+
+impl PathMap {
+    pub(crate) fn new(rules: Vec<Rule>) -> Self {
+        PathMap { rules }
+    }
+
+    /// Adds the rules in a TOML file of `"SERVER" = "LOCAL"` lines.
+    pub(crate) fn load(&mut self, path: &Path) -> Result<()> {
+        let text = fs::read_to_string(path).context(format!("reading {:?}", path))?;
+        let table: BTreeMap<String, String> =
+            toml::from_str(&text).context(format!("parsing {:?}", path))?;
+        self.extend(table);
+        Ok(())
+    }
+
+    /// Adds rules from a table of server prefixes and local prefixes.
+    pub(crate) fn extend(&mut self, table: BTreeMap<String, String>) {
+        self.rules
+            .extend(table.into_iter().map(|(server, local)| Rule {
+                server: server.into(),
+                local: local.into(),
+            }));
+    }
+
+    /// Where a file the server knows as `path` is found locally.
+    pub(crate) fn to_local(&self, path: &Path) -> Result<PathBuf> {
+        if self.rules.is_empty() {
+            return Ok(path.to_owned());
+        }
+        translate(
+            path,
+            self.rules
+                .iter()
+                .map(|r| (r.server.as_path(), r.local.as_path())),
+        )
+        .with_context(|| format!("no path mapping for {:?}", path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn translates_by_the_longest_prefix() {
+        let map = PathMap::new(vec![
+            "/media=/Volumes/Media".parse().unwrap(),
+            "/media/tv=/Volumes/TV".parse().unwrap(),
+        ]);
+        let local = |path: &str| map.to_local(Path::new(path)).ok();
+        assert_eq!(
+            local("/media/tv/Show/S01E01.mkv"),
+            Some(PathBuf::from("/Volumes/TV/Show/S01E01.mkv"))
+        );
+        assert_eq!(
+            local("/media/movies/Film.mkv"),
+            Some(PathBuf::from("/Volumes/Media/movies/Film.mkv"))
+        );
+        // prefixes match whole components only:
+        assert_eq!(local("/mediaserver/Film.mkv"), None);
+    }
+
+    #[test]
+    fn keeps_paths_without_rules() {
+        let path = Path::new("/media/Film.mkv");
+        assert_eq!(PathMap::default().to_local(path).unwrap(), path);
+        assert!("no-equals-sign".parse::<Rule>().is_err());
+    }
+}
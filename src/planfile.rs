@@ -0,0 +1,209 @@
+//! Plan files: the chapters detection came up with, as TOML meant for
+//! editing by hand before "apply --plan" writes them. Unlike files saved
+//! with --emit, they list the breaks that didn't make a chapter, as
+//! comments that can be uncommented to use them after all.
+use crate::candidates::{Entry, Marker};
+use anyhow::{Context, Result};
+use serde_derive::Deserialize;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+const HEADER: &str = "\
+# Chapters planned by \"detect-silence --plan\". Edit them as you see fit,
+# then write them with \"apply --plan FILE --do-it\" (leave out --do-it
+# to see what would be written). Breaks that didn't make a chapter are
+# listed between the chapters as comments; uncomment one to use it.
+";
+
+/// A break detection found that didn't make a chapter.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Unused {
+    pub(crate) start: Duration,
+    pub(crate) pause: Duration,
+    pub(crate) signals: &'static str,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PlanFile {
+    #[serde(default)]
+    file: Vec<Entry>,
+}
+
+/// Quotes `s` as a TOML basic string.
+pub(crate) fn quote(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            '\r' => quoted.push_str("\\r"),
+            c if c.is_control() => {
+                let _ = write!(quoted, "\\u{:04X}", c as u32);
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn quote_path(path: &Path) -> Result<String> {
+    let path = path
+        .to_str()
+        .with_context(|| format!("{:?} can't be written to a plan file", path))?;
+    Ok(quote(path))
+}
+
+fn marker(marker: &Marker) -> String {
+    let mut line = format!(
+        "{{ start = {:.3}, name = {}",
+        marker.start,
+        quote(&marker.name)
+    );
+    if let Some(pause) = marker.pause {
+        let _ = write!(line, ", pause = {:.3}", pause);
+    }
+    line.push_str(" },");
+    line
+}
+
+fn write_entry(out: &mut String, entry: &Entry, unused: &[Unused]) -> Result<()> {
+    out.push_str("\n[[file]]\n");
+    let _ = writeln!(out, "path = {}", quote_path(&entry.path)?);
+    if let Some(episode) = &entry.episode {
+        let _ = writeln!(out, "episode = {}", quote(episode));
+    }
+    if let Some(status) = &entry.status {
+        let _ = writeln!(out, "status = {}", quote(status));
+    }
+    if let Some(remux_to) = &entry.remux_to {
+        let _ = writeln!(out, "remux_to = {}", quote_path(remux_to)?);
+    }
+    if let Some(checksum) = &entry.checksum {
+        let _ = writeln!(out, "checksum = {}", quote(checksum));
+    }
+    let _ = writeln!(out, "detected = {}", entry.detected);
+    let _ = writeln!(out, "outlier = {}", entry.outlier);
+    if let Some(intro) = &entry.intro {
+        let _ = writeln!(
+            out,
+            "intro = {{ start = {:.3}, end = {:.3} }}",
+            intro.start, intro.end
+        );
+    }
+    out.push_str("chapters = [\n");
+    let mut unused = unused.iter().peekable();
+    for chapter in &entry.chapters {
+        while let Some(gap) = unused.next_if(|u| u.start.as_secs_f64() < chapter.start) {
+            write_unused(out, gap);
+        }
+        let _ = writeln!(out, "    {}", marker(chapter));
+    }
+    for gap in unused {
+        write_unused(out, gap);
+    }
+    out.push_str("]\n");
+    Ok(())
+}
+
+fn write_unused(out: &mut String, unused: &Unused) {
+    let _ = writeln!(out, "    # not used ({}):", unused.signals);
+    let marker = Marker {
+        start: unused.start.as_secs_f64(),
+        name: "Chapter".to_string(),
+        pause: Some(unused.pause.as_secs_f64()),
+    };
+    let _ = writeln!(out, "    # {}", self::marker(&marker));
+}
+
+/// The text of a plan file for the entries, each along with the breaks
+/// that didn't make it into its chapters (sorted by start).
+fn render(entries: &[(Entry, Vec<Unused>)]) -> Result<String> {
+    let mut out = HEADER.to_string();
+    for (entry, unused) in entries {
+        write_entry(&mut out, entry, unused).with_context(|| format!("{:?}", &entry.path))?;
+    }
+    Ok(out)
+}
+
+/// Writes a plan file for the entries, as `render` does.
+pub(crate) fn write(path: &Path, entries: &[(Entry, Vec<Unused>)]) -> Result<()> {
+    fs::write(path, render(entries)?).context(format!("writing {:?}", path))
+}
+
+pub(crate) fn read(path: &Path) -> Result<Vec<Entry>> {
+    let text = fs::read_to_string(path).context(format!("reading {:?}", path))?;
+    let plan: PlanFile = toml::from_str(&text).context(format!("parsing {:?}", path))?;
+    Ok(plan.file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::candidates::Span;
+    use std::path::PathBuf;
+
+    /// An entry with every field set to something other than its
+    /// default, so that a field `write_entry` leaves out is missed.
+    fn full_entry() -> Entry {
+        Entry {
+            path: PathBuf::from("/tv/Some \"Show\"/Some Show S01E02.mkv"),
+            episode: Some("S01E02".to_string()),
+            status: Some("detected".to_string()),
+            remux_to: Some(PathBuf::from("/tv/Some Show S01E02.mkv")),
+            intro: Some(Span {
+                start: 61.5,
+                end: 92.25,
+            }),
+            detected: true,
+            outlier: true,
+            chapters: vec![
+                Marker {
+                    start: 0.0,
+                    name: "Start".to_string(),
+                    pause: None,
+                },
+                Marker {
+                    start: 61.5,
+                    name: "Start of intro".to_string(),
+                    pause: Some(0.5),
+                },
+            ],
+            checksum: Some("1234abcd".to_string()),
+        }
+    }
+
+    #[test]
+    fn reads_what_it_writes() {
+        let entry = full_entry();
+        let written = serde_json::to_value(&entry).unwrap();
+        for (field, value) in written.as_object().unwrap() {
+            assert!(
+                !value.is_null() && *value != serde_json::json!(false),
+                "{} isn't set in the test entry",
+                field
+            );
+        }
+        let unused = vec![Unused {
+            start: Duration::from_secs(30),
+            pause: Duration::from_millis(250),
+            signals: "silence+black",
+        }];
+        let text = render(&[(entry, unused)]).unwrap();
+        let plan: PlanFile = toml::from_str(&text).unwrap();
+        assert_eq!(plan.file.len(), 1);
+        assert_eq!(serde_json::to_value(&plan.file[0]).unwrap(), written);
+
+        // and the unused break, once uncommented:
+        let text = text.replace("    # {", "    {");
+        let plan: PlanFile = toml::from_str(&text).unwrap();
+        let starts: Vec<f64> = plan.file[0].chapters.iter().map(|m| m.start).collect();
+        assert_eq!(starts, vec![0.0, 30.0, 61.5]);
+    }
+}
@@ -0,0 +1,119 @@
+//! Guess where breaks might be from packet sizes alone, without
+//! decoding anything.
+use crate::util::to_duration;
+use anyhow::{Context, Result};
+use ffmpeg::media;
+use std::ops::Range;
+use std::path::Path;
+use std::time::Duration;
+
+/// Video packets smaller than this fraction of the median packet size
+/// look like they could be (mostly) black frames.
+const SMALL_VIDEO_FRACTION: f64 = 0.2;
+
+/// Audio packets smaller than this fraction of the median packet size
+/// look like they could be silence.
+const SMALL_AUDIO_FRACTION: f64 = 0.5;
+
+/// Audio packet sizes vary less than this factor on constant bitrate
+/// encodes, where they say nothing about the content.
+const MIN_AUDIO_SIZE_SPREAD: f64 = 1.5;
+
+/// How much to scan on either side of a run of small packets.
+const WINDOW_PADDING: Duration = Duration::from_secs(3);
+
+struct PacketInfo {
+    at: Duration,
+    size: usize,
+}
+
+fn median_size(packets: &[PacketInfo]) -> f64 {
+    let mut sizes: Vec<usize> = packets.iter().map(|p| p.size).collect();
+    sizes.sort_unstable();
+    sizes.get(sizes.len() / 2).copied().unwrap_or(0) as f64
+}
+
+/// Returns the offsets of packets smaller than `fraction` of the median.
+fn small_packets(packets: &[PacketInfo], fraction: f64) -> Vec<Duration> {
+    let limit = median_size(packets) * fraction;
+    packets
+        .iter()
+        .filter(|p| (p.size as f64) < limit)
+        .map(|p| p.at)
+        .collect()
+}
+
+/// Merges padded spans around `offsets` (which must be sorted) into
+/// non-overlapping windows.
+fn windows_around(offsets: &[Duration]) -> Vec<Range<Duration>> {
+    let mut windows: Vec<Range<Duration>> = vec![];
+    for at in offsets {
+        let start = at.saturating_sub(WINDOW_PADDING);
+        let end = *at + WINDOW_PADDING;
+        match windows.last_mut() {
+            Some(last) if start <= last.end => last.end = end,
+            _ => windows.push(start..end),
+        }
+    }
+    windows
+}
+
+/// Reads the packets of `path` (up to `until`) and returns the time
+/// windows where small video packets — and, if audio packet sizes vary
+/// at all, small audio packets — suggest a break. Window starts are
+/// moved back to the preceding video keyframe, so decoding can start
+/// there.
+pub(crate) fn quiet_windows(path: &Path, until: Duration) -> Result<Vec<Range<Duration>>> {
+    let mut ictx =
+        ffmpeg::format::input(&path).context(format!("opening input file {:?}", &path))?;
+    let video_index = ictx
+        .streams()
+        .best(media::Type::Video)
+        .context("finding the video stream")?
+        .index();
+    let audio_index = ictx.streams().best(media::Type::Audio).map(|s| s.index());
+
+    let mut video = vec![];
+    let mut audio = vec![];
+    let mut keyframes = vec![];
+    for (stream, packet) in ictx.packets() {
+        let at = match packet.pts() {
+            Some(pts) => to_duration(pts, stream.time_base()),
+            None => continue,
+        };
+        if at >= until {
+            break;
+        }
+        let info = PacketInfo {
+            at,
+            size: packet.size(),
+        };
+        if stream.index() == video_index {
+            if packet.is_key() {
+                keyframes.push(at);
+            }
+            video.push(info);
+        } else if Some(stream.index()) == audio_index {
+            audio.push(info);
+        }
+    }
+    video.sort_by_key(|p| p.at);
+    keyframes.sort();
+
+    let mut windows = windows_around(&small_packets(&video, SMALL_VIDEO_FRACTION));
+    let spread = {
+        let min = audio.iter().map(|p| p.size).min().unwrap_or(0) as f64;
+        let max = audio.iter().map(|p| p.size).max().unwrap_or(0) as f64;
+        max / min.max(1.0)
+    };
+    if spread >= MIN_AUDIO_SIZE_SPREAD {
+        let quiet_audio = small_packets(&audio, SMALL_AUDIO_FRACTION);
+        windows.retain(|window| quiet_audio.iter().any(|at| window.contains(at)));
+    }
+    for window in windows.iter_mut() {
+        if let Some(key) = keyframes.iter().rev().find(|k| **k <= window.start) {
+            window.start = *key;
+        }
+    }
+    Ok(windows)
+}
@@ -0,0 +1,234 @@
+//! Print what one of the detection filters reports, frame by frame, to
+//! find out why a break was missed before touching thresholds.
+use crate::detect::{
+    audio_decoder, audio_filter, video_decoder, video_filter, AudioPreference, Thresholds,
+    VideoSettings,
+};
+use crate::features;
+use crate::frames::{Decoder, Filtered};
+use crate::util::to_duration;
+use anyhow::{bail, Context, Result};
+use ffmpeg::frame;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// The signals detection (and calibration) looks at.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Signal {
+    Silence,
+    Black,
+    Loudness,
+    Scenes,
+}
+
+impl FromStr for Signal {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "silence" => Ok(Signal::Silence),
+            "black" => Ok(Signal::Black),
+            "loudness" => Ok(Signal::Loudness),
+            "scenes" => Ok(Signal::Scenes),
+            _ => bail!(
+                "unknown signal {:?} (known: silence, black, loudness, scenes)",
+                s
+            ),
+        }
+    }
+}
+
+impl Signal {
+    fn is_audio(self) -> bool {
+        matches!(self, Signal::Silence | Signal::Loudness)
+    }
+
+    /// The filter chain producing the signal, as detection (or
+    /// calibration) sets it up.
+    fn filter(self, thresholds: &Thresholds) -> String {
+        match self {
+            Signal::Silence => thresholds.silence_filter(),
+            Signal::Black => thresholds.black_filter(),
+            Signal::Loudness => "astats=metadata=1:reset=1".to_string(),
+            Signal::Scenes => "select=gte(scene\\,0)".to_string(),
+        }
+    }
+
+    /// The frame metadata keys the filter reports the signal in start
+    /// with this.
+    fn key_prefix(self) -> &'static str {
+        match self {
+            Signal::Silence => "lavfi.silence_",
+            Signal::Black => "lavfi.black_",
+            Signal::Loudness => "lavfi.astats.Overall.RMS_",
+            Signal::Scenes => "lavfi.scene_score",
+        }
+    }
+}
+
+/// Prints the time and metadata values of a filtered frame, if it
+/// carries any for `signal`.
+fn print_event(signal: Signal, at: Duration, frame: &frame::Frame) {
+    let prefix = signal.key_prefix();
+    let values: Vec<String> = frame
+        .metadata()
+        .iter()
+        .filter(|(key, _)| key.starts_with(prefix))
+        .map(|(key, value)| format!("{}={}", key.trim_start_matches("lavfi."), value))
+        .collect();
+    if !values.is_empty() {
+        println!("{:>10.3}s  {}", at.as_secs_f64(), values.join(" "));
+    }
+}
+
+/// Runs the first `window` of `path` through the filter for `signal`
+/// (listening to the `audio` track for audio signals) and prints every
+/// event it reports.
+pub(crate) fn probe(
+    path: &Path,
+    window: Duration,
+    signal: Signal,
+    thresholds: &Thresholds,
+    audio: &AudioPreference,
+) -> Result<()> {
+    let mut ictx =
+        ffmpeg::format::input(&path).context(format!("opening input file {:?}", &path))?;
+    let spec = signal.filter(thresholds);
+    if signal.is_audio() {
+        let (index, decoder) = audio_decoder(&ictx, audio)?;
+        let graph = audio_filter(&decoder, &spec)?;
+        print_events(
+            &mut ictx,
+            Filtered::new(index, decoder, graph),
+            signal,
+            window,
+        )
+    } else {
+        let threads = rayon::current_num_threads();
+        let (index, decoder) = video_decoder(&ictx, &VideoSettings::with_threads(threads))?;
+        let graph = video_filter(&decoder, None, &spec)?;
+        print_events(
+            &mut ictx,
+            Filtered::new(index, decoder, graph),
+            signal,
+            window,
+        )
+    }
+}
+
+/// Prints the events for `signal` in the `frames` of `ictx`, up to
+/// `window`.
+fn print_events<D: Decoder>(
+    ictx: &mut ffmpeg::format::context::Input,
+    mut frames: Filtered<D>,
+    signal: Signal,
+    window: Duration,
+) -> Result<()> {
+    let time_base = frames.time_base();
+    for (stream, mut packet) in ictx.packets() {
+        if stream.index() != frames.stream() {
+            continue;
+        }
+        let mut past_window = false;
+        frames.feed(stream.time_base(), &mut packet, |frame| {
+            let at = match frame.timestamp() {
+                Some(ts) => to_duration(ts, time_base),
+                None => return,
+            };
+            past_window |= at > window;
+            if !past_window {
+                print_event(signal, at, frame);
+            }
+        })?;
+        if past_window {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// How much of the file each character of a `levels` timeline covers.
+const LEVELS_BUCKET: Duration = Duration::from_secs(5);
+
+/// How many buckets go on one line of a `levels` timeline.
+const LEVELS_PER_LINE: usize = 60;
+
+/// The range of audio levels a `levels` timeline shows, in dBFS.
+const LEVELS_DB_RANGE: (f64, f64) = (-70.0, 0.0);
+
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// The character for `value`, from the lowest at `min` to the highest
+/// at `max`.
+fn spark(value: f64, (min, max): (f64, f64)) -> char {
+    let fraction = ((value - min) / (max - min)).clamp(0.0, 1.0);
+    SPARKS[(fraction * (SPARKS.len() - 1) as f64).round() as usize]
+}
+
+/// The lowest value in each bucket; `None` values count as `floor`.
+fn bucket_minimums(
+    values: impl Iterator<Item = (f64, Option<f64>)>,
+    buckets: usize,
+    floor: f64,
+) -> Vec<Option<f64>> {
+    let mut minimums: Vec<Option<f64>> = vec![None; buckets];
+    for (time, value) in values {
+        let bucket = (time / LEVELS_BUCKET.as_secs_f64()) as usize;
+        if let Some(minimum) = minimums.get_mut(bucket) {
+            let value = value.unwrap_or(floor);
+            *minimum = Some(minimum.map_or(value, |m| m.min(value)));
+        }
+    }
+    minimums
+}
+
+/// Prints a timeline of the quietest audio and darkest video in each
+/// 5-second bucket of the first `window` of `path`: breaks show up as
+/// dips in both, and the thresholds show which dips detection catches.
+pub(crate) fn levels(
+    path: &Path,
+    window: Duration,
+    thresholds: &Thresholds,
+    audio: &AudioPreference,
+) -> Result<()> {
+    let measured = features::measure(path, window, audio)?;
+    let buckets = (window.as_secs_f64() / LEVELS_BUCKET.as_secs_f64()).ceil() as usize;
+    let loudness = bucket_minimums(
+        measured.audio.iter().map(|f| (f.time, f.rms_db)),
+        buckets,
+        LEVELS_DB_RANGE.0,
+    );
+    let luma = bucket_minimums(
+        measured.video.iter().map(|f| (f.time, f.luma)),
+        buckets,
+        0.0,
+    );
+    let render = |values: &[Option<f64>], range: (f64, f64)| -> String {
+        values
+            .iter()
+            .map(|value| value.map_or(' ', |value| spark(value, range)))
+            .collect()
+    };
+    println!(
+        "quietest audio ({} to {}dBFS) and darkest video per {}s; silences below {}dB show as {} or lower, black frames as {}",
+        LEVELS_DB_RANGE.0,
+        LEVELS_DB_RANGE.1,
+        LEVELS_BUCKET.as_secs(),
+        thresholds.silence_db,
+        spark(thresholds.silence_db, LEVELS_DB_RANGE),
+        spark(thresholds.black_pixel, (0.0, 1.0)),
+    );
+    for start in (0..buckets).step_by(LEVELS_PER_LINE) {
+        let end = (start + LEVELS_PER_LINE).min(buckets);
+        let at = LEVELS_BUCKET.as_secs() * start as u64;
+        println!(
+            "{:>3}:{:02} audio {}",
+            at / 60,
+            at % 60,
+            render(&loudness[start..end], LEVELS_DB_RANGE)
+        );
+        println!("       video {}", render(&luma[start..end], (0.0, 1.0)));
+    }
+    Ok(())
+}
@@ -0,0 +1,223 @@
+//! Progress display for a batch of files: a bar for each file that is
+//! being worked on, and a line counting the ones that are done.
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const FILE_TEMPLATE: &str =
+    "[{prefix}:{elapsed_precise}] {bar:30.cyan/blue} {pos:>7}ms/{len:7}ms [ETA:{eta}] {msg}";
+
+const TOTAL_TEMPLATE: &str = "[{elapsed_precise}] {bar:30.green/white} {pos}/{len} files {msg}";
+
+/// How fast the current pass over the files goes.
+struct Rates {
+    /// What the pass does to each file.
+    pass: String,
+    since: Instant,
+
+    /// How much of the files' media the pass got through.
+    media: Duration,
+    files: u64,
+
+    /// How many files the pass is over.
+    of: u64,
+}
+
+impl Rates {
+    fn new(pass: &str, of: u64) -> Self {
+        Rates {
+            pass: pass.to_string(),
+            since: Instant::now(),
+            media: Duration::from_secs(0),
+            files: 0,
+            of,
+        }
+    }
+
+    fn describe(&self) -> String {
+        let elapsed = self.since.elapsed();
+        let secs = elapsed.as_secs_f64().max(1.0);
+        let mut rates = vec![];
+        // files are only scanned partly (and not always from the start),
+        // so their sizes say nothing about how much of them was read;
+        // the media worked through is what the speed is measured in:
+        if self.media > Duration::from_secs(0) {
+            rates.push(format!("{:.0}x realtime", self.media.as_secs_f64() / secs));
+        }
+        rates.push(format!("{:.1} files/min", self.files as f64 * 60.0 / secs));
+        if self.files > 0 && self.files < self.of {
+            // the files so far took this long each, given how many are
+            // worked on at once:
+            let eta = elapsed / self.files as u32 * (self.of - self.files) as u32;
+            rates.push(format!(
+                "ETA {}",
+                humantime::format_duration(Duration::from_secs(eta.as_secs()))
+            ));
+        }
+        format!("{} ({})", self.pass, rates.join(", "))
+    }
+}
+
+pub(crate) struct Progress {
+    /// Counts the files that are done. Messages are printed through it,
+    /// so only the thread drawing the bars writes to the terminal.
+    total: ProgressBar,
+
+    /// Bars that no file is using right now.
+    idle: Mutex<Vec<ProgressBar>>,
+
+    rates: Mutex<Rates>,
+
+    drawing: Option<thread::JoinHandle<()>>,
+}
+
+impl Progress {
+    /// Sets up the display for `files` files, at most `slots` of which
+    /// get a bar at a time; the others only count towards the total.
+    /// Without a terminal, nothing is drawn.
+    pub(crate) fn new(files: usize, slots: usize, visible: bool, pass: &str) -> Self {
+        if !visible {
+            return Progress {
+                total: ProgressBar::hidden(),
+                idle: Mutex::new(vec![]),
+                rates: Mutex::new(Rates::new(pass, files as u64)),
+                drawing: None,
+            };
+        }
+        let multi = MultiProgress::new();
+        // bars only show up once they're first updated, i.e. when a
+        // file starts on them:
+        let idle = (0..slots)
+            .map(|_| {
+                let bar = multi.add(ProgressBar::new(0));
+                bar.set_style(ProgressStyle::default_bar().template(FILE_TEMPLATE));
+                bar
+            })
+            .collect();
+        let total = multi.add(ProgressBar::new(files as u64));
+        total.set_style(ProgressStyle::default_bar().template(TOTAL_TEMPLATE));
+        total.set_message(pass);
+        let drawing = thread::spawn(move || {
+            let _ = multi.join_and_clear();
+        });
+        Progress {
+            total,
+            idle: Mutex::new(idle),
+            rates: Mutex::new(Rates::new(pass, files as u64)),
+            drawing: Some(drawing),
+        }
+    }
+
+    /// Takes a bar for working on `path`. It's given back, and the file
+    /// counted as done, once the returned handle is dropped.
+    pub(crate) fn start(&self, path: &Path) -> FileBar<'_> {
+        let bar = self.idle.lock().unwrap_or_else(|e| e.into_inner()).pop();
+        // with all bars taken, the file goes without:
+        let bar = bar.unwrap_or_else(ProgressBar::hidden);
+        bar.reset();
+        bar.set_message("");
+        if let Some(name) = path.file_name().and_then(|s| s.to_str()) {
+            let name: String = name.chars().take(50).collect();
+            bar.set_prefix(&name);
+        }
+        FileBar {
+            progress: self,
+            bar,
+            media: MediaCount::default(),
+        }
+    }
+
+    /// Where to print messages that aren't about one bar's file, and
+    /// those that come after its bar is given back.
+    pub(crate) fn sink(&self) -> &ProgressBar {
+        &self.total
+    }
+
+    /// Counts a file, of which `media` was worked through, as done.
+    pub(crate) fn done(&self, media: Duration) {
+        let mut rates = self.rates.lock().unwrap_or_else(|e| e.into_inner());
+        rates.media += media;
+        rates.files += 1;
+        self.total.set_message(&rates.describe());
+        self.total.inc(1);
+    }
+
+    /// Starts counting again, for the next `pass` over the same files.
+    pub(crate) fn restart(&self, pass: &str) {
+        let mut rates = self.rates.lock().unwrap_or_else(|e| e.into_inner());
+        *rates = Rates::new(pass, rates.of);
+        self.total.set_position(0);
+        self.total.set_message(pass);
+    }
+
+    /// Takes the bars off the terminal, once everything is done.
+    pub(crate) fn finish(self) {
+        for bar in self.idle.into_inner().unwrap_or_else(|e| e.into_inner()) {
+            bar.finish_and_clear();
+        }
+        self.total.finish_and_clear();
+        if let Some(drawing) = self.drawing {
+            let _ = drawing.join();
+        }
+    }
+}
+
+/// Adds up how much of a file's media its scans worked through.
+#[derive(Default)]
+pub(crate) struct MediaCount(AtomicU64);
+
+impl MediaCount {
+    pub(crate) fn add(&self, media: Duration) {
+        self.0
+            .fetch_add(media.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    fn total(&self) -> Duration {
+        Duration::from_millis(self.0.load(Ordering::Relaxed))
+    }
+}
+
+/// A file's bar, given back when dropped.
+pub(crate) struct FileBar<'a> {
+    progress: &'a Progress,
+    bar: ProgressBar,
+
+    /// What the file's scans worked through; the bar only says how far
+    /// into the file the last one got.
+    media: MediaCount,
+}
+
+impl FileBar<'_> {
+    pub(crate) fn media(&self) -> &MediaCount {
+        &self.media
+    }
+}
+
+impl Deref for FileBar<'_> {
+    type Target = ProgressBar;
+
+    fn deref(&self) -> &ProgressBar {
+        &self.bar
+    }
+}
+
+impl Drop for FileBar<'_> {
+    fn drop(&mut self) {
+        let media = self.media.total();
+        self.bar.set_prefix("");
+        self.bar.set_message("idle");
+        self.bar.set_position(0);
+        self.progress.done(media);
+        if !self.bar.is_hidden() {
+            self.progress
+                .idle
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .push(self.bar.clone());
+        }
+    }
+}
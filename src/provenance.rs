@@ -0,0 +1,96 @@
+//! File-level tags recording which version and which run of this tool
+//! last wrote a file's chapters, with what settings, and whether it
+//! found an intro.
+use crate::util;
+use anyhow::{Context, Result};
+use std::path::Path;
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The version of this tool.
+pub(crate) const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+pub(crate) const VERSION_TAG: &str = "ICA_VERSION";
+const RUN_ID_TAG: &str = "ICA_RUN_ID";
+const PARAMS_TAG: &str = "ICA_PARAMS";
+
+/// "found" or "none", depending on whether the file has an intro.
+const INTRO_TAG: &str = "ICA_INTRO";
+
+/// Identifies this run: when it started, and as which process.
+fn run_id() -> &'static str {
+    static RUN_ID: OnceLock<String> = OnceLock::new();
+    RUN_ID.get_or_init(|| {
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        format!("{}-{}", started, std::process::id())
+    })
+}
+
+/// Returns the tags to put on a file we write chapters to; `params`
+/// describes the settings that produced them, if they were detected,
+/// and is stored hashed. `found_intro` says whether the file has an
+/// intro, if that's known.
+pub(crate) fn tags(params: Option<&str>, found_intro: Option<bool>) -> Vec<(String, String)> {
+    let mut tags = vec![
+        (VERSION_TAG.to_string(), VERSION.to_string()),
+        (RUN_ID_TAG.to_string(), run_id().to_string()),
+    ];
+    if let Some(found) = found_intro {
+        let status = if found { "found" } else { "none" };
+        tags.push((INTRO_TAG.to_string(), status.to_string()));
+    }
+    if let Some(params) = params {
+        let hash = util::fnv1a64(params.bytes());
+        tags.push((PARAMS_TAG.to_string(), format!("{:016x}", hash)));
+    }
+    tags
+}
+
+/// Returns the version of this tool that last wrote `path`'s chapters,
+/// if any did.
+pub(crate) fn written_version(path: &Path) -> Result<Option<String>> {
+    let ictx = ffmpeg::format::input(&path).with_context(|| format!("opening {:?}", path))?;
+    let version = ictx.metadata().get(VERSION_TAG).map(str::to_string);
+    Ok(version)
+}
+
+/// Whether an earlier run found no intro in `path`.
+pub(crate) fn without_intro(path: &Path) -> Result<bool> {
+    let ictx = ffmpeg::format::input(&path).with_context(|| format!("opening {:?}", path))?;
+    Ok(ictx.metadata().get(INTRO_TAG) == Some("none"))
+}
+
+/// Whether the file `ictx` was opened from already says about its
+/// intro what `tags` do (or `tags` say nothing about it).
+pub(crate) fn same_intro_status(
+    ictx: &ffmpeg::format::context::Input,
+    tags: &[(String, String)],
+) -> bool {
+    let metadata = ictx.metadata();
+    tags.iter()
+        .filter(|(name, _)| name == INTRO_TAG)
+        .all(|(_, status)| metadata.get(INTRO_TAG) == Some(status.as_str()))
+}
+
+/// Splits a version into its numeric components, ignoring any
+/// pre-release suffix like `-dev`.
+fn components(version: &str) -> Vec<u64> {
+    let release = version.trim().split(['-', '+']).next();
+    release
+        .unwrap_or_default()
+        .split('.')
+        .map(|part| part.parse().unwrap_or(0))
+        .collect()
+}
+
+/// Whether `version` comes before `than`.
+pub(crate) fn older_than(version: &str, than: &str) -> bool {
+    let (mut version, mut than) = (components(version), components(than));
+    let len = version.len().max(than.len());
+    version.resize(len, 0);
+    than.resize(len, 0);
+    version < than
+}
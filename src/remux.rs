@@ -0,0 +1,221 @@
+//! Rewrite a file into a new container by copying its streams
+use crate::chapters::{self, Chapter};
+use crate::util;
+use anyhow::{bail, Context, Result};
+use ffmpeg::{codec, encoder, format, media, Rational};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Extensions of containers that can't carry chapters, but whose
+/// streams we can copy into a Matroska file.
+pub(crate) const REMUX_SOURCE_EXTENSIONS: &[&str] = &["ts", "m2ts", "mts", "avi", "mpg", "mpeg"];
+
+/// The time base in which chapter boundaries are handed to the muxer.
+const CHAPTER_TIME_BASE: Rational = Rational(1, 1000);
+
+/// Copies the audio, video and subtitle streams of `input` into
+/// `output` (whose container is picked from its extension) without
+/// re-encoding, replacing the chapter list with `chapters` and adding
+/// `file_tags` to its metadata.
+pub(crate) fn remux(
+    input: &Path,
+    output: &Path,
+    chapters: &[Chapter],
+    file_tags: &[(String, String)],
+) -> Result<()> {
+    let mut ictx = format::input(&input).with_context(|| format!("opening {:?}", input))?;
+    let mut octx = format::output(&output).with_context(|| format!("creating {:?}", output))?;
+    let duration = util::container_duration(&ictx);
+
+    let mut stream_mapping: Vec<Option<usize>> = vec![None; ictx.streams().len()];
+    let mut next_index = 0;
+    for ist in ictx.streams() {
+        match ist.parameters().medium() {
+            media::Type::Audio | media::Type::Video | media::Type::Subtitle => {}
+            _ => continue,
+        }
+        stream_mapping[ist.index()] = Some(next_index);
+        next_index += 1;
+        let mut ost = octx.add_stream(encoder::find(codec::Id::None))?;
+        ost.set_parameters(ist.parameters());
+        // let the muxer pick a codec tag that's valid in the new container:
+        unsafe {
+            (*ost.parameters().as_mut_ptr()).codec_tag = 0;
+        }
+    }
+    let mut metadata = ictx.metadata().to_owned();
+    for (name, value) in file_tags {
+        metadata.set(name, value);
+    }
+    octx.set_metadata(metadata);
+    for (n, chapter) in chapters.iter().enumerate() {
+        let end = chapters
+            .get(n + 1)
+            .map(|next| next.start)
+            .or(duration)
+            .unwrap_or(chapter.start);
+        octx.add_chapter(
+            chapter.id as i32,
+            CHAPTER_TIME_BASE,
+            chapter.start.as_millis() as i64,
+            end.as_millis() as i64,
+            &chapter.name,
+        )?;
+    }
+
+    // the MP4 muxer leaves out metadata keys it doesn't know (like our
+    // file tags) unless told to keep them:
+    let mut options = ffmpeg::Dictionary::new();
+    let ext = output
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    if ext.is_some_and(|ext| chapters::MP4_EXTENSIONS.contains(&ext.as_str())) {
+        options.set("movflags", "use_metadata_tags");
+    }
+    octx.write_header_with(options)
+        .with_context(|| format!("writing header of {:?}", output))?;
+    // the muxer may have changed the time bases when writing the header:
+    let ost_time_bases: Vec<Rational> = octx.streams().map(|s| s.time_base()).collect();
+    for (stream, mut packet) in ictx.packets() {
+        let ost_index = match stream_mapping.get(stream.index()).copied().flatten() {
+            Some(index) => index,
+            None => continue,
+        };
+        packet.rescale_ts(stream.time_base(), ost_time_bases[ost_index]);
+        packet.set_position(-1);
+        packet.set_stream(ost_index);
+        packet.write_interleaved(&mut octx)?;
+    }
+    octx.write_trailer()?;
+    Ok(())
+}
+
+/// Replaces `path` with a remuxed copy of itself that carries
+/// `chapters` and `file_tags`.
+pub(crate) fn rewrite_in_place(
+    path: &Path,
+    chapters: &[Chapter],
+    file_tags: &[(String, String)],
+    temp_dir: Option<&Path>,
+) -> Result<()> {
+    remux_via_tmp(path, path, chapters, file_tags, temp_dir)
+}
+
+/// Remuxes `input` into the new file `output`, which must not exist
+/// yet.
+pub(crate) fn remux_into_new(
+    input: &Path,
+    output: &Path,
+    chapters: &[Chapter],
+    file_tags: &[(String, String)],
+    temp_dir: Option<&Path>,
+) -> Result<()> {
+    if output.exists() {
+        bail!("not remuxing {:?}: {:?} already exists", input, output);
+    }
+    remux_via_tmp(input, output, chapters, file_tags, temp_dir)
+}
+
+/// Fails unless the filesystem holding `dir` has room for `needed`
+/// bytes. If we can't tell how much room it has, we find out while
+/// writing.
+fn check_free_space(dir: &Path, needed: u64) -> Result<()> {
+    match util::free_space(dir) {
+        Some(free) if free < needed => bail!(
+            "{:?} has {} MiB free, but remuxing needs about {} MiB",
+            dir,
+            free / (1 << 20),
+            needed / (1 << 20)
+        ),
+        _ => Ok(()),
+    }
+}
+
+/// Remuxes into a temporary file (next to `output`, or in `temp_dir`)
+/// first and only then moves it into place, so a failed remux never
+/// leaves a truncated `output` (or, when rewriting in place, a damaged
+/// original) behind.
+fn remux_via_tmp(
+    input: &Path,
+    output: &Path,
+    chapters: &[Chapter],
+    file_tags: &[(String, String)],
+    temp_dir: Option<&Path>,
+) -> Result<()> {
+    let sibling = sibling_tmp_path(output);
+    let tmp = match temp_dir {
+        Some(dir) => tmp_path_in(dir, output),
+        None => sibling.clone(),
+    };
+    // the copy comes out about as large as the original, plus a bit:
+    let size = fs::metadata(input)
+        .with_context(|| format!("looking at {:?}", input))?
+        .len();
+    let needed = size + size / 20;
+    let output_dir = sibling.parent().unwrap_or_else(|| Path::new("."));
+    check_free_space(output_dir, needed)?;
+    if let Some(dir) = temp_dir {
+        check_free_space(dir, needed)?;
+    }
+    if let Err(e) = remux(input, &tmp, chapters, file_tags) {
+        let _ = fs::remove_file(&tmp);
+        return Err(e);
+    }
+    if tmp != sibling && fs::rename(&tmp, output).is_ok() {
+        return Ok(());
+    }
+    if tmp != sibling {
+        // on another filesystem; copy next to the output so the final
+        // move can't be interrupted halfway:
+        let copied = fs::copy(&tmp, &sibling);
+        let _ = fs::remove_file(&tmp);
+        if let Err(e) = copied {
+            let _ = fs::remove_file(&sibling);
+            return Err(e).with_context(|| format!("copying {:?} to {:?}", tmp, sibling));
+        }
+    }
+    fs::rename(&sibling, output).with_context(|| format!("moving {:?} to {:?}", sibling, output))
+}
+
+/// A hidden path in `dir` for remuxing into `output`, named after it
+/// and the directory it's in (see `util::output_name`), so same-named
+/// files of different directories remuxed at the same time don't share
+/// one. It keeps the extension, so the muxer picks the same container.
+fn tmp_path_in(dir: &Path, output: &Path) -> PathBuf {
+    let mut name = std::ffi::OsString::from(util::output_name(output));
+    if let Some(ext) = output.extension() {
+        name.push(".");
+        name.push(ext);
+    }
+    sibling_tmp_path(&dir.join(name))
+}
+
+/// A hidden path in the same directory as `path` that keeps its
+/// extension, so the muxer picks the same container.
+fn sibling_tmp_path(path: &Path) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let name = match path.extension() {
+        Some(ext) => format!(".{}.ica-tmp.{}", stem, ext.to_string_lossy()),
+        None => format!(".{}.ica-tmp", stem),
+    };
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_named_files_get_their_own_tmp_path() {
+        let dir = Path::new("/tmp/remuxing");
+        let a = tmp_path_in(dir, Path::new("/tv/A/S01E01.mp4"));
+        let b = tmp_path_in(dir, Path::new("/tv/B/S01E01.mp4"));
+        assert_ne!(a, b);
+        assert_eq!(a.parent(), Some(dir));
+        assert_eq!(a.extension().unwrap(), "mp4");
+    }
+}
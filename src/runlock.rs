@@ -0,0 +1,142 @@
+//! Advisory locks on the directories we write to, so that two runs
+//! (say, one from cron and one started by hand) never write chapters
+//! to the same files at the same time.
+use crate::{service, util};
+use anyhow::{bail, Context, Result};
+use std::collections::BTreeSet;
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+
+/// The lock file in each directory, there only while a run holds it.
+const LOCK_FILE_NAME: &str = ".ica-lock";
+
+/// Held locks; they're released, and their lock files removed, when
+/// this is dropped.
+pub(crate) struct DirLocks {
+    files: Vec<(PathBuf, File)>,
+}
+
+impl Drop for DirLocks {
+    fn drop(&mut self) {
+        // removed while still locked, so whoever waits for the lock
+        // next finds it gone (see `lock_dirs`):
+        for (path, file) in self.files.drain(..) {
+            let _ = fs::remove_file(&path);
+            drop(file);
+        }
+    }
+}
+
+/// Takes the lock, returning whether it was free.
+#[cfg(unix)]
+fn flock(file: &File, wait: bool) -> std::io::Result<bool> {
+    use std::os::unix::io::AsRawFd;
+    let operation = if wait {
+        libc::LOCK_EX
+    } else {
+        libc::LOCK_EX | libc::LOCK_NB
+    };
+    if unsafe { libc::flock(file.as_raw_fd(), operation) } == 0 {
+        return Ok(true);
+    }
+    let error = std::io::Error::last_os_error();
+    if error.raw_os_error() == Some(libc::EWOULDBLOCK) {
+        Ok(false)
+    } else {
+        Err(error)
+    }
+}
+
+#[cfg(not(unix))]
+fn flock(_file: &File, _wait: bool) -> std::io::Result<bool> {
+    Ok(true)
+}
+
+/// Whether `file` is (still) the one at `path`.
+#[cfg(unix)]
+fn is_at(file: &File, path: &Path) -> std::io::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+    let open = file.metadata()?;
+    Ok(match fs::metadata(path) {
+        Ok(there) => (open.dev(), open.ino()) == (there.dev(), there.ino()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => false,
+        Err(e) => return Err(e),
+    })
+}
+
+#[cfg(not(unix))]
+fn is_at(_file: &File, _path: &Path) -> std::io::Result<bool> {
+    Ok(true)
+}
+
+/// Locks the directories holding `paths`, in order so that runs
+/// locking overlapping directories can't deadlock. If another run holds
+/// one of them, waits for it when `wait` is set and fails otherwise.
+/// Directories we can't create a lock file in are written to unlocked.
+pub(crate) fn lock_dirs<'a>(
+    paths: impl IntoIterator<Item = &'a Path>,
+    wait: bool,
+) -> Result<DirLocks> {
+    let dirs: BTreeSet<_> = paths
+        .into_iter()
+        .map(|path| util::canonical(path.parent().unwrap_or_else(|| Path::new("."))))
+        .collect();
+    let mut locks = DirLocks { files: vec![] };
+    'dirs: for dir in dirs {
+        let lock_path = dir.join(LOCK_FILE_NAME);
+        let locking = || format!("locking {:?}", &lock_path);
+        loop {
+            let file = match OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(&lock_path)
+            {
+                Ok(file) => file,
+                Err(e) => {
+                    service::warn(format!("not locking {:?}: {}", &dir, e));
+                    continue 'dirs;
+                }
+            };
+            if !flock(&file, false).with_context(locking)? {
+                if !wait {
+                    bail!(
+                        "another run is writing chapters in {:?} (use --wait to wait for it)",
+                        &dir
+                    );
+                }
+                service::info(format!(
+                    "waiting for another run writing chapters in {:?}",
+                    &dir
+                ));
+                flock(&file, true).with_context(locking)?;
+            }
+            // the run we waited for removed the file it held; the lock
+            // is on whatever file is there now:
+            if is_at(&file, &lock_path).with_context(locking)? {
+                locks.files.push((lock_path, file));
+                break;
+            }
+        }
+    }
+    Ok(locks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mktemp::Temp;
+
+    #[test]
+    fn removes_lock_files_when_done() {
+        let dir = Temp::new_dir().unwrap();
+        let episode = dir.as_path().join("episode.mkv");
+        let lock_file = dir.as_path().join(LOCK_FILE_NAME);
+        let locks = lock_dirs(std::iter::once(episode.as_path()), false).unwrap();
+        assert!(lock_file.exists());
+        assert!(lock_dirs(std::iter::once(episode.as_path()), false).is_err());
+        drop(locks);
+        assert!(!lock_file.exists());
+        assert!(lock_dirs(std::iter::once(episode.as_path()), false).is_ok());
+    }
+}
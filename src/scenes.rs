@@ -0,0 +1,86 @@
+//! Find rough chapter boundaries from how often the picture cuts, for
+//! content that has neither silences nor black frames.
+use crate::features::VideoFrame;
+use std::time::Duration;
+
+/// Frames whose scene change score is at least this are cuts.
+const CUT_SCORE: f64 = 0.4;
+
+/// How far on either side of a cut to count the cuts around it, in
+/// seconds.
+const DENSITY_WINDOW: f64 = 30.0;
+
+/// Boundaries are at least this far apart, in seconds.
+const MIN_SPACING: f64 = 15.0;
+
+/// At most this many boundaries are picked from a scan window.
+const MAX_BOUNDARIES: usize = 8;
+
+/// Returns the cuts where the cutting pace changes the most (like the
+/// quickly cut montage of an opening giving way to the episode), in
+/// time order.
+pub(crate) fn boundaries(video: &[VideoFrame]) -> Vec<Duration> {
+    let cuts: Vec<f64> = video
+        .iter()
+        .filter(|f| f.scene.map(|s| s >= CUT_SCORE).unwrap_or(false))
+        .map(|f| f.time)
+        .collect();
+    let count = |from: f64, to: f64| cuts.iter().filter(|t| **t > from && **t < to).count();
+    let mut scored: Vec<(usize, f64)> = cuts
+        .iter()
+        .map(|&at| {
+            let before = count(at - DENSITY_WINDOW, at);
+            let after = count(at, at + DENSITY_WINDOW);
+            (before.max(after) - before.min(after), at)
+        })
+        .collect();
+    scored.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+
+    let mut picked: Vec<f64> = vec![];
+    for (_, at) in scored {
+        if picked.len() >= MAX_BOUNDARIES {
+            break;
+        }
+        if picked.iter().all(|p| (p - at).abs() >= MIN_SPACING) {
+            picked.push(at);
+        }
+    }
+    picked.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    picked
+        .into_iter()
+        .filter_map(|at| Duration::try_from_secs_f64(at).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(time: f64, scene: Option<f64>) -> VideoFrame {
+        VideoFrame {
+            time,
+            luma: None,
+            scene,
+        }
+    }
+
+    #[test]
+    fn finds_where_the_cutting_slows_down() {
+        // a montage cutting every second, then scenes of ten seconds:
+        let mut video: Vec<VideoFrame> = (0..60).map(|t| frame(t as f64, Some(0.9))).collect();
+        video.extend((6..30).map(|t| frame(t as f64 * 10.0, Some(0.5))));
+        video.push(frame(300.5, Some(0.1)));
+        video.push(frame(301.0, None));
+        let found = boundaries(&video);
+        assert!(found.contains(&Duration::from_secs(60)), "{:?}", found);
+        assert!(found.len() <= MAX_BOUNDARIES);
+        assert!(found
+            .windows(2)
+            .all(|pair| (pair[1] - pair[0]).as_secs_f64() >= MIN_SPACING));
+    }
+
+    #[test]
+    fn finds_nothing_without_cuts() {
+        assert!(boundaries(&[frame(1.0, Some(0.1)), frame(2.0, None)]).is_empty());
+    }
+}
@@ -0,0 +1,140 @@
+//! Compare detection results across the episodes of a season
+use std::cmp::{max, min};
+use std::time::Duration;
+
+/// Intros starting this much earlier or later than the season's
+/// median are probably misdetected.
+const START_TOLERANCE: Duration = Duration::from_secs(60);
+
+/// Intros running this much longer or shorter than the season's
+/// median are probably misdetected.
+const LENGTH_TOLERANCE: Duration = Duration::from_secs(10);
+
+/// Seasons with fewer intros than this don't have a meaningful median.
+const MIN_INTROS: usize = 3;
+
+pub(crate) fn median(mut values: Vec<Duration>) -> Duration {
+    values.sort();
+    values[values.len() / 2]
+}
+
+fn distance(a: Duration, b: Duration) -> Duration {
+    max(a, b) - min(a, b)
+}
+
+/// Returns, for each of a season's episodes, whether its intro (start
+/// and end) deviates strongly from the season's median intro.
+/// Episodes without an intro are never outliers.
+pub(crate) fn outliers(intros: &[Option<(Duration, Duration)>]) -> Vec<bool> {
+    let found: Vec<(Duration, Duration)> = intros.iter().flatten().copied().collect();
+    if found.len() < MIN_INTROS {
+        return vec![false; intros.len()];
+    }
+    let start = median(found.iter().map(|(start, _)| *start).collect());
+    let length = median(found.iter().map(|(start, end)| *end - *start).collect());
+    intros
+        .iter()
+        .map(|intro| match intro {
+            Some((s, e)) => {
+                distance(*s, start) > START_TOLERANCE
+                    || distance(*e - *s, length) > LENGTH_TOLERANCE
+            }
+            None => false,
+        })
+        .collect()
+}
+
+/// What to do about an episode's intro to make its season consistent.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Consistency {
+    /// Fine as it is: at the median already, without an intro, or in a
+    /// season too small to tell.
+    Keep,
+
+    /// Move the intro here, the season's median start and length.
+    Snap(Duration, Duration),
+
+    /// Too far off the median to move it there.
+    Irreconcilable,
+}
+
+/// Returns, for each of a season's episodes, how to bring its intro in
+/// line with the season's median (if both its start and its length are
+/// within `tolerance` of it).
+pub(crate) fn reconcile(
+    intros: &[Option<(Duration, Duration)>],
+    tolerance: Duration,
+) -> Vec<Consistency> {
+    let found: Vec<(Duration, Duration)> = intros.iter().flatten().copied().collect();
+    if found.len() < MIN_INTROS {
+        return vec![Consistency::Keep; intros.len()];
+    }
+    let start = median(found.iter().map(|(start, _)| *start).collect());
+    let length = median(found.iter().map(|(start, end)| *end - *start).collect());
+    intros
+        .iter()
+        .map(|intro| match intro {
+            Some((s, e)) if (*s, *e - *s) == (start, length) => Consistency::Keep,
+            Some((s, e))
+                if distance(*s, start) <= tolerance && distance(*e - *s, length) <= tolerance =>
+            {
+                Consistency::Snap(start, start + length)
+            }
+            Some(_) => Consistency::Irreconcilable,
+            None => Consistency::Keep,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn intro(start: u64, end: u64) -> Option<(Duration, Duration)> {
+        Some((Duration::from_secs(start), Duration::from_secs(end)))
+    }
+
+    #[test]
+    fn flags_intros_far_off_the_median() {
+        let intros = [
+            intro(60, 90),
+            intro(62, 92),
+            None,
+            intro(200, 230),
+            intro(61, 120),
+            intro(59, 89),
+        ];
+        assert_eq!(
+            outliers(&intros),
+            vec![false, false, false, true, true, false]
+        );
+        // too few to go by:
+        assert_eq!(outliers(&[intro(60, 90), intro(600, 630)]), vec![false; 2]);
+    }
+
+    #[test]
+    fn snaps_close_intros_to_the_median() {
+        let secs = Duration::from_secs;
+        let intros = [
+            intro(60, 90),
+            intro(62, 92),
+            intro(61, 91),
+            None,
+            intro(90, 120),
+        ];
+        assert_eq!(
+            reconcile(&intros, secs(5)),
+            vec![
+                Consistency::Snap(secs(62), secs(92)),
+                Consistency::Keep,
+                Consistency::Snap(secs(62), secs(92)),
+                Consistency::Keep,
+                Consistency::Irreconcilable,
+            ]
+        );
+        assert_eq!(
+            reconcile(&[intro(60, 90), intro(62, 92)], secs(5)),
+            vec![Consistency::Keep; 2]
+        );
+    }
+}
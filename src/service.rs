@@ -0,0 +1,73 @@
+//! Fitting in when running as a service (e.g. under systemd): no
+//! progress bars without a terminal, log levels journald understands,
+//! and readiness notifications.
+use std::fmt::Display;
+
+/// Whether stderr is a terminal that progress bars can be drawn on.
+pub(crate) fn interactive() -> bool {
+    #[cfg(unix)]
+    return unsafe { libc::isatty(2) } == 1;
+    #[cfg(not(unix))]
+    return true;
+}
+
+/// Whether our output goes to the journal, which reads a `<N>` prefix
+/// on a line as its syslog priority.
+fn journald() -> bool {
+    std::env::var_os("JOURNAL_STREAM").is_some()
+}
+
+fn print_at(priority: u8, message: impl Display) {
+    if journald() {
+        eprintln!("<{}>{}", priority, message);
+    } else {
+        eprintln!("{}", message);
+    }
+}
+
+/// Prints an informational message to stderr.
+pub(crate) fn info(message: impl Display) {
+    print_at(6, message);
+}
+
+/// Prints a warning to stderr.
+pub(crate) fn warn(message: impl Display) {
+    print_at(4, message);
+}
+
+/// Prints an error to stderr.
+pub(crate) fn error(message: impl Display) {
+    print_at(3, message);
+}
+
+/// Tells the service manager about a state change (e.g. `READY=1`),
+/// if it asked to be told (sd_notify).
+#[cfg(unix)]
+pub(crate) fn notify(state: &str) {
+    use std::os::unix::net::UnixDatagram;
+    let path = match std::env::var_os("NOTIFY_SOCKET") {
+        Some(path) => path,
+        None => return,
+    };
+    let socket = match UnixDatagram::unbound() {
+        Ok(socket) => socket,
+        Err(_) => return,
+    };
+    // the service manager carries on without notifications it can't
+    // get, so failing to send one isn't an error:
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::linux::net::SocketAddrExt;
+        use std::os::unix::ffi::OsStrExt;
+        if let Some(name) = path.as_bytes().strip_prefix(b"@") {
+            if let Ok(addr) = std::os::unix::net::SocketAddr::from_abstract_name(name) {
+                let _ = socket.send_to_addr(state.as_bytes(), &addr);
+            }
+            return;
+        }
+    }
+    let _ = socket.send_to(state.as_bytes(), path);
+}
+
+#[cfg(not(unix))]
+pub(crate) fn notify(_state: &str) {}
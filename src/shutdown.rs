@@ -0,0 +1,59 @@
+//! Stop cleanly on Ctrl-C: files already being worked on are finished,
+//! but no new ones are started.
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// The error for work that was never started because we were asked to
+/// stop.
+#[derive(Debug)]
+pub(crate) struct Interrupted;
+
+impl fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "interrupted before starting")
+    }
+}
+
+impl std::error::Error for Interrupted {}
+
+#[cfg(unix)]
+extern "C" fn on_signal(_: libc::c_int) {
+    const MESSAGE: &[u8] =
+        b"\ninterrupted: finishing the files in progress (again to quit right away)\n";
+    INTERRUPTED.store(true, Ordering::SeqCst);
+    // only async-signal-safe calls in here:
+    unsafe {
+        libc::write(2, MESSAGE.as_ptr() as *const libc::c_void, MESSAGE.len());
+        libc::signal(libc::SIGINT, libc::SIG_DFL);
+        libc::signal(libc::SIGTERM, libc::SIG_DFL);
+    }
+}
+
+/// Catches the first SIGINT and SIGTERM; the second one ends the
+/// process as usual.
+#[cfg(unix)]
+pub(crate) fn install() {
+    let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    unsafe {
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn install() {}
+
+pub(crate) fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Fails with `Interrupted` once we've been asked to stop.
+pub(crate) fn check() -> Result<(), Interrupted> {
+    if interrupted() {
+        Err(Interrupted)
+    } else {
+        Ok(())
+    }
+}
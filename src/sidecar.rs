@@ -0,0 +1,113 @@
+//! Per-file detection settings, read from a `.ica.toml` file next to
+//! the video.
+use crate::exclude::Exclusions;
+use crate::intro::{ForcedIntro, ScanLength};
+use crate::learned;
+use anyhow::{bail, Context, Result};
+use serde::de::{self, Deserializer};
+use serde::Deserialize;
+use serde_derive::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Settings that take precedence over the command line for one file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Overrides {
+    #[serde(default, deserialize_with = "optional_scan_length")]
+    pub(crate) until: Option<ScanLength>,
+
+    #[serde(default, deserialize_with = "optional_duration")]
+    pub(crate) threshold: Option<Duration>,
+
+    pub(crate) only: Option<usize>,
+
+    /// Audio quieter than this many dBFS is silent.
+    pub(crate) silence_noise: Option<f64>,
+
+    /// Pixels darker than this fraction of the luma range are black.
+    pub(crate) black_pixel: Option<f64>,
+
+    pub(crate) name: Option<String>,
+
+    /// Skip detection and put intro chapters here instead.
+    pub(crate) intro: Option<ForcedIntro>,
+
+    /// Ignore breaks in these ranges, on top of the command line's.
+    pub(crate) exclude: Option<Exclusions>,
+
+    /// The confirmed intros of a show's episodes; only in profiles.
+    #[serde(default)]
+    pub(crate) learned: Vec<learned::Intro>,
+}
+
+pub(crate) fn duration<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    humantime::parse_duration(&s).map_err(de::Error::custom)
+}
+
+fn optional_duration<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Duration>, D::Error> {
+    duration(deserializer).map(Some)
+}
+
+fn optional_scan_length<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<ScanLength>, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    s.parse().map(Some).map_err(de::Error::custom)
+}
+
+/// Returns the path of the sidecar for `path`: `episode.mkv.ica.toml`
+/// for `episode.mkv`.
+fn sidecar_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(".ica.toml");
+    path.with_file_name(name)
+}
+
+impl Overrides {
+    /// Reads the overrides for `path`; if it has no sidecar, there are
+    /// none.
+    pub(crate) fn load(path: &Path) -> Result<Self> {
+        let sidecar = sidecar_path(path);
+        let text = match fs::read_to_string(&sidecar) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e).context(format!("reading {:?}", &sidecar)),
+        };
+        let overrides: Self = toml::from_str(&text).context(format!("parsing {:?}", &sidecar))?;
+        if !overrides.learned.is_empty() {
+            bail!(
+                "{:?}: learned intros only go in --config profiles",
+                &sidecar
+            );
+        }
+        overrides
+            .validate()
+            .with_context(|| format!("{:?}", &sidecar))?;
+        Ok(overrides)
+    }
+
+    /// Checks what the types alone can't.
+    pub(crate) fn validate(&self) -> Result<()> {
+        if let Some(intro) = &self.intro {
+            if intro.end <= intro.start {
+                bail!("intro end is not after its start");
+            }
+        }
+        if let Some(silence_noise) = self.silence_noise {
+            if !silence_noise.is_finite() || silence_noise > 0.0 {
+                bail!("silence_noise must be at or below 0 (dBFS)");
+            }
+        }
+        if let Some(black_pixel) = self.black_pixel {
+            if !(0.0..=1.0).contains(&black_pixel) {
+                bail!("black_pixel must be between 0 and 1");
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,212 @@
+//! Run detection over the beginning of one file again and again,
+//! varying one parameter, to see which setting finds the breaks a
+//! difficult show has.
+use crate::detect::Candidate;
+use crate::intro;
+use crate::sidecar::Overrides;
+use anyhow::{bail, Context, Result};
+use indicatif::ProgressBar;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Sweeps with more settings than this are probably a typo.
+const MAX_STEPS: usize = 50;
+
+/// The parameters a sweep can vary.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Param {
+    /// The silence threshold, in dBFS.
+    SilenceNoise,
+
+    /// The black pixel threshold (blackdetect's `pix_th`).
+    BlackPixel,
+
+    /// How long a pause must last to count, in seconds (--threshold).
+    MinPause,
+}
+
+impl FromStr for Param {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "silence-noise" => Ok(Param::SilenceNoise),
+            "black-pixel" => Ok(Param::BlackPixel),
+            "min-pause" => Ok(Param::MinPause),
+            _ => bail!(
+                "unknown parameter {:?} (known: silence-noise, black-pixel, min-pause)",
+                s
+            ),
+        }
+    }
+}
+
+fn pause_length(secs: f64) -> Result<Duration> {
+    Duration::try_from_secs_f64(secs).context("invalid pause length")
+}
+
+impl Param {
+    fn default_step(self) -> f64 {
+        match self {
+            Param::SilenceNoise => 5.0,
+            Param::BlackPixel => 0.02,
+            Param::MinPause => 0.25,
+        }
+    }
+
+    fn describe(self, value: f64) -> String {
+        match self {
+            Param::SilenceNoise => format!("{}dB", value),
+            Param::BlackPixel => format!("{:.3}", value),
+            Param::MinPause => format!("{}s", value),
+        }
+    }
+}
+
+/// Which parameter to vary, and across which values.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Sweep {
+    param: Param,
+    values: Vec<f64>,
+}
+
+impl FromStr for Sweep {
+    type Err = anyhow::Error;
+
+    /// Parses `NAME=FROM..TO`, optionally followed by `step STEP`,
+    /// e.g. `silence-noise=-60..-35 step 5`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        let range = words.next().context("expected NAME=FROM..TO")?;
+        let (name, range) = range.split_once('=').context("expected NAME=FROM..TO")?;
+        let param: Param = name.parse()?;
+        let (from, to) = range.split_once("..").context("expected FROM..TO")?;
+        let from: f64 = from.parse().context("parsing FROM")?;
+        let to: f64 = to.parse().context("parsing TO")?;
+        let step = match (words.next(), words.next()) {
+            (None, _) => param.default_step(),
+            (Some("step"), Some(step)) => step.parse().context("parsing STEP")?,
+            _ => bail!("expected \"step STEP\" after the range"),
+        };
+        if words.next().is_some() {
+            bail!("unexpected words after the step");
+        }
+        if !(from.is_finite() && to.is_finite() && step.is_finite()) || step <= 0.0 {
+            bail!("the range and step must be numbers, and the step positive");
+        }
+        if from > to {
+            bail!("FROM is larger than TO");
+        }
+        // a little slack, so the end of the range isn't lost to rounding:
+        let steps = ((to - from) / step + 1e-9).floor() as usize + 1;
+        if steps > MAX_STEPS {
+            bail!("{} settings are too many (at most {})", steps, MAX_STEPS);
+        }
+        let values = (0..steps).map(|i| from + step * i as f64).collect();
+        Ok(Sweep { param, values })
+    }
+}
+
+/// Scans with each setting of the swept parameter (the others as
+/// detection would set them) by calling `scan`, which returns the
+/// breaks detection would consider with those `Overrides`, and prints
+/// a line for each with the breaks it found, and where an intro of
+/// `intro_length` would be.
+pub(crate) fn sweep(
+    sweep: &Sweep,
+    intro_length: &RangeInclusive<Duration>,
+    mut scan: impl FnMut(&Overrides) -> Result<Vec<Candidate>>,
+) -> Result<()> {
+    let bar = ProgressBar::new(sweep.values.len() as u64);
+    // varying the pause length alone takes just one scan, which keeps
+    // the shortest pauses:
+    let once = match sweep.param {
+        Param::MinPause => {
+            let shortest = sweep.values.iter().copied().fold(f64::INFINITY, f64::min);
+            let overrides = Overrides {
+                threshold: Some(pause_length(shortest)?),
+                ..Default::default()
+            };
+            Some(scan(&overrides)?)
+        }
+        _ => None,
+    };
+    let mut rows = vec![];
+    for value in &sweep.values {
+        let mut overrides = Overrides::default();
+        let mut min_pause = None;
+        match sweep.param {
+            Param::SilenceNoise => overrides.silence_noise = Some(*value),
+            Param::BlackPixel => overrides.black_pixel = Some(*value),
+            Param::MinPause => min_pause = Some(pause_length(*value)?),
+        }
+        let described = sweep.param.describe(*value);
+        let candidates = match &once {
+            Some(candidates) => candidates.clone(),
+            None => scan(&overrides).context(described.clone())?,
+        };
+        let starts: Vec<Duration> = candidates
+            .iter()
+            .filter(|cand| min_pause.is_none_or(|min_pause| cand.length > min_pause))
+            .map(|cand| cand.offset)
+            .collect();
+        let intro = intro::find_intro(&starts, intro_length);
+        rows.push((described, starts, intro));
+        bar.inc(1);
+    }
+    bar.finish_and_clear();
+    println!("{:<14} {:>6} {:<17} breaks", "setting", "breaks", "intro");
+    for (described, starts, intro) in rows {
+        let intro = match intro {
+            Some((start, end)) => format!("{:.1}s-{:.1}s", start.as_secs_f64(), end.as_secs_f64()),
+            None => "-".to_string(),
+        };
+        let breaks: Vec<String> = starts
+            .iter()
+            .map(|start| format!("{:.1}", start.as_secs_f64()))
+            .collect();
+        println!(
+            "{:<14} {:>6} {:<17} {}",
+            described,
+            starts.len(),
+            intro,
+            breaks.join(" ")
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sweeps() {
+        let sweep: Sweep = "silence-noise=-60..-35 step 5".parse().unwrap();
+        assert_eq!(sweep.param, Param::SilenceNoise);
+        assert_eq!(sweep.values, vec![-60.0, -55.0, -50.0, -45.0, -40.0, -35.0]);
+        let sweep: Sweep = "min-pause=0.25..1".parse().unwrap();
+        assert_eq!(sweep.values, vec![0.25, 0.5, 0.75, 1.0]);
+        let sweep: Sweep = "black-pixel=0.1..0.1".parse().unwrap();
+        assert_eq!(sweep.values, vec![0.1]);
+    }
+
+    #[test]
+    fn rejects_bad_sweeps() {
+        for s in &[
+            "",
+            "loudness=1..2",
+            "min-pause=1",
+            "min-pause=2..1",
+            "min-pause=1..2 step 0",
+            "min-pause=1..2 step -1",
+            "min-pause=1..2 by 1",
+            "min-pause=1..2 step 1 more",
+            "silence-noise=-90..0 step 1",
+            "min-pause=NaN..1",
+        ] {
+            assert!(s.parse::<Sweep>().is_err(), "{:?}", s);
+        }
+    }
+}
@@ -0,0 +1,55 @@
+//! The rows of the CSV file that add-chapter-markers reads. Like
+//! `offset`, this doesn't use ffmpeg, so the fuzz targets in `fuzz/`
+//! can build it on its own.
+use crate::offset::Offset;
+use anyhow::{bail, Context};
+use serde_derive::Deserialize;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// A row of the CSV file; offsets are in seconds, as `HH:MM:SS.mmm`
+/// (or `MM:SS.mmm`), humantime durations like `1m32s`, or relative to
+/// one of the file's chapters, like `chapter:2+00:05`.
+#[derive(Debug, PartialEq, Deserialize)]
+pub(crate) struct TitleInfo {
+    pub(crate) location: PathBuf,
+    theme_start: Offset,
+    theme_end: Offset,
+}
+
+impl TitleInfo {
+    /// Returns the theme start and end offsets, if they describe a
+    /// sensible span inside a file of the given duration whose chapters
+    /// start at `chapter_starts`.
+    pub(crate) fn theme_range(
+        &self,
+        chapter_starts: &[Duration],
+        file_duration: Option<Duration>,
+    ) -> anyhow::Result<(Duration, Duration)> {
+        let theme_start = self
+            .theme_start
+            .resolve(chapter_starts)
+            .with_context(|| format!("invalid theme_start {}", self.theme_start))?;
+        let theme_end = self
+            .theme_end
+            .resolve(chapter_starts)
+            .with_context(|| format!("invalid theme_end {}", self.theme_end))?;
+        if theme_end <= theme_start {
+            bail!(
+                "theme_end {} is not after theme_start {}",
+                self.theme_end,
+                self.theme_start
+            );
+        }
+        if let Some(duration) = file_duration {
+            if theme_end > duration {
+                bail!(
+                    "theme_end {} exceeds the file duration of {}",
+                    self.theme_end,
+                    humantime::format_duration(duration)
+                );
+            }
+        }
+        Ok((theme_start, theme_end))
+    }
+}
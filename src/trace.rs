@@ -0,0 +1,52 @@
+//! Record every pause the detectors report and how it changed the
+//! combined detection state, as JSON lines, for debugging the fusion
+//! of audio and video pauses offline.
+use anyhow::{Context, Result};
+use serde_derive::Serialize;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+/// One pause, as it was fed into the detection state.
+#[derive(Debug, Serialize)]
+pub(crate) struct Event<'a> {
+    pub(crate) file: &'a Path,
+
+    /// `audio` or `video`.
+    pub(crate) stream: &'a str,
+
+    /// `start` or `end`.
+    pub(crate) pause: &'a str,
+
+    /// Offset of the pause, in seconds.
+    pub(crate) at: f64,
+
+    pub(crate) state_before: String,
+    pub(crate) state_after: String,
+
+    /// Offset and length of the candidate this completed, in seconds.
+    pub(crate) candidate: Option<(f64, f64)>,
+}
+
+/// A trace file shared by all files being scanned.
+pub(crate) struct Trace {
+    out: Mutex<BufWriter<File>>,
+}
+
+impl Trace {
+    pub(crate) fn create(path: &Path) -> Result<Self> {
+        let file = File::create(path).context(format!("creating {:?}", path))?;
+        Ok(Trace {
+            out: Mutex::new(BufWriter::new(file)),
+        })
+    }
+
+    pub(crate) fn record(&self, event: &Event) {
+        let mut out = self.out.lock().unwrap_or_else(|e| e.into_inner());
+        // a broken trace shouldn't fail the scan:
+        if serde_json::to_writer(&mut *out, event).is_ok() {
+            let _ = writeln!(out);
+        }
+    }
+}
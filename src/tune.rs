@@ -0,0 +1,55 @@
+//! Derive detection thresholds from how loud and how bright a file is
+//! to begin with.
+use crate::detect::{AudioPreference, Thresholds};
+use crate::features;
+use anyhow::Result;
+use std::path::Path;
+use std::time::Duration;
+
+/// How much of the start of a file to measure.
+const CALIBRATION_WINDOW: Duration = Duration::from_secs(120);
+
+/// Which fraction of audio frames we expect to be at or below the
+/// noise floor.
+const NOISE_FLOOR_PERCENTILE: f64 = 0.1;
+
+/// How far above the noise floor audio still counts as silence.
+const SILENCE_MARGIN_DB: f64 = 6.0;
+
+/// The silence threshold stays within these levels, however noisy or
+/// quiet the file is.
+const SILENCE_DB_RANGE: (f64, f64) = (-70.0, -30.0);
+
+/// Pixels darker than this fraction of the average luma count as black.
+const BLACK_LUMA_FRACTION: f64 = 0.3;
+
+/// The black pixel threshold stays within these fractions of the luma
+/// range.
+const BLACK_PIXEL_RANGE: (f64, f64) = (0.03, 0.15);
+
+fn clamp(value: f64, (min, max): (f64, f64)) -> f64 {
+    value.max(min).min(max)
+}
+
+/// Measures the audio noise floor and average luma over the start of
+/// `path` (in the `audio` track detection listens to) and derives
+/// thresholds from them. Whatever can't be measured keeps its default.
+pub(crate) fn auto_thresholds(path: &Path, audio: &AudioPreference) -> Result<Thresholds> {
+    let features = features::measure(path, CALIBRATION_WINDOW, audio)?;
+    // digitally silent frames have no level and say nothing about the
+    // noise floor:
+    let mut levels: Vec<f64> = features.audio.iter().filter_map(|f| f.rms_db).collect();
+    let lumas: Vec<f64> = features.video.iter().filter_map(|f| f.luma).collect();
+
+    let mut thresholds = Thresholds::default();
+    if !levels.is_empty() {
+        levels.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let floor = levels[(levels.len() as f64 * NOISE_FLOOR_PERCENTILE) as usize];
+        thresholds.silence_db = clamp(floor + SILENCE_MARGIN_DB, SILENCE_DB_RANGE);
+    }
+    if !lumas.is_empty() {
+        let average = lumas.iter().sum::<f64>() / lumas.len() as f64;
+        thresholds.black_pixel = clamp(average * BLACK_LUMA_FRACTION, BLACK_PIXEL_RANGE);
+    }
+    Ok(thresholds)
+}
@@ -1,6 +1,553 @@
-use ffmpeg::Rational;
-use std::time::Duration;
+use crate::service;
+use anyhow::{bail, Context};
+use ffmpeg::{format, Rational};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Condvar, Mutex};
+use std::{thread, time::Duration};
 
+/// The time base in which container-level durations are expressed
+/// (`AV_TIME_BASE`, microseconds).
+const CONTAINER_TIME_BASE: Rational = Rational(1, 1_000_000);
+
+/// Converts a timestamp in `time_base` units into a duration, clamping
+/// anything that can't be represented (negative or non-finite values)
+/// to zero.
 pub(crate) fn to_duration(time_ref: i64, time_base: Rational) -> Duration {
-    Duration::from_secs_f64((time_ref as f64 / time_base.1 as f64) * time_base.0 as f64)
+    checked_duration(time_ref, time_base).unwrap_or_default()
+}
+
+/// Converts a timestamp in `time_base` units into a duration, if it
+/// represents a non-negative, finite offset.
+pub(crate) fn checked_duration(time_ref: i64, time_base: Rational) -> Option<Duration> {
+    if time_base.1 == 0 {
+        return None;
+    }
+    Duration::try_from_secs_f64((time_ref as f64 / time_base.1 as f64) * time_base.0 as f64).ok()
+}
+
+/// Returns the overall duration of the input file, if the container
+/// knows it.
+pub(crate) fn container_duration(ictx: &format::context::Input) -> Option<Duration> {
+    checked_duration(ictx.duration(), CONTAINER_TIME_BASE).filter(|d| *d > Duration::from_secs(0))
+}
+
+/// Returns the overall duration of the file at `path`, if its container
+/// knows it.
+pub(crate) fn file_duration(path: &Path) -> anyhow::Result<Option<Duration>> {
+    let ictx = format::input(&path).with_context(|| format!("opening {:?}", path))?;
+    Ok(container_duration(&ictx))
+}
+
+/// How long the priming samples an audio encoder put in front of the
+/// sound of `stream` last, in seconds. Players drop them, so the sound
+/// starts that much after the stream does.
+fn codec_delay(stream: &ffmpeg::Stream) -> f64 {
+    let parameters = stream.parameters();
+    if parameters.medium() != ffmpeg::media::Type::Audio {
+        return 0.0;
+    }
+    let (padding, rate) = unsafe {
+        let parameters = &*parameters.as_ptr();
+        (parameters.initial_padding, parameters.sample_rate)
+    };
+    if padding > 0 && rate > 0 {
+        f64::from(padding) / f64::from(rate)
+    } else {
+        0.0
+    }
+}
+
+/// Returns where the earliest stream starts to play, in seconds: its
+/// start time, and for audio its codec delay. Players show this as 0,
+/// but MP4 edit lists and audio priming delays move it away from 0 (in
+/// either direction). Every offset we report goes through
+/// `displayed_offset` with it.
+pub(crate) fn stream_origin(ictx: &format::context::Input) -> f64 {
+    let origin = ictx
+        .streams()
+        .filter(|s| s.start_time() != ffmpeg::ffi::AV_NOPTS_VALUE && s.time_base().1 != 0)
+        .map(|s| {
+            s.start_time() as f64 * s.time_base().0 as f64 / s.time_base().1 as f64
+                + codec_delay(&s)
+        })
+        .fold(f64::INFINITY, f64::min);
+    if origin.is_finite() {
+        origin
+    } else {
+        0.0
+    }
+}
+
+/// Converts an offset in the file's own timestamps into where players
+/// show it, given the file's `stream_origin`.
+pub(crate) fn displayed_offset(offset: Duration, origin: f64) -> Duration {
+    Duration::try_from_secs_f64(offset.as_secs_f64() - origin).unwrap_or_default()
+}
+
+/// Rounds `offset` up to the next whole millisecond, so a frame start
+/// written with millisecond precision stays within that frame.
+pub(crate) fn ceil_to_millis(offset: Duration) -> Duration {
+    let millis = offset.as_nanos().div_ceil(1_000_000);
+    Duration::from_millis(millis as u64)
+}
+
+/// Canonicalizes a path for comparison purposes, falling back to the
+/// path as given if that's not possible.
+pub(crate) fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// A name for what we write about the media file `path` (logs, feature
+/// dumps) into a directory shared by all files: its file name, and a
+/// hash of the directory it's in, so that same-named episodes of
+/// different shows don't overwrite each other's.
+pub(crate) fn output_name(path: &Path) -> String {
+    let path = canonical(path);
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    // 32-bit FNV-1a, to keep the names short:
+    let hash = dir
+        .to_string_lossy()
+        .bytes()
+        .fold(0x811c_9dc5_u32, |hash, byte| {
+            (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193)
+        });
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    format!("{}.{:08x}", name, hash)
+}
+
+/// 64-bit FNV-1a of `bytes`. Unlike std's hasher, this stays the same
+/// across releases, so it's fine to store.
+pub(crate) fn fnv1a64(bytes: impl IntoIterator<Item = u8>) -> u64 {
+    bytes
+        .into_iter()
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        })
+}
+
+/// How much of each end of a file goes into its fingerprint.
+const FINGERPRINT_CHUNK: u64 = 64 * 1024;
+
+/// A quick fingerprint of the file at `path`, from its size and its
+/// first and last 64 KiB: enough to notice that a file was replaced
+/// by a different encode, without reading all of it.
+pub(crate) fn fingerprint(path: &Path) -> anyhow::Result<String> {
+    use std::io::{Read, Seek, SeekFrom};
+    let mut f = fs::File::open(path).with_context(|| format!("opening {:?}", path))?;
+    let size = f.metadata()?.len();
+    let mut head = vec![];
+    (&mut f).take(FINGERPRINT_CHUNK).read_to_end(&mut head)?;
+    let mut tail = vec![];
+    f.seek(SeekFrom::Start(size.saturating_sub(FINGERPRINT_CHUNK)))?;
+    f.take(FINGERPRINT_CHUNK).read_to_end(&mut tail)?;
+    let hash = fnv1a64(head.into_iter().chain(tail));
+    Ok(format!("{}-{:016x}", size, hash))
+}
+
+/// Fails unless `path` (once canonicalized) is inside one of `roots`;
+/// with no roots, anything goes. `path` itself doesn't need to exist
+/// yet, but its directory does.
+pub(crate) fn check_within(roots: &[PathBuf], path: &Path) -> anyhow::Result<()> {
+    if roots.is_empty() {
+        return Ok(());
+    }
+    let resolved = match path.canonicalize() {
+        Ok(resolved) => resolved,
+        Err(_) => {
+            let dir = path
+                .parent()
+                .filter(|dir| !dir.as_os_str().is_empty())
+                .unwrap_or_else(|| Path::new("."));
+            let name = path.file_name().context("not a file")?;
+            dir.canonicalize()
+                .with_context(|| format!("resolving {:?}", path))?
+                .join(name)
+        }
+    };
+    for root in roots {
+        let root = root
+            .canonicalize()
+            .with_context(|| format!("resolving {:?}", root))?;
+        if resolved.starts_with(&root) {
+            return Ok(());
+        }
+    }
+    bail!(
+        "{:?} is outside the directories given to --restrict-to",
+        path
+    )
+}
+
+/// Drops paths that refer to the same file as an earlier entry,
+/// warning about each one.
+pub(crate) fn dedup_paths(paths: Vec<PathBuf>) -> Vec<PathBuf> {
+    let mut seen = HashSet::new();
+    paths
+        .into_iter()
+        .filter(|path| {
+            if seen.insert(canonical(path)) {
+                true
+            } else {
+                service::warn(format!("skipping duplicate input {:?}", path));
+                false
+            }
+        })
+        .collect()
+}
+
+/// How many bytes can still be written to the filesystem holding
+/// `dir`, if we can tell.
+#[cfg(unix)]
+pub(crate) fn free_space(dir: &Path) -> Option<u64> {
+    use std::os::unix::ffi::OsStrExt;
+    let path = std::ffi::CString::new(dir.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    // the field types differ between platforms:
+    #[allow(clippy::unnecessary_cast)]
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+pub(crate) fn free_space(_dir: &Path) -> Option<u64> {
+    None
+}
+
+/// Which files of a batch to work on first.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Order {
+    /// Most recently modified first, so fresh downloads get their
+    /// chapters before the backlog.
+    NewestFirst,
+    SmallestFirst,
+    Path,
+}
+
+impl FromStr for Order {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "newest-first" => Ok(Order::NewestFirst),
+            "smallest-first" => Ok(Order::SmallestFirst),
+            "path" => Ok(Order::Path),
+            _ => bail!(
+                "unknown order {:?} (known: newest-first, smallest-first, path)",
+                s
+            ),
+        }
+    }
+}
+
+impl Order {
+    /// Sorts `paths` into this order. Files that can't be looked at go
+    /// last.
+    pub(crate) fn sort(self, paths: &mut [PathBuf]) {
+        match self {
+            Order::NewestFirst => paths.sort_by_cached_key(|path| {
+                std::cmp::Reverse(fs::metadata(path).and_then(|m| m.modified()).ok())
+            }),
+            Order::SmallestFirst => paths
+                .sort_by_cached_key(|path| fs::metadata(path).map(|m| m.len()).unwrap_or(u64::MAX)),
+            Order::Path => paths.sort(),
+        }
+    }
+}
+
+/// Keeps `n` of the `paths`, picked at random (but in the order they
+/// were given), and returns the seed that picked them.
+pub(crate) fn sample(paths: &mut Vec<PathBuf>, n: usize, seed: Option<u64>) -> u64 {
+    let seed = seed.unwrap_or_else(|| {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default();
+        now.as_nanos() as u64 ^ u64::from(std::process::id())
+    });
+    // splitmix64, which is plenty for picking files:
+    let mut state = seed;
+    let mut next = || {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    };
+    let mut indices: Vec<usize> = (0..paths.len()).collect();
+    let n = n.min(indices.len());
+    // the first n steps of a Fisher-Yates shuffle:
+    for i in 0..n {
+        let j = i + (next() % (indices.len() - i) as u64) as usize;
+        indices.swap(i, j);
+    }
+    let mut picked: Vec<usize> = indices[..n].to_vec();
+    picked.sort_unstable();
+    let mut picked = picked.into_iter().peekable();
+    let mut index = 0;
+    paths.retain(|_| {
+        let keep = picked.next_if_eq(&index).is_some();
+        index += 1;
+        keep
+    });
+    seed
+}
+
+/// Hands out one lock per (canonicalized) file, so that concurrent
+/// workers never write to the same file at the same time.
+#[derive(Default)]
+pub(crate) struct FileLocks {
+    locks: Mutex<HashMap<PathBuf, Arc<Mutex<()>>>>,
+}
+
+impl FileLocks {
+    /// Runs `f` while holding the lock for `path`.
+    pub(crate) fn with_lock<T>(&self, path: &Path, f: impl FnOnce() -> T) -> T {
+        let lock = self
+            .locks
+            .lock()
+            .unwrap()
+            .entry(canonical(path))
+            .or_default()
+            .clone();
+        let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+        f()
+    }
+}
+
+/// How often to re-check a file that is still changing before giving up.
+const STABILITY_ATTEMPTS: u32 = 5;
+
+/// Waits until the file's size and modification time have stayed the
+/// same for `settle`, backing off between attempts. Files that are
+/// still being copied into place fail with an error.
+pub(crate) fn wait_until_stable(path: &Path, settle: Duration) -> anyhow::Result<()> {
+    let snapshot = |path: &Path| -> anyhow::Result<_> {
+        let meta = fs::metadata(path).with_context(|| format!("checking {:?}", path))?;
+        Ok((meta.len(), meta.modified().ok()))
+    };
+    let mut wait = settle;
+    let mut before = snapshot(path)?;
+    for _ in 0..STABILITY_ATTEMPTS {
+        thread::sleep(wait);
+        let after = snapshot(path)?;
+        if after == before {
+            return Ok(());
+        }
+        before = after;
+        wait *= 2;
+    }
+    bail!(
+        "{:?} is still changing after {} checks; is it still being copied?",
+        path,
+        STABILITY_ATTEMPTS
+    );
+}
+
+/// Replaces every directory in `paths` with the files directly inside
+/// it whose extension is one of `extensions` (compared
+/// case-insensitively), in name order. Other paths are kept as they are.
+pub(crate) fn expand_dirs(
+    paths: Vec<PathBuf>,
+    extensions: &[&str],
+) -> anyhow::Result<Vec<PathBuf>> {
+    let mut expanded = vec![];
+    for path in paths {
+        if !path.is_dir() {
+            expanded.push(path);
+            continue;
+        }
+        let mut entries: Vec<PathBuf> = fs::read_dir(&path)
+            .with_context(|| format!("listing {:?}", path))?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|p| {
+                p.is_file()
+                    && p.extension()
+                        .and_then(|e| e.to_str())
+                        .map(|e| extensions.iter().any(|x| x.eq_ignore_ascii_case(e)))
+                        .unwrap_or(false)
+            })
+            .collect();
+        entries.sort();
+        expanded.extend(entries);
+    }
+    Ok(expanded)
+}
+
+/// A shift applied to offsets, in either direction.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Shift {
+    earlier: bool,
+    amount: Duration,
+}
+
+impl Shift {
+    /// Shifts `offset`, stopping at the start of the file. Returns
+    /// `None` for an offset that ends up at or past the end of a file of
+    /// `duration`.
+    pub(crate) fn apply(&self, offset: Duration, duration: Option<Duration>) -> Option<Duration> {
+        let shifted = if self.earlier {
+            offset.saturating_sub(self.amount)
+        } else {
+            offset + self.amount
+        };
+        Some(shifted).filter(|shifted| duration.is_none_or(|duration| *shifted < duration))
+    }
+}
+
+impl std::str::FromStr for Shift {
+    type Err = anyhow::Error;
+
+    /// Parses a duration with an optional sign, like `-0.25s`, `+1s`
+    /// or `-250ms`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (earlier, rest) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        let amount = match humantime::parse_duration(rest) {
+            Ok(amount) => amount,
+            Err(_) => {
+                let secs: f64 = rest
+                    .trim_end_matches('s')
+                    .parse()
+                    .with_context(|| format!("invalid shift {:?}", s))?;
+                Duration::try_from_secs_f64(secs)?
+            }
+        };
+        Ok(Shift { earlier, amount })
+    }
+}
+
+/// Parses a byte size like `512M`, `4GiB` or `1073741824`. Suffixes
+/// are binary multiples, whether or not they're spelled with an `i`.
+pub(crate) fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid size {:?}", s))?;
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" | "kib" => 1 << 10,
+        "m" | "mb" | "mib" => 1 << 20,
+        "g" | "gb" | "gib" => 1 << 30,
+        "t" | "tb" | "tib" => 1 << 40,
+        other => return Err(format!("unknown size unit {:?}", other)),
+    };
+    Ok((number * multiplier as f64) as u64)
+}
+
+/// A pool of memory that concurrent workers reserve their expected
+/// usage from, blocking until enough has been released by others.
+pub(crate) struct MemoryBudget {
+    total: u64,
+    available: Mutex<u64>,
+    released: Condvar,
+}
+
+/// Memory reserved from a `MemoryBudget`, given back when dropped.
+pub(crate) struct Reservation<'a> {
+    budget: &'a MemoryBudget,
+    amount: u64,
+}
+
+impl MemoryBudget {
+    pub(crate) fn new(total: u64) -> Self {
+        MemoryBudget {
+            total,
+            available: Mutex::new(total),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Waits until `amount` bytes are available and reserves them.
+    /// Requests larger than the whole budget wait for all of it, so
+    /// they run on their own.
+    pub(crate) fn reserve(&self, amount: u64) -> Reservation<'_> {
+        let amount = amount.min(self.total);
+        let mut available = self.available.lock().unwrap();
+        while *available < amount {
+            available = self.released.wait(available).unwrap();
+        }
+        *available -= amount;
+        Reservation {
+            budget: self,
+            amount,
+        }
+    }
+}
+
+impl<'a> Drop for Reservation<'a> {
+    fn drop(&mut self) {
+        *self.budget.available.lock().unwrap() += self.amount;
+        self.budget.released.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_shifts() {
+        let shifted = |s: &str, secs| {
+            let shift = s.parse::<Shift>().unwrap();
+            shift.apply(Duration::from_secs(secs), Some(Duration::from_secs(20)))
+        };
+        let ms = |ms| Some(Duration::from_millis(ms));
+        assert_eq!(shifted("-250ms", 10), ms(9_750));
+        assert_eq!(shifted("+1s", 10), ms(11_000));
+        assert_eq!(shifted("0.5", 10), ms(10_500));
+        assert_eq!(shifted("-0.25s", 10), ms(9_750));
+        assert_eq!(shifted("-1m", 10), ms(0));
+        assert_eq!(shifted("+10s", 10), None);
+        assert!("-".parse::<Shift>().is_err());
+        assert!("soon".parse::<Shift>().is_err());
+    }
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("1073741824"), Ok(1 << 30));
+        assert_eq!(parse_size("512M"), Ok(512 << 20));
+        assert_eq!(parse_size("4GiB"), Ok(4 << 30));
+        assert_eq!(parse_size("1.5 kb"), Ok(1536));
+        assert!(parse_size("").is_err());
+        assert!(parse_size("12 parsecs").is_err());
+    }
+
+    #[test]
+    fn samples_in_order_and_repeatably() {
+        let paths: Vec<PathBuf> = (0..20)
+            .map(|i| PathBuf::from(format!("{:02}", i)))
+            .collect();
+        let mut picked = paths.clone();
+        let seed = sample(&mut picked, 5, Some(42));
+        assert_eq!(seed, 42);
+        assert_eq!(picked.len(), 5);
+        assert!(picked.windows(2).all(|pair| pair[0] < pair[1]));
+        let mut again = paths.clone();
+        sample(&mut again, 5, Some(seed));
+        assert_eq!(picked, again);
+
+        let mut all = paths.clone();
+        sample(&mut all, 50, None);
+        assert_eq!(all, paths);
+    }
+
+    #[test]
+    fn names_outputs_after_the_directory_too() {
+        let name = |path: &str| output_name(Path::new(path));
+        let first = name("/nonexistent/Show A/Season 1/S01E01.mkv");
+        assert!(first.starts_with("S01E01.mkv."));
+        assert_eq!(first, name("/nonexistent/Show A/Season 1/S01E01.mkv"));
+        assert_ne!(first, name("/nonexistent/Show B/Season 1/S01E01.mkv"));
+    }
 }
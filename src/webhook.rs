@@ -0,0 +1,175 @@
+//! A tiny HTTP endpoint for playback webhooks, so that a file has its
+//! intro marked by the time the intro starts on its first watch.
+//!
+//! Point Tautulli's (or Jellyfin's) webhook for playback starts at it,
+//! with a JSON body of `{"file": "{file}"}`.
+use crate::{service, shutdown};
+use anyhow::{Context, Result};
+use serde_derive::Deserialize;
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Sender, TryRecvError};
+use std::thread;
+use std::time::Duration;
+
+/// How often to check whether we're asked to stop while waiting.
+const SHUTDOWN_POLL: Duration = Duration::from_millis(500);
+
+/// Clients taking longer than this to send their request, or to take
+/// the response, are given up on.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Webhook bodies larger than this are refused.
+const MAX_BODY_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Deserialize)]
+struct Playback {
+    /// The played file's path, as the server sees it.
+    file: PathBuf,
+}
+
+/// Reads one request and returns the file it names.
+fn read_request(stream: &TcpStream) -> Result<PathBuf> {
+    let mut reader = BufReader::new(stream);
+    let mut length = 0;
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().context("parsing Content-Length")?;
+            }
+        }
+    }
+    anyhow::ensure!(length <= MAX_BODY_SIZE, "body too large");
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    let playback: Playback = serde_json::from_slice(&body).context("parsing webhook body")?;
+    Ok(playback.file)
+}
+
+fn respond(mut stream: &TcpStream, status: &str) {
+    // the sender finding out or not doesn't change what we do:
+    let _ = write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        status
+    );
+}
+
+/// Answers one connection, queueing the file it names.
+fn accept(stream: TcpStream, queue: Sender<PathBuf>) {
+    let timeouts = stream
+        .set_read_timeout(Some(REQUEST_TIMEOUT))
+        .and_then(|_| stream.set_write_timeout(Some(REQUEST_TIMEOUT)));
+    match timeouts
+        .map_err(anyhow::Error::from)
+        .and_then(|_| read_request(&stream))
+    {
+        Ok(file) => {
+            respond(&stream, "202 Accepted");
+            // only fails once we're stopping:
+            let _ = queue.send(file);
+        }
+        Err(e) => {
+            service::warn(format!("bad webhook request: {:#}", e));
+            respond(&stream, "400 Bad Request");
+        }
+    }
+}
+
+/// A file to detect the intro of.
+pub(crate) enum Job {
+    /// Started playing, by its path as the server sees it.
+    Played(PathBuf),
+
+    /// From the backlog, by its local path.
+    Queued(PathBuf),
+}
+
+/// Listens on `addr` and calls `handle` for each played file, one at a
+/// time and in the order they came in, and for the files of `backlog`
+/// while no played file is waiting. A backlog file that's being scanned
+/// is finished first, but played files go before the rest of it.
+/// Requests are answered right away (each on its own thread), so
+/// webhooks don't time out while a file is scanned.
+pub(crate) fn serve(
+    addr: &str,
+    mut backlog: VecDeque<PathBuf>,
+    mut handle: impl FnMut(Job),
+) -> Result<()> {
+    let listener = TcpListener::bind(addr).context(format!("listening on {}", addr))?;
+    service::info(format!("waiting for playback webhooks on {}", addr));
+    service::notify("READY=1");
+    let (queue, played) = mpsc::channel();
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let queue = queue.clone();
+            thread::spawn(move || accept(stream, queue));
+        }
+    });
+    while !shutdown::interrupted() {
+        let job = match played.try_recv() {
+            Ok(file) => Job::Played(file),
+            Err(TryRecvError::Disconnected) => break,
+            Err(TryRecvError::Empty) => match backlog.pop_front() {
+                Some(path) => Job::Queued(path),
+                None => match played.recv_timeout(SHUTDOWN_POLL) {
+                    Ok(file) => Job::Played(file),
+                    Err(mpsc::RecvTimeoutError::Timeout) => continue,
+                    Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                },
+            },
+        };
+        handle(job);
+    }
+    service::notify("STOPPING=1");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    /// Sends `request` to a listener, returning what `accept` queued
+    /// and what it responded.
+    fn exchange(request: &str) -> (Option<PathBuf>, String) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.write_all(request.as_bytes()).unwrap();
+        let (queue, played) = mpsc::channel();
+        accept(listener.accept().unwrap().0, queue);
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        (played.try_recv().ok(), response)
+    }
+
+    #[test]
+    fn queues_played_files() {
+        let body = r#"{"file": "/media/tv/Show S01E01.mkv"}"#;
+        let (file, response) = exchange(&format!(
+            "POST / HTTP/1.1\r\ncontent-length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        ));
+        assert_eq!(
+            file.as_deref(),
+            Some(Path::new("/media/tv/Show S01E01.mkv"))
+        );
+        assert!(response.starts_with("HTTP/1.1 202"));
+    }
+
+    #[test]
+    fn refuses_bad_requests() {
+        let (file, response) = exchange("POST / HTTP/1.1\r\nContent-Length: 2\r\n\r\n{}");
+        assert_eq!(file, None);
+        assert!(response.starts_with("HTTP/1.1 400"));
+    }
+}
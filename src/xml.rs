@@ -0,0 +1,215 @@
+//! Just enough of XML to pick apart the tags mkvextract exports: the
+//! elements, the text right inside each, and where each one is in the
+//! document. Declarations, comments and the doctype are skipped, and
+//! attributes are read past.
+use anyhow::{bail, Context, Result};
+use std::ops::Range;
+
+/// An element, with the elements and text in it.
+#[derive(Debug, Default)]
+pub(crate) struct Element {
+    pub(crate) name: String,
+
+    /// Where the element is in the document, its tags included.
+    pub(crate) span: Range<usize>,
+
+    pub(crate) children: Vec<Element>,
+
+    /// The text right inside the element (not inside its children),
+    /// with entities and CDATA sections resolved.
+    pub(crate) text: String,
+}
+
+impl Element {
+    pub(crate) fn children_named<'a>(
+        &'a self,
+        name: &'a str,
+    ) -> impl Iterator<Item = &'a Element> + 'a {
+        self.children.iter().filter(move |child| child.name == name)
+    }
+}
+
+/// Returns the text up to `end`, and the position after it.
+fn until<'a>(doc: &'a str, from: usize, end: &str) -> Result<(&'a str, usize)> {
+    let len = doc[from..]
+        .find(end)
+        .with_context(|| format!("no {:?} after position {}", end, from))?;
+    Ok((&doc[from..from + len], from + len + end.len()))
+}
+
+/// Returns the position after the `>` that closes the tag (or doctype)
+/// starting at `from`, skipping over quoted values and, in a doctype,
+/// its internal subset.
+fn tag_end(doc: &str, from: usize) -> Result<usize> {
+    let mut quote = None;
+    let mut depth = 0;
+    for (i, c) in doc[from..].char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (None, '[') => depth += 1,
+            (None, ']') => depth -= 1,
+            (None, '>') if depth <= 0 => return Ok(from + i + 1),
+            _ => {}
+        }
+    }
+    bail!("unclosed tag at position {}", from)
+}
+
+/// Resolves the entities in `text`.
+fn unescape(text: &str) -> Result<String> {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        let (entity, after) = until(rest, amp + 1, ";")?;
+        let c = match entity {
+            "lt" => '<',
+            "gt" => '>',
+            "amp" => '&',
+            "quot" => '"',
+            "apos" => '\'',
+            _ => {
+                let code = match entity.strip_prefix("#x") {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                    None => entity.strip_prefix('#').and_then(|dec| dec.parse().ok()),
+                };
+                code.and_then(char::from_u32)
+                    .with_context(|| format!("unknown entity &{};", entity))?
+            }
+        };
+        out.push(c);
+        rest = &rest[after..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Adds a complete `element` to the one it's in, or makes it the root.
+fn add(open: &mut [Element], root: &mut Option<Element>, element: Element) -> Result<()> {
+    match (open.last_mut(), root) {
+        (Some(parent), _) => parent.children.push(element),
+        (None, Some(_)) => bail!("more than one root element"),
+        (None, root) => *root = Some(element),
+    }
+    Ok(())
+}
+
+/// Parses `doc` and returns its root element.
+pub(crate) fn parse(doc: &str) -> Result<Element> {
+    let mut open: Vec<Element> = vec![];
+    let mut root = None;
+    let mut pos = 0;
+    loop {
+        let next = doc[pos..].find('<').map_or(doc.len(), |i| pos + i);
+        let text = &doc[pos..next];
+        match open.last_mut() {
+            Some(parent) => parent.text.push_str(&unescape(text)?),
+            None if !text.trim().is_empty() => bail!("text outside of the root element"),
+            None => {}
+        }
+        if next == doc.len() {
+            break;
+        }
+        let rest = &doc[next..];
+        pos = if rest.starts_with("<?") {
+            until(doc, next, "?>")?.1
+        } else if rest.starts_with("<!--") {
+            until(doc, next, "-->")?.1
+        } else if rest.starts_with("<![CDATA[") {
+            let (text, after) = until(doc, next + "<![CDATA[".len(), "]]>")?;
+            open.last_mut()
+                .context("text outside of the root element")?
+                .text
+                .push_str(text);
+            after
+        } else if rest.starts_with("<!") {
+            tag_end(doc, next)?
+        } else if rest.starts_with("</") {
+            let (name, after) = until(doc, next + 2, ">")?;
+            let mut element = open
+                .pop()
+                .with_context(|| format!("</{}> closes nothing", name.trim()))?;
+            if element.name != name.trim() {
+                bail!("<{}> is closed by </{}>", element.name, name.trim());
+            }
+            element.span.end = after;
+            add(&mut open, &mut root, element)?;
+            after
+        } else {
+            let after = tag_end(doc, next)?;
+            let inside = &doc[next + 1..after - 1];
+            let name = inside
+                .split(|c: char| c.is_whitespace() || c == '/')
+                .next()
+                .unwrap_or_default();
+            if name.is_empty() {
+                bail!("a tag without a name at position {}", next);
+            }
+            let element = Element {
+                name: name.to_string(),
+                span: next..after,
+                ..Default::default()
+            };
+            if inside.ends_with('/') {
+                add(&mut open, &mut root, element)?;
+            } else {
+                open.push(element);
+            }
+            after
+        };
+    }
+    if let Some(element) = open.last() {
+        bail!("<{}> is never closed", element.name);
+    }
+    root.context("no root element")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_elements_and_their_text() {
+        let doc = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE Tags SYSTEM "matroskatags.dtd" [ <!ENTITY x "y"> ]>
+<!-- exported -->
+<Tags>
+  <Tag id="a > b" >
+    <Simple><Name>ICA_VERSION</Name><String>Tom &amp; Jerry &#x41;<![CDATA[<b>]]></String></Simple>
+  </Tag>
+  <Tag/>
+</Tags>
+"#;
+        let root = parse(doc).unwrap();
+        assert_eq!(root.name, "Tags");
+        assert_eq!(root.children.len(), 2);
+        let tag = &root.children[0];
+        assert!(doc[tag.span.clone()].starts_with("<Tag id="));
+        assert!(doc[tag.span.clone()].ends_with("</Tag>"));
+        let simple = tag.children_named("Simple").next().unwrap();
+        let text = |name| &simple.children_named(name).next().unwrap().text;
+        assert_eq!(text("Name"), "ICA_VERSION");
+        assert_eq!(text("String"), "Tom & Jerry A<b>");
+        assert_eq!(&doc[root.children[1].span.clone()], "<Tag/>");
+    }
+
+    #[test]
+    fn rejects_broken_documents() {
+        for doc in &[
+            "",
+            "text",
+            "<Tags>",
+            "<Tags></Tag>",
+            "<Tags></Tags><Tags></Tags>",
+            "<Tags/><Tags/>",
+            "<Tags>&nbsp;</Tags>",
+            "<Tags><Tag></Tags>",
+            "<Tags><></Tags>",
+            "<Tags id=\"></Tags>",
+        ] {
+            assert!(parse(doc).is_err(), "{:?}", doc);
+        }
+    }
+}